@@ -0,0 +1,261 @@
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, Utc};
+use serde::Serialize;
+use tracing::{debug, error, info, warn, Instrument};
+
+use crate::{
+    cli::{BackfillArgs, BackfillType},
+    commands::{lines, print},
+    config::{Config, Route},
+    discord::{errors::RequestError, models::ChannelOptions},
+    filters, item_span,
+    selfoss::{
+        adapter::{get_items, get_sources, ItemQuery, ItemType},
+        models::SelfossItem,
+    },
+    sinks::{Sinks, DISCORD_SINK},
+    sources::SourceError,
+    stats::RunStats,
+};
+
+/// An item and the channel it is posted in.
+#[derive(Serialize, Debug)]
+pub struct BackfillPost {
+    pub channel: String,
+    pub item: SelfossItem,
+}
+
+fn start_of_day(date: NaiveDate) -> DateTime<Utc> {
+    date.and_time(NaiveTime::MIN).and_utc()
+}
+
+/// Fetches the items selected by `args`, oldest first.
+async fn fetch_items(
+    config: &Config,
+    args: &BackfillArgs,
+) -> Result<Vec<SelfossItem>, RequestError> {
+    let source = match &args.source {
+        Some(title) => match get_sources(config)
+            .await?
+            .into_iter()
+            .find(|s| &s.title == title)
+        {
+            Some(source) => Some(source.id),
+            None => {
+                warn!(source = title, "No source with this title");
+                return Ok(vec![]);
+            }
+        },
+        None => None,
+    };
+    let mut query = ItemQuery {
+        item_type: match args.item_type {
+            BackfillType::Starred => ItemType::Starred,
+            BackfillType::Read | BackfillType::All => ItemType::Newest,
+        },
+        tag: args.tag.clone(),
+        source,
+        ..Default::default()
+    };
+    let since = args.since.map(start_of_day);
+    let until = args
+        .until
+        .map(|date| start_of_day(date) + Duration::days(1));
+
+    let mut items = vec![];
+    loop {
+        let page = get_items(config, &query).await?;
+        // Items are returned newest first, so later pages are older than `since` as well.
+        let done = page.len() < query.items
+            || page
+                .last()
+                .is_some_and(|item| since.is_some_and(|since| item.datetime < since));
+        items.extend(page.into_iter().filter(|item| {
+            (args.item_type != BackfillType::Read || !item.unread)
+                && since.is_none_or(|since| item.datetime >= since)
+                && until.is_none_or(|until| item.datetime < until)
+        }));
+        if done {
+            break;
+        }
+        query.offset += query.items;
+    }
+    items.reverse();
+    Ok(items)
+}
+
+/// Posts already read or starred items again, without marking anything as read.
+pub async fn backfill(
+    config: &Config,
+    args: &BackfillArgs,
+    json: bool,
+) -> Result<(), RequestError> {
+    if let Some(source) = &config.source {
+        let message = "Backfill is only supported for Selfoss";
+        return Err(RequestError::Source(SourceError::new(
+            source.name(),
+            message,
+        )));
+    }
+    let items = fetch_items(config, args).await?;
+    let mut stats = RunStats {
+        fetched: items.len(),
+        ..Default::default()
+    };
+
+    let mut posts = vec![];
+    for item in items {
+        if let Some(rule) = filters::find_blocking_rule(config.filters_for(&item), &item) {
+            debug!(id = item.id, rule, "Skipping filtered item");
+            stats.filtered += 1;
+            continue;
+        }
+        let channel = match &args.channel {
+            Some(channel) => channel.trim_start_matches('#').to_string(),
+            None => config.channel_name(&item.sourcetitle),
+        };
+        posts.push(BackfillPost { channel, item });
+    }
+
+    if args.dry_run {
+        print(json, &posts, |posts| {
+            lines(posts, "No items to backfill", |post| {
+                format!(
+                    "{}\t#{}\t{}: {}",
+                    post.item.datetime.to_rfc3339(),
+                    post.channel,
+                    post.item.sourcetitle,
+                    post.item.title
+                )
+            })
+        });
+        return Ok(());
+    }
+
+    // Old items do not ping anyone again.
+    let mut sinks = Sinks::new(config, None).without_mentions();
+    for post in &posts {
+        let options = match args.channel {
+            Some(_) => ChannelOptions::default(),
+            None => config.channel_options_for(&post.item),
+        };
+        let sink = config
+            .route_for(&post.item)
+            .and_then(Route::other_sink)
+            .unwrap_or(DISCORD_SINK);
+        let result = async {
+            if !sinks.get(config, sink)?.has_content(&post.item) {
+                return Ok(false);
+            }
+            sinks
+                .post_item(config, sink, &post.channel, &options, &post.item)
+                .await
+                .map(|_| true)
+        }
+        .instrument(item_span(&post.item))
+        .await;
+        match result {
+            Ok(true) => stats.posted += 1,
+            Ok(false) => {}
+            Err(e) if e.is_fatal() => return Err(e),
+            Err(e) => {
+                error!(
+                    id = post.item.id,
+                    error = config.redact(&e.to_string()),
+                    "Could not backfill item"
+                );
+                stats.failed += 1;
+            }
+        }
+    }
+
+    info!(
+        fetched = stats.fetched,
+        posted = stats.posted,
+        filtered = stats.filtered,
+        failed = stats.failed,
+        "Backfill done"
+    );
+    print(json, &stats, |stats| {
+        format!(
+            "Posted {} of {} items, {} filtered, {} failed",
+            stats.posted, stats.fetched, stats.filtered, stats.failed
+        )
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::NaiveDate;
+    use httpmock::Method::{GET, POST};
+
+    use crate::{
+        backfill::backfill,
+        cli::{BackfillArgs, BackfillType},
+        test::start_server,
+    };
+
+    #[tokio::test]
+    async fn test_backfill_read_items() {
+        let (server, config) = start_server();
+
+        let get_items_mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/items")
+                .query_param("type", "newest")
+                .query_param("tag", "news");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/selfoss_mock_response.json");
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/guilds/123/channels");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/discord_items_mock_response.json");
+        });
+        let create_channel_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/guilds/123/channels")
+                .json_body_partial(r#"{"name": "backfill"}"#);
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/discord_create_channel_mock_response.json");
+        });
+        let send_message_mock = server.mock(|when, then| {
+            when.method(POST).path("/channels/my_channel_id/messages");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/discord_send_message_mock_response.json");
+        });
+        let mark_item_read_mock = server.mock(|when, then| {
+            when.method(POST).path_contains("/mark/");
+            then.status(200);
+        });
+
+        let mut args = BackfillArgs {
+            item_type: BackfillType::Read,
+            source: None,
+            tag: Some(String::from("news")),
+            since: NaiveDate::from_ymd_opt(2023, 12, 1),
+            until: NaiveDate::from_ymd_opt(2023, 12, 15),
+            channel: Some(String::from("#backfill")),
+            dry_run: false,
+        };
+        backfill(&config, &args, false)
+            .await
+            .expect("Error backfilling items");
+
+        // The item is from December 15th, before this range.
+        args.since = NaiveDate::from_ymd_opt(2023, 12, 16);
+        args.until = None;
+        backfill(&config, &args, false)
+            .await
+            .expect("Error backfilling items");
+
+        get_items_mock.assert_hits_async(2).await;
+        create_channel_mock.assert_async().await;
+        send_message_mock.assert_async().await;
+        mark_item_read_mock.assert_hits_async(0).await;
+    }
+}
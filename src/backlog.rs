@@ -0,0 +1,170 @@
+//! Keeps the first run of the bridge from posting the whole backlog of unread items.
+
+use std::io::{self, IsTerminal, Write};
+
+use tracing::info;
+
+use crate::{
+    config::{BacklogAction, Config, FirstRunConfig},
+    errors::AppError,
+    selfoss::models::SelfossItem,
+    state::State,
+    stats::RunStats,
+};
+
+/// Asks on the terminal whether the backlog should be posted, `None` without a terminal or a
+/// clear answer.
+fn ask(count: usize) -> Option<BacklogAction> {
+    if !io::stdin().is_terminal() {
+        return None;
+    }
+    eprint!(
+        "{} unread items are waiting. Post them (y), or mark them as read without posting (n)? ",
+        count
+    );
+    io::stderr().flush().ok()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).ok()?;
+    match answer.trim().to_lowercase().as_str() {
+        "y" | "yes" => Some(BacklogAction::Post),
+        "n" | "no" => Some(BacklogAction::MarkRead),
+        _ => None,
+    }
+}
+
+/// What to do with a backlog of `count` items, asking with `ask` if the settings leave it open.
+fn backlog_action(
+    first_run: &FirstRunConfig,
+    count: usize,
+    ask: impl FnOnce(usize) -> Option<BacklogAction>,
+) -> Result<BacklogAction, AppError> {
+    match first_run.backlog {
+        BacklogAction::Ask if count <= first_run.threshold => Ok(BacklogAction::Post),
+        BacklogAction::Ask => ask(count).ok_or(AppError::Backlog(count)),
+        action => Ok(action),
+    }
+}
+
+/// Decides what happens to the unread items on the first run, and returns the ones to post. As
+/// `items` are only the newest ones, the whole backlog is fetched to decide, and marked as read if
+/// it is not posted. Its items are then not returned.
+pub async fn handle_first_run(
+    config: &Config,
+    state: &State,
+    stats: &mut RunStats,
+    items: Vec<SelfossItem>,
+) -> Result<Vec<SelfossItem>, AppError> {
+    if !state.is_new {
+        return Ok(items);
+    }
+    let backlog: Vec<SelfossItem> = config
+        .source()
+        .fetch_all_unread()
+        .await?
+        .into_iter()
+        .filter(|item| config.handles_source(&item.sourcetitle))
+        .collect();
+    if backlog_action(&config.first_run, backlog.len(), ask)? == BacklogAction::Post {
+        return Ok(items);
+    }
+
+    stats.fetched += backlog.len();
+    for item in &backlog {
+        config.source().mark_read(item.id).await?;
+        stats.marked_read += 1;
+    }
+    info!(items = backlog.len(), "Marked the backlog as read");
+    Ok(vec![])
+}
+
+#[cfg(test)]
+mod test {
+    use httpmock::Method::{GET, POST};
+
+    use crate::{
+        backlog::{backlog_action, handle_first_run},
+        config::{BacklogAction, FirstRunConfig},
+        errors::AppError,
+        selfoss::models::SelfossItem,
+        state::State,
+        stats::RunStats,
+        test::{get_mock_item, start_server},
+    };
+
+    #[test]
+    fn test_backlog_action() {
+        let mut first_run = FirstRunConfig {
+            threshold: 2,
+            ..Default::default()
+        };
+        let unanswered = |_| None;
+        let action = backlog_action(&first_run, 2, unanswered).unwrap();
+        assert_eq!(action, BacklogAction::Post);
+        let result = backlog_action(&first_run, 3, unanswered);
+        assert!(matches!(result, Err(AppError::Backlog(3))));
+        let action = backlog_action(&first_run, 3, |_| Some(BacklogAction::MarkRead));
+        assert_eq!(action.unwrap(), BacklogAction::MarkRead);
+
+        first_run.backlog = BacklogAction::Post;
+        let action = backlog_action(&first_run, 3, unanswered).unwrap();
+        assert_eq!(action, BacklogAction::Post);
+    }
+
+    #[tokio::test]
+    async fn test_handle_first_run() {
+        let (server, mut config) = start_server();
+        config.first_run = FirstRunConfig {
+            threshold: 2,
+            ..Default::default()
+        };
+        let items: Vec<SelfossItem> = (0..250)
+            .map(|i| SelfossItem {
+                id: 187204 + i,
+                ..get_mock_item()
+            })
+            .collect();
+        let second_page_mock = server.mock(|when, then| {
+            when.method(GET).path("/items").query_param("offset", "200");
+            then.status(200).json_body_obj(&items[200..].to_vec());
+        });
+        let first_page_mock = server.mock(|when, then| {
+            when.method(GET).path("/items");
+            then.status(200).json_body_obj(&items[..200].to_vec());
+        });
+        let mark_item_read_mock = server.mock(|when, then| {
+            when.method(POST).path_contains("/mark/");
+            then.status(200);
+        });
+        let new_state = State {
+            is_new: true,
+            ..Default::default()
+        };
+        let mut stats = RunStats::default();
+
+        // Later runs are not checked.
+        let posted = handle_first_run(&config, &State::default(), &mut stats, items.clone())
+            .await
+            .unwrap();
+        assert_eq!(posted.len(), 250);
+        first_page_mock.assert_hits_async(0).await;
+
+        config.first_run.backlog = BacklogAction::Post;
+        let posted = handle_first_run(&config, &new_state, &mut stats, items[..200].to_vec())
+            .await
+            .unwrap();
+        assert_eq!(posted.len(), 200);
+        mark_item_read_mock.assert_hits_async(0).await;
+
+        // The whole backlog is marked as read, not only the newest page.
+        config.first_run.backlog = BacklogAction::MarkRead;
+        let posted = handle_first_run(&config, &new_state, &mut stats, items[..200].to_vec())
+            .await
+            .unwrap();
+        assert!(posted.is_empty());
+        first_page_mock.assert_hits_async(2).await;
+        second_page_mock.assert_hits_async(2).await;
+        mark_item_read_mock.assert_hits_async(250).await;
+        assert_eq!(stats.fetched, 250);
+        assert_eq!(stats.marked_read, 250);
+    }
+}
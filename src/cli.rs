@@ -0,0 +1,136 @@
+use chrono::NaiveDate;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use crate::config::BacklogAction;
+
+/// Send RSS updates from Selfoss to Discord.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(flatten)]
+    pub options: GlobalOptions,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct GlobalOptions {
+    /// TOML settings file, overrides `CONFIG_PATH`.
+    #[arg(long, global = true)]
+    pub config: Option<String>,
+    /// Log at debug level, unless `RUST_LOG` is set.
+    #[arg(short, long, global = true)]
+    pub verbose: bool,
+    /// Print the output of the command as JSON.
+    #[arg(long, global = true)]
+    pub json: bool,
+    /// Bridge to run the command for, `<selfoss instance>/<guild>`. Defaults to the first one.
+    #[arg(long, global = true)]
+    pub bridge: Option<String>,
+    /// What to do with the unread items on the first run, overrides `first_run.backlog`.
+    #[arg(long, global = true, value_enum)]
+    pub backlog: Option<BacklogAction>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Send unread Selfoss items to Discord once.
+    Sync,
+    /// Keep running and sync periodically, see the `[daemon]` settings.
+    Daemon,
+    /// List the sources in Selfoss.
+    ListSources,
+    /// List the channels in the Discord server.
+    ListChannels,
+    /// Show which route and channel the items of each source go to.
+    Map,
+    /// Post a test message to a channel, given by name or id.
+    SendTest {
+        channel: String,
+        /// Post through this sink instead of Discord.
+        #[arg(long)]
+        sink: Option<String>,
+        /// Delete the message again after posting it.
+        #[arg(long)]
+        delete: bool,
+    },
+    /// Mark the unread items of a source as read without posting them.
+    MarkRead {
+        #[arg(long)]
+        source: String,
+    },
+    /// Create, rename and archive channels to match the sources in Selfoss.
+    SyncSources,
+    /// Post items that were already read or starred again, without changing their read state.
+    Backfill(BackfillArgs),
+    /// Validate the settings and environment, and check access to Selfoss and Discord.
+    CheckConfig,
+    /// Create a category per folder and a channel per feed of an OPML file, and route the feeds
+    /// to their channels in the settings file.
+    ImportOpml(ImportOpmlArgs),
+    /// Inspect items that could not be delivered.
+    Failed {
+        #[command(subcommand)]
+        action: Option<FailedAction>,
+    },
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct BackfillArgs {
+    /// Which items to post.
+    #[arg(long = "type", value_enum, default_value_t = BackfillType::All)]
+    pub item_type: BackfillType,
+    /// Only items of the source with this title.
+    #[arg(long)]
+    pub source: Option<String>,
+    /// Only items with this tag.
+    #[arg(long)]
+    pub tag: Option<String>,
+    /// Only items published on or after this date, e.g. 2024-01-31.
+    #[arg(long)]
+    pub since: Option<NaiveDate>,
+    /// Only items published on or before this date.
+    #[arg(long)]
+    pub until: Option<NaiveDate>,
+    /// Post all items in this channel instead of their routed channels.
+    #[arg(long)]
+    pub channel: Option<String>,
+    /// Only show which items would be posted.
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct ImportOpmlArgs {
+    /// OPML file, e.g. exported from another feed reader.
+    pub path: String,
+    /// Subscribe to the feeds in Selfoss instead of adding routes to the settings file.
+    #[arg(long)]
+    pub subscribe: bool,
+    /// Only show which channels, routes and subscriptions would be added.
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum BackfillType {
+    Read,
+    Starred,
+    All,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum FailedAction {
+    /// List failed items with their last error (default).
+    List,
+    /// Retry failed items now, all of them if no ids are given.
+    Retry { ids: Vec<u64> },
+    /// Stop retrying items and mark them as read in the feed reader.
+    Discard {
+        #[arg(required = true)]
+        ids: Vec<u64>,
+        /// Also star the items, so they can still be found in the feed reader.
+        #[arg(long)]
+        star: bool,
+    },
+}
@@ -0,0 +1,528 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+use serde_json::json;
+use tracing::{info, warn, Instrument};
+
+use crate::{
+    config::{Config, Settings, SourceSyncConfig},
+    deliver_item,
+    discord::{
+        adapter::{delete_message, get_channels, post_message},
+        errors::RequestError,
+        models::{AllowedMentions, ChannelOptions},
+    },
+    errors::AppError,
+    get_channel_map, item_span,
+    selfoss::models::{SelfossItem, SelfossSource},
+    sinks::{Sinks, DISCORD_SINK},
+    source_sync,
+    state::State,
+    stats::RunStats,
+};
+
+const TEST_MESSAGE: &str = "Test message from selfoss-discord";
+
+/// Prints `value` as JSON, or as the text returned by `text`.
+pub fn print<T: Serialize + ?Sized>(json: bool, value: &T, text: impl FnOnce(&T) -> String) {
+    match json {
+        true => println!("{}", serde_json::to_string_pretty(value).unwrap()),
+        false => println!("{}", text(value)),
+    }
+}
+
+pub fn lines<T>(items: &[T], empty: &str, line: impl Fn(&T) -> String) -> String {
+    match items.is_empty() {
+        true => empty.to_string(),
+        false => items.iter().map(line).collect::<Vec<_>>().join("\n"),
+    }
+}
+
+pub async fn list_sources(config: &Config, json: bool) -> Result<(), RequestError> {
+    let sources = config.source().list_feeds().await?;
+    print(json, &sources, |sources| {
+        lines(sources, "No sources", |s| {
+            format!("{}\t{}\t{}", s.id, s.title, s.tags.join(", "))
+        })
+    });
+    Ok(())
+}
+
+pub async fn list_channels(config: &Config, json: bool) -> Result<(), RequestError> {
+    let channels = get_channels(config).await?;
+    print(json, &channels, |channels| {
+        lines(channels, "No channels", |c| format!("{}\t{}", c.id, c.name))
+    });
+    Ok(())
+}
+
+/// Where the items of a source are posted.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct SourceMapping {
+    pub source: String,
+    pub route: Option<String>,
+    pub channel: String,
+    /// `None` if the channel is created when the first item arrives.
+    pub channel_id: Option<String>,
+}
+
+pub fn source_mappings(
+    config: &Config,
+    sources: &[SelfossSource],
+    channel_map: &HashMap<String, String>,
+) -> Vec<SourceMapping> {
+    sources
+        .iter()
+        .map(|source| {
+            let channel = config.channel_name(&source.title);
+            SourceMapping {
+                source: source.title.clone(),
+                route: config
+                    .route_for_source(&source.title)
+                    .map(|r| r.name.clone()),
+                channel_id: channel_map.get(&channel).cloned(),
+                channel,
+            }
+        })
+        .collect()
+}
+
+pub async fn map(config: &Config, json: bool) -> Result<(), RequestError> {
+    let sources = config.source().list_feeds().await?;
+    let channel_map = get_channel_map(config).await?;
+    let mappings = source_mappings(config, &sources, &channel_map);
+    print(json, &mappings, |mappings| {
+        lines(mappings, "No sources", |m| {
+            let channel_id = m.channel_id.as_deref().unwrap_or("not created yet");
+            let route = m.route.as_deref().unwrap_or("no route");
+            format!("{} -> #{} ({})\t{}", m.source, m.channel, channel_id, route)
+        })
+    });
+    Ok(())
+}
+
+pub async fn sync_sources(config: &Config, json: bool) -> Result<(), AppError> {
+    let default = SourceSyncConfig::default();
+    let source_sync = config.source_sync.as_ref().unwrap_or(&default);
+    let mut state = State::load(&config.state_path).map_err(AppError::State)?;
+    let result = source_sync::sync_sources(config, source_sync, &mut state).await;
+    state.save(&config.state_path).map_err(AppError::State)?;
+    let stats = result?;
+    print(json, &stats, |stats| {
+        format!(
+            "Created {}, renamed {} and archived {} channels",
+            stats.created, stats.renamed, stats.archived
+        )
+    });
+    Ok(())
+}
+
+/// Posts a test message to the channel with the given name, or else the given id.
+pub async fn send_test(
+    config: &Config,
+    channel: &str,
+    sink: Option<&str>,
+    delete: bool,
+    json: bool,
+) -> Result<(), RequestError> {
+    let channel = channel.trim_start_matches('#');
+    let sink = sink.filter(|s| *s != DISCORD_SINK);
+    let mut sinks = Sinks::new(config, None);
+    let (channel_id, message_id) = match sink {
+        Some(sink) => {
+            let item = SelfossItem {
+                title: TEST_MESSAGE.to_string(),
+                sourcetitle: String::new(),
+                content: String::new(),
+                datetime: config.clock.now(),
+                id: 0,
+                link: String::new(),
+                author: None,
+                tags: vec![],
+                unread: false,
+                starred: false,
+                thumbnail: None,
+            };
+            let options = ChannelOptions::default();
+            sinks
+                .post_item(config, sink, channel, &options, &item)
+                .await?
+        }
+        None => {
+            let channel_map = get_channel_map(config).await?;
+            let channel_id = channel_map.get(channel).map_or(channel, String::as_str);
+            let message = post_message(
+                config,
+                channel_id,
+                TEST_MESSAGE,
+                &AllowedMentions::default(),
+            )
+            .await?;
+            (channel_id.to_string(), message.id)
+        }
+    };
+    if delete {
+        match sink {
+            Some(sink) => sinks.delete(config, sink, &channel_id, &message_id).await?,
+            None => delete_message(config, &channel_id, &message_id).await?,
+        }
+    }
+    let output = json!({"channel_id": channel_id, "message_id": message_id, "deleted": delete});
+    print(json, &output, |_| {
+        let action = if delete {
+            "Posted and deleted"
+        } else {
+            "Posted"
+        };
+        format!(
+            "{} message {} in channel {}",
+            action, message_id, channel_id
+        )
+    });
+    Ok(())
+}
+
+/// Marks all unread items of `source` as read without posting them.
+pub async fn mark_read(config: &Config, source: &str, json: bool) -> Result<(), RequestError> {
+    let items: Vec<SelfossItem> = config
+        .source()
+        .fetch_all_unread()
+        .await?
+        .into_iter()
+        .filter(|item| item.sourcetitle == source)
+        .collect();
+    let mut result = Ok(());
+    for item in &items {
+        result = config.source().mark_read(item.id).await;
+        if result.is_err() {
+            break;
+        }
+    }
+    config.source().save().await?;
+    result?;
+    let output = json!({"source": source, "marked_read": items.len()});
+    print(json, &output, |_| {
+        format!("Marked {} items of {:?} as read", items.len(), source)
+    });
+    Ok(())
+}
+
+/// Outcome of a single check of `check-config`.
+#[derive(Serialize, Debug)]
+pub struct Check {
+    pub name: String,
+    pub ok: bool,
+    pub message: String,
+}
+
+impl Check {
+    fn new(name: impl Into<String>, result: Result<String, String>) -> Self {
+        let ok = result.is_ok();
+        let message = result.unwrap_or_else(|e| e);
+        Check {
+            name: name.into(),
+            ok,
+            message,
+        }
+    }
+}
+
+/// Runs all checks and prints them, returns whether all of them passed.
+pub async fn check_config(
+    path: Option<&str>,
+    settings: Result<Option<Settings>, String>,
+    json: bool,
+) -> bool {
+    let mut checks = vec![];
+    let settings = match settings {
+        Ok(settings) => {
+            let message = match path {
+                Some(path) => format!("Read {}", path),
+                None => String::from("No settings file, using defaults"),
+            };
+            checks.push(Check::new("settings", Ok(message)));
+            settings.unwrap_or_default()
+        }
+        Err(e) => {
+            checks.push(Check::new("settings", Err(e)));
+            Settings::default()
+        }
+    };
+
+    let problems = settings.validate();
+    let routes = match problems.is_empty() {
+        true => Ok(format!("{} routes", settings.routes.len())),
+        false => Err(problems.join("; ")),
+    };
+    checks.push(Check::new("routes", routes));
+
+    match Config::bridges(&settings) {
+        Ok(bridges) => {
+            let names: Vec<&str> = bridges.iter().map(|b| b.name.as_str()).collect();
+            let message = format!("Bridges {}", names.join(", "));
+            checks.push(Check::new("environment", Ok(message)));
+            for config in &bridges {
+                let source = config.source();
+                let feeds = source
+                    .list_feeds()
+                    .await
+                    .map(|sources| format!("{} sources", sources.len()))
+                    .map_err(|e| config.redact(&e.to_string()));
+                let name = source.name().to_lowercase();
+                checks.push(Check::new(format!("{} {}", name, config.name), feeds));
+                let discord = get_channels(config)
+                    .await
+                    .map(|channels| format!("{} channels", channels.len()))
+                    .map_err(|e| config.redact(&e.to_string()));
+                checks.push(Check::new(format!("discord {}", config.name), discord));
+            }
+        }
+        Err(e) => checks.push(Check::new("environment", Err(e))),
+    }
+
+    print(json, &checks, |checks| {
+        lines(checks, "", |c| {
+            let status = if c.ok { "ok" } else { "error" };
+            format!("{}\t{}\t{}", status, c.name, c.message)
+        })
+    });
+    checks.iter().all(|c| c.ok)
+}
+
+pub fn list_failed(config: &Config, json: bool) -> Result<(), AppError> {
+    let state = State::load(&config.state_path).map_err(AppError::State)?;
+    print(json, &state.dead_letters, |dead_letters| {
+        lines(dead_letters, "No failed items", |dead_letter| {
+            let next_attempt = match dead_letter.retryable {
+                true => format!(
+                    "next attempt at {}",
+                    dead_letter.next_attempt_at.to_rfc3339()
+                ),
+                false => String::from("not retried automatically"),
+            };
+            let error = match dead_letter.posted {
+                true => format!("posted, but not marked as read: {}", dead_letter.error),
+                false => dead_letter.error.clone(),
+            };
+            format!(
+                "{}\t{} attempts\t{}\t{}: {}\n\t{}",
+                dead_letter.item.id,
+                dead_letter.attempts,
+                next_attempt,
+                dead_letter.item.sourcetitle,
+                dead_letter.item.title,
+                error
+            )
+        })
+    });
+    Ok(())
+}
+
+/// Immediately retries the failed items with the given ids, or all failed items if none given.
+pub async fn retry_failed(config: &Config, ids: &[u64]) -> Result<(), AppError> {
+    let mut state = State::load(&config.state_path).map_err(AppError::State)?;
+    let channel_map = get_channel_map(config).await?;
+    let items: Vec<SelfossItem> = state
+        .dead_letters
+        .iter()
+        .filter(|d| ids.is_empty() || ids.contains(&d.item.id))
+        .map(|d| d.item.clone())
+        .collect();
+
+    let mut stats = RunStats::default();
+    let mut sinks = Sinks::new(config, Some(channel_map));
+    let mut result = Ok(());
+    for item in &items {
+        let now = config.clock.now();
+        result = deliver_item(config, &mut state, &mut stats, item, &mut sinks, now)
+            .instrument(item_span(item))
+            .await;
+        if result.is_err() {
+            break;
+        }
+    }
+    state.save(&config.state_path).map_err(AppError::State)?;
+    config.source().save().await?;
+    result?;
+    info!(
+        retried = items.len(),
+        failed = stats.failed,
+        "Retried failed items"
+    );
+    Ok(())
+}
+
+/// Removes failed items from the dead-letter queue and marks them as read in the feed reader.
+pub async fn discard_failed(config: &Config, ids: &[u64], star: bool) -> Result<(), AppError> {
+    let mut state = State::load(&config.state_path).map_err(AppError::State)?;
+    let source = config.source();
+    let mut result = Ok(());
+    for &id in ids {
+        if state.dead_letter(id).is_none() {
+            warn!(id, "No failed item with this id");
+            continue;
+        }
+        result = async {
+            if star {
+                source.star(id).await?;
+            }
+            source.mark_read(id).await
+        }
+        .await;
+        if result.is_err() {
+            break;
+        }
+        // Only removed once it is marked as read, so the items discarded before an error stay
+        // discarded and the others stay queued.
+        state.remove_dead_letter(id);
+        info!(id, "Discarded failed item");
+    }
+    state.save(&config.state_path).map_err(AppError::State)?;
+    source.save().await?;
+    Ok(result?)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, env, fs};
+
+    use httpmock::Method::{GET, POST};
+
+    use crate::{
+        commands::{discard_failed, mark_read, source_mappings, SourceMapping},
+        config::Route,
+        selfoss::models::{SelfossItem, SelfossSource},
+        state::State,
+        test::{get_mock_item, start_server},
+    };
+
+    #[tokio::test]
+    async fn test_discard_failed_keeps_items_after_error() {
+        let (server, mut config) = start_server();
+        let path = env::temp_dir().join(format!(
+            "selfoss-discord-discard-{}.json",
+            std::process::id()
+        ));
+        config.state_path = path.to_string_lossy().into_owned();
+        let mut state = State::default();
+        for id in [187204, 187205] {
+            let item = SelfossItem {
+                id,
+                ..get_mock_item()
+            };
+            state.record_failure(
+                &item,
+                String::from("error"),
+                true,
+                false,
+                config.clock.now(),
+            );
+        }
+        state.save(&config.state_path).unwrap();
+
+        let failed_mock = server.mock(|when, then| {
+            when.method(POST).path("/mark/187205");
+            then.status(500);
+        });
+        let mark_item_read_mock = server.mock(|when, then| {
+            when.method(POST).path("/mark/187204");
+            then.status(200);
+        });
+
+        assert!(discard_failed(&config, &[187204, 187205], false)
+            .await
+            .is_err());
+        mark_item_read_mock.assert_async().await;
+        failed_mock.assert_async().await;
+        let ids: Vec<u64> = State::load(&config.state_path)
+            .unwrap()
+            .dead_letters
+            .iter()
+            .map(|d| d.item.id)
+            .collect();
+        assert_eq!(ids, vec![187205]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_mark_read_pages_through_items() {
+        let (server, config) = start_server();
+        let items: Vec<SelfossItem> = (0..250)
+            .map(|i| SelfossItem {
+                id: 187204 + i,
+                sourcetitle: String::from(if i % 2 == 0 { "my_channel" } else { "other" }),
+                ..get_mock_item()
+            })
+            .collect();
+        let second_page_mock = server.mock(|when, then| {
+            when.method(GET).path("/items").query_param("offset", "200");
+            then.status(200).json_body_obj(&items[200..].to_vec());
+        });
+        let first_page_mock = server.mock(|when, then| {
+            when.method(GET).path("/items");
+            then.status(200).json_body_obj(&items[..200].to_vec());
+        });
+        let other_read_mock = server.mock(|when, then| {
+            when.method(POST).path("/mark/187205");
+            then.status(200);
+        });
+
+        let mark_item_read_mock = server.mock(|when, then| {
+            when.method(POST).path_contains("/mark/");
+            then.status(200);
+        });
+        mark_read(&config, "my_channel", true).await.unwrap();
+        first_page_mock.assert_async().await;
+        second_page_mock.assert_async().await;
+        mark_item_read_mock.assert_hits_async(125).await;
+        other_read_mock.assert_hits_async(0).await;
+    }
+
+    #[test]
+    fn test_source_mappings() {
+        let (_server, mut config) = start_server();
+        config.routes = vec![Route {
+            name: String::from("sims"),
+            sources: vec![String::from("MSFS News")],
+            channel: Some(String::from("flight-sim")),
+            selfoss: None,
+            guild: None,
+            category: None,
+            topic: None,
+            announcement: false,
+            sink: None,
+            digest: None,
+            window: None,
+            limit: None,
+            filters: vec![],
+            mentions: vec![],
+        }];
+        let source = |id, title: &str| SelfossSource {
+            id,
+            title: title.to_string(),
+            tags: vec![],
+            spout: String::new(),
+        };
+        let sources = vec![source(1, "MSFS News"), source(2, "my channel")];
+        let channel_map =
+            HashMap::from([(String::from("my-channel"), String::from("my_channel_id"))]);
+
+        assert_eq!(
+            source_mappings(&config, &sources, &channel_map),
+            vec![
+                SourceMapping {
+                    source: String::from("MSFS News"),
+                    route: Some(String::from("sims")),
+                    channel: String::from("flight-sim"),
+                    channel_id: None,
+                },
+                SourceMapping {
+                    source: String::from("my channel"),
+                    route: None,
+                    channel: String::from("my-channel"),
+                    channel_id: Some(String::from("my_channel_id")),
+                },
+            ]
+        );
+    }
+}
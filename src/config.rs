@@ -1,9 +1,1284 @@
-#[derive(Clone)]
+use std::{collections::HashMap, env, fmt, fs, net::SocketAddr, sync::Arc};
+
+use chrono::{Locale, NaiveTime, Weekday};
+use chrono_tz::Tz;
+use clap::ValueEnum;
+use serde::{Deserialize, Deserializer};
+
+use crate::{
+    discord::models::{ChannelOptions, GUILD_ANNOUNCEMENT, MAX_ATTACHMENTS},
+    filters::Filter,
+    mentions::Mention,
+    metrics::Metrics,
+    selfoss::models::{channel_name_for_source, SelfossItem},
+    sinks::DISCORD_SINK,
+    sources::{self, feeds::Feeds, Selfoss, Source},
+    timestamps::is_valid_format,
+    utils::Clock,
+};
+
+/// Name of the Selfoss instance and Discord guild that are configured through the environment.
+pub const DEFAULT_NAME: &str = "default";
+
+/// Name of the instance that fetches the `[[feeds]]` directly.
+pub const FEEDS_NAME: &str = "feeds";
+
+/// Inserts `name` into the path of the state file, e.g. `state.<name>.json`.
+fn state_path_with(path: &str, name: &str) -> String {
+    match path.strip_suffix(".json") {
+        Some(stem) => format!("{}.{}.json", stem, name),
+        None => format!("{}.{}", path, name),
+    }
+}
+
+/// Inserts the names of the instance and guild of a bridge into the path of the state file.
+fn bridge_state_path(path: &str, instance: &str, guild: &str) -> String {
+    state_path_with(path, &format!("{}.{}", instance, guild))
+}
+
+#[derive(Clone, Default)]
 pub struct Config {
+    /// Name of the bridge, `<selfoss instance>/<guild>`.
+    pub name: String,
+    /// Name of the guild that this config posts in.
+    pub guild: String,
+    /// Name of the guild that items are posted in unless their route names another one.
+    pub default_guild: String,
     pub discord_base_url: String,
     pub discord_token: String,
     pub discord_server_id: String,
     pub selfoss_base_url: String,
     pub selfoss_username: String,
     pub selfoss_password: String,
+    pub state_path: String,
+    pub routes: Vec<Route>,
+    pub filters: Vec<Filter>,
+    pub mark_filtered_as_read: bool,
+    pub max_item_age_hours: Option<i64>,
+    pub mark_old_as_read: bool,
+    pub first_run: FirstRunConfig,
+    pub mentions: Vec<Mention>,
+    pub sanitize: SanitizeConfig,
+    pub timestamps: Option<TimestampConfig>,
+    pub dedup: Option<DedupConfig>,
+    pub on_channel_deleted: ChannelDeletedAction,
+    pub source_sync: Option<SourceSyncConfig>,
+    pub sinks: Vec<SinkConfig>,
+    pub images: Option<ImageConfig>,
+    /// The feed reader of the instance, Selfoss if not set.
+    pub source: Option<Arc<dyn Source>>,
+    pub metrics: Arc<Metrics>,
+    pub clock: Clock,
+}
+
+const REDACTED: &str = "[redacted]";
+
+/// Encodes `s` the way it appears in the query string of a request.
+fn form_urlencode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'*' => {
+                (b as char).to_string()
+            }
+            b' ' => String::from("+"),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+impl Config {
+    /// Builds a config for every pair of Selfoss instance and Discord guild that items are
+    /// posted between, from the environment and the settings file.
+    pub fn bridges(settings: &Settings) -> Result<Vec<Config>, String> {
+        let mut missing = vec![];
+        let mut var = |key: &str| {
+            env::var(key).unwrap_or_else(|_| {
+                missing.push(key.to_string());
+                String::new()
+            })
+        };
+
+        let mut instances = settings.selfoss.clone();
+        // Feeds can be fetched without any Selfoss instance.
+        let without_selfoss = instances.is_empty() && settings.feeds.is_empty();
+        if without_selfoss || env::var("SELFOSS_BASE_URL").is_ok() {
+            let instance = SelfossInstance {
+                name: default_name(),
+                base_url: var("SELFOSS_BASE_URL"),
+                username: var("SELFOSS_USERNAME"),
+                password_env: String::from("SELFOSS_PASSWORD"),
+                guild: default_name(),
+                kind: SourceKind::Selfoss,
+            };
+            instances.insert(0, instance);
+        }
+        if !settings.feeds.is_empty() {
+            instances.push(SelfossInstance {
+                name: String::from(FEEDS_NAME),
+                base_url: String::new(),
+                username: String::new(),
+                password_env: String::new(),
+                guild: default_name(),
+                kind: SourceKind::Feeds,
+            });
+        }
+        let mut guilds = settings.guilds.clone();
+        if guilds.is_empty() || env::var("DISCORD_SERVER_ID").is_ok() {
+            let guild = GuildConfig {
+                name: default_name(),
+                server_id: var("DISCORD_SERVER_ID"),
+                token_env: default_token_env(),
+            };
+            guilds.insert(0, guild);
+        }
+        let passwords: Vec<String> = instances
+            .iter()
+            .map(|i| match i.kind {
+                SourceKind::Feeds => String::new(),
+                _ => var(&i.password_env),
+            })
+            .collect();
+        let tokens: Vec<String> = guilds.iter().map(|g| var(&g.token_env)).collect();
+        if !missing.is_empty() {
+            missing.dedup();
+            return Err(format!(
+                "Missing environment variables: {}",
+                missing.join(", ")
+            ));
+        }
+
+        let guild_exists = |name: &String| guilds.iter().any(|g| &g.name == name);
+        for route in &settings.routes {
+            if let Some(name) = route.selfoss.as_ref() {
+                if !instances.iter().any(|i| &i.name == name) {
+                    return Err(format!(
+                        "Route {:?} uses unknown Selfoss instance {:?}",
+                        route.name, name
+                    ));
+                }
+            }
+            if let Some(name) = route.guild.as_ref().filter(|name| !guild_exists(name)) {
+                return Err(format!(
+                    "Route {:?} uses unknown guild {:?}",
+                    route.name, name
+                ));
+            }
+        }
+        if let Some(instance) = instances.iter().find(|i| !guild_exists(&i.guild)) {
+            return Err(format!(
+                "Selfoss instance {:?} uses unknown guild {:?}",
+                instance.name, instance.guild
+            ));
+        }
+
+        let metrics = Metrics::new();
+        let state_path = settings
+            .state_path
+            .clone()
+            .unwrap_or_else(|| String::from("state.json"));
+        let mut bridges = vec![];
+        for (instance, password) in instances.iter().zip(passwords) {
+            let routes: Vec<Route> = settings
+                .routes
+                .iter()
+                .filter(|r| r.selfoss.as_ref().is_none_or(|s| s == &instance.name))
+                .cloned()
+                .collect();
+            let used_guilds: Vec<(&GuildConfig, &String)> = guilds
+                .iter()
+                .zip(&tokens)
+                .filter(|(guild, _)| {
+                    guild.name == instance.guild
+                        || routes.iter().any(|r| r.guild.as_ref() == Some(&guild.name))
+                })
+                .collect();
+            let names: Vec<String> = used_guilds
+                .iter()
+                .map(|(guild, _)| format!("{}/{}", instance.name, guild.name))
+                .collect();
+            // Shared by the bridges of the instance, so that they share its login.
+            let source_metrics = Arc::new(metrics.for_bridges(&names));
+            let source = match instance.kind {
+                SourceKind::Feeds => Some(Arc::new(Feeds::new(
+                    settings.feeds.clone(),
+                    state_path_with(&state_path, FEEDS_NAME),
+                    source_metrics,
+                )) as Arc<dyn Source>),
+                kind => sources::build(
+                    kind,
+                    &instance.base_url,
+                    &instance.username,
+                    &password,
+                    source_metrics,
+                ),
+            };
+            for ((guild, token), name) in used_guilds.into_iter().zip(names) {
+                let is_default = instance.name == DEFAULT_NAME && guild.name == DEFAULT_NAME;
+                bridges.push(Config {
+                    metrics: Arc::new(metrics.for_bridges(std::slice::from_ref(&name))),
+                    name,
+                    guild: guild.name.clone(),
+                    default_guild: instance.guild.clone(),
+                    discord_base_url: String::from("https://discord.com/api/v10"),
+                    discord_token: token.clone(),
+                    discord_server_id: guild.server_id.clone(),
+                    selfoss_base_url: instance.base_url.clone(),
+                    selfoss_username: instance.username.clone(),
+                    selfoss_password: password.clone(),
+                    // Every bridge keeps its own state, the default one where it always was.
+                    state_path: match is_default {
+                        true => state_path.clone(),
+                        false => bridge_state_path(&state_path, &instance.name, &guild.name),
+                    },
+                    routes: routes.clone(),
+                    filters: settings.filters.clone(),
+                    mark_filtered_as_read: settings.mark_filtered_as_read,
+                    max_item_age_hours: settings.max_item_age_hours,
+                    mark_old_as_read: settings.mark_old_as_read,
+                    first_run: settings.first_run,
+                    mentions: settings.mentions.clone(),
+                    sanitize: settings.sanitize,
+                    timestamps: settings.timestamps.clone(),
+                    dedup: settings.dedup.clone(),
+                    on_channel_deleted: settings.on_channel_deleted,
+                    source_sync: settings.source_sync.clone(),
+                    sinks: settings.sinks.clone(),
+                    images: settings.images.clone(),
+                    source: source.clone(),
+                    clock: Clock::System,
+                });
+            }
+        }
+        Ok(bridges)
+    }
+
+    /// The feed reader that items are fetched from and marked as read in.
+    pub fn source(&self) -> Arc<dyn Source> {
+        match &self.source {
+            Some(source) => source.clone(),
+            None => Arc::new(Selfoss::new(self.clone())),
+        }
+    }
+
+    /// Replaces the Discord token, the password of the feed reader and the secrets of the sinks
+    /// in `text`, e.g. in URLs of errors.
+    pub fn redact(&self, text: &str) -> String {
+        let sink_secrets = self
+            .sinks
+            .iter()
+            .flat_map(|sink| sink.kind.secret_envs())
+            .filter_map(|key| env::var(key).ok());
+        [self.discord_token.clone(), self.selfoss_password.clone()]
+            .into_iter()
+            .chain(sink_secrets)
+            .filter(|secret| !secret.is_empty())
+            .flat_map(|secret| [form_urlencode(&secret), secret])
+            .fold(text.to_string(), |text, secret| {
+                text.replace(&secret, REDACTED)
+            })
+    }
+}
+
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("name", &self.name)
+            .field("discord_base_url", &self.discord_base_url)
+            .field("discord_token", &REDACTED)
+            .field("discord_server_id", &self.discord_server_id)
+            .field(
+                "source",
+                &self.source.as_ref().map_or("Selfoss", |s| s.name()),
+            )
+            .field("selfoss_base_url", &self.selfoss_base_url)
+            .field("selfoss_username", &self.selfoss_username)
+            .field("selfoss_password", &REDACTED)
+            .field("state_path", &self.state_path)
+            .field("routes", &self.routes)
+            .field("filters", &self.filters)
+            .field("mark_filtered_as_read", &self.mark_filtered_as_read)
+            .field("max_item_age_hours", &self.max_item_age_hours)
+            .field("mark_old_as_read", &self.mark_old_as_read)
+            .field("first_run", &self.first_run)
+            .field("mentions", &self.mentions)
+            .field("sanitize", &self.sanitize)
+            .field("timestamps", &self.timestamps)
+            .field("dedup", &self.dedup)
+            .field("on_channel_deleted", &self.on_channel_deleted)
+            .field("source_sync", &self.source_sync)
+            .field("sinks", &self.sinks)
+            .field("images", &self.images)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Settings read from the optional TOML file given by `--config` or `CONFIG_PATH`.
+#[derive(Deserialize, Debug)]
+pub struct Settings {
+    pub state_path: Option<String>,
+    #[serde(default)]
+    pub routes: Vec<Route>,
+    /// Filters that apply to the items of all routes.
+    #[serde(default)]
+    pub filters: Vec<Filter>,
+    /// Whether items dropped by a filter are marked as read in Selfoss.
+    #[serde(default = "default_true")]
+    pub mark_filtered_as_read: bool,
+    /// Items published longer ago than this are not posted.
+    pub max_item_age_hours: Option<i64>,
+    /// Whether items that are too old are marked as read in Selfoss.
+    #[serde(default = "default_true")]
+    pub mark_old_as_read: bool,
+    /// What to do with the unread items when the bridge runs for the first time.
+    #[serde(default)]
+    pub first_run: FirstRunConfig,
+    /// Roles and users that are pinged for matching items of all routes.
+    #[serde(default)]
+    pub mentions: Vec<Mention>,
+    /// How feed text is made safe to post in Discord.
+    #[serde(default)]
+    pub sanitize: SanitizeConfig,
+    /// Show when items were published in their messages.
+    pub timestamps: Option<TimestampConfig>,
+    pub dedup: Option<DedupConfig>,
+    /// What to do when the channel of a feed has been deleted in Discord.
+    #[serde(default)]
+    pub on_channel_deleted: ChannelDeletedAction,
+    /// Keep a channel for every Selfoss source, also before it has new items.
+    pub source_sync: Option<SourceSyncConfig>,
+    /// Selfoss instances besides the one configured through the environment.
+    #[serde(default)]
+    pub selfoss: Vec<SelfossInstance>,
+    /// Discord servers besides the one configured through the environment.
+    #[serde(default)]
+    pub guilds: Vec<GuildConfig>,
+    /// Chat services that routes can post to instead of Discord.
+    #[serde(default)]
+    pub sinks: Vec<SinkConfig>,
+    /// Feeds that are fetched directly instead of through a feed reader.
+    #[serde(default)]
+    pub feeds: Vec<FeedConfig>,
+    /// Upload the images of items to Discord instead of relying on embeds.
+    pub images: Option<ImageConfig>,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    /// Keep running and sync periodically instead of exiting after a single sync.
+    pub daemon: Option<DaemonConfig>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct SelfossInstance {
+    pub name: String,
+    pub base_url: String,
+    pub username: String,
+    /// Environment variable that holds the password.
+    pub password_env: String,
+    /// Guild that items are posted in, unless their route names another one.
+    #[serde(default = "default_name")]
+    pub guild: String,
+    /// The feed reader, which does not have to be Selfoss.
+    #[serde(rename = "type", default)]
+    pub kind: SourceKind,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceKind {
+    #[default]
+    Selfoss,
+    Miniflux,
+    /// FreshRSS or another reader with the Google Reader API.
+    FreshRss,
+    /// Tiny Tiny RSS.
+    TtRss,
+    /// The `[[feeds]]`, which cannot be configured as instance.
+    #[serde(skip)]
+    Feeds,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct FeedConfig {
+    /// URL of an RSS, Atom or JSON feed.
+    pub url: String,
+    /// Defaults to the title of the feed.
+    pub title: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct GuildConfig {
+    pub name: String,
+    pub server_id: String,
+    /// Environment variable that holds the bot token.
+    #[serde(default = "default_token_env")]
+    pub token_env: String,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct SinkConfig {
+    /// Name that routes refer to with `sink`.
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: SinkKind,
+}
+
+/// The service of a sink, secrets are read from the environment variables named by `*_env`.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SinkKind {
+    /// Uses the Web API with a bot token, or an incoming webhook if `webhook_env` is set.
+    Slack {
+        #[serde(default = "default_slack_url")]
+        base_url: String,
+        #[serde(default = "default_slack_token_env")]
+        token_env: String,
+        webhook_env: Option<String>,
+    },
+    Matrix {
+        homeserver: String,
+        server_name: String,
+        token_env: String,
+    },
+    Mattermost {
+        webhook_env: String,
+    },
+    Telegram {
+        #[serde(default = "default_telegram_url")]
+        base_url: String,
+        token_env: String,
+        /// Chat ids by channel name.
+        #[serde(default)]
+        chats: HashMap<String, String>,
+        default_chat: Option<String>,
+    },
+}
+
+impl SinkKind {
+    /// The environment variables that hold the secrets of the sink.
+    pub fn secret_envs(&self) -> Vec<&str> {
+        match self {
+            SinkKind::Slack {
+                token_env,
+                webhook_env,
+                ..
+            } => [Some(token_env), webhook_env.as_ref()]
+                .into_iter()
+                .flatten()
+                .map(String::as_str)
+                .collect(),
+            SinkKind::Matrix { token_env, .. } | SinkKind::Telegram { token_env, .. } => {
+                vec![token_env]
+            }
+            SinkKind::Mattermost { webhook_env } => vec![webhook_env],
+        }
+    }
+}
+
+fn default_slack_url() -> String {
+    String::from("https://slack.com/api")
+}
+
+fn default_slack_token_env() -> String {
+    String::from("SLACK_TOKEN")
+}
+
+fn default_telegram_url() -> String {
+    String::from("https://api.telegram.org")
+}
+
+fn default_name() -> String {
+    String::from(DEFAULT_NAME)
+}
+
+fn default_token_env() -> String {
+    String::from("DISCORD_TOKEN")
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct DaemonConfig {
+    #[serde(default = "default_interval_seconds")]
+    pub interval_seconds: u64,
+    /// Address to serve `/metrics`, `/healthz` and `/readyz` on, e.g. `127.0.0.1:9184`.
+    pub metrics_address: Option<SocketAddr>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ChannelDeletedAction {
+    /// Create the channel again and post the item there.
+    #[default]
+    Recreate,
+    /// Stop posting items of the feed and mark them as read.
+    Unsubscribe,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct SourceSyncConfig {
+    /// Category that channels of removed sources are moved to.
+    #[serde(default = "default_archive_category")]
+    pub archive_category: String,
+    /// Consecutive runs a source has to be missing from the reader before its channel is
+    /// archived, so that an incomplete response does not archive channels.
+    #[serde(default = "default_archive_after_runs")]
+    pub archive_after_runs: u32,
+    #[serde(default)]
+    pub layout: ChannelLayout,
+    /// Where the colours of Selfoss tags are shown, only used with the tags layout.
+    pub tag_colors: Option<TagColors>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ChannelLayout {
+    /// Channels are left in the category they were created in.
+    #[default]
+    Flat,
+    /// Every Selfoss tag is a category, containing the channels of its sources.
+    Tags,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TagColors {
+    /// Mention the tag and its colour in the topic of its channels.
+    Topic,
+    /// Keep a role per tag with the colour of the tag.
+    Role,
+}
+
+fn default_archive_category() -> String {
+    String::from("archived")
+}
+
+fn default_archive_after_runs() -> u32 {
+    3
+}
+
+impl Default for SourceSyncConfig {
+    fn default() -> Self {
+        toml::from_str("").unwrap()
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct LoggingConfig {
+    /// Filter directives such as `info` or `warn,selfoss_discord=debug`, overridden by `RUST_LOG`.
+    #[serde(default = "default_log_level")]
+    pub level: String,
+    #[serde(default)]
+    pub format: LogFormat,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human-readable lines.
+    #[default]
+    Text,
+    /// One JSON object per line, for journald or Loki.
+    Json,
+}
+
+/// A route applies delivery options to the items of one or more sources.
+///
+/// A route without `sources` matches every item.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Route {
+    pub name: String,
+    #[serde(default)]
+    pub sources: Vec<String>,
+    pub channel: Option<String>,
+    /// Only apply to items of this Selfoss instance.
+    pub selfoss: Option<String>,
+    /// Post in this guild instead of the default guild of the Selfoss instance.
+    pub guild: Option<String>,
+    /// Id of the Discord category that channels of this route are created in.
+    pub category: Option<String>,
+    /// Topic of channels created for this route.
+    pub topic: Option<String>,
+    /// Create announcement channels and publish every message to the servers that follow them.
+    #[serde(default)]
+    pub announcement: bool,
+    /// Name of the sink to post to, Discord if not set.
+    pub sink: Option<String>,
+    pub digest: Option<DigestConfig>,
+    /// Only post during these hours, items arriving outside of them are queued.
+    pub window: Option<WindowConfig>,
+    /// Limit how many items are posted in a channel or from a source.
+    pub limit: Option<LimitConfig>,
+    #[serde(default)]
+    pub filters: Vec<Filter>,
+    #[serde(default)]
+    pub mentions: Vec<Mention>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct DigestConfig {
+    pub schedule: DigestSchedule,
+    /// Local time of day at which daily digests are posted.
+    pub at: Option<NaiveTime>,
+    #[serde(default = "default_timezone")]
+    pub timezone: Tz,
+}
+
+/// Hours during which the items of a route are posted. Items arriving outside of them are queued
+/// in the state and posted in order when the window opens.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct WindowConfig {
+    /// Days on which the window opens, every day if empty.
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    pub start: NaiveTime,
+    /// End of the window, on the next day if it is before `start`.
+    pub end: NaiveTime,
+    #[serde(default = "default_timezone")]
+    pub timezone: Tz,
+    /// Post the queued items as a digest if more than this many are waiting.
+    pub digest_threshold: Option<usize>,
+}
+
+/// Caps on the number of items that are posted, for feeds that publish many items at once.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct LimitConfig {
+    /// Cap per channel, counting the items of all sources posted in it.
+    pub channel: Option<Cap>,
+    /// Cap per source.
+    pub source: Option<Cap>,
+    #[serde(default)]
+    pub overflow: OverflowAction,
+}
+
+/// At most `items` items per `minutes` minutes.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Cap {
+    pub items: usize,
+    pub minutes: i64,
+}
+
+/// What happens to items over a cap.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OverflowAction {
+    /// Leave them unread, so they are posted once the cap allows it.
+    #[default]
+    Defer,
+    /// Post their links in a single "and N more" message, and mark them as read.
+    Summarize,
+    /// Mark them as read without posting.
+    Drop,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DigestSchedule {
+    Hourly,
+    Daily,
+}
+
+/// Detects the same story arriving through several feeds.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct DedupConfig {
+    /// How long posted items are remembered.
+    #[serde(default = "default_dedup_window_hours")]
+    pub window_hours: i64,
+    /// Minimum similarity (between 0 and 1) of two titles to be considered duplicates.
+    #[serde(default = "default_title_similarity")]
+    pub title_similarity: f64,
+    #[serde(default)]
+    pub action: DedupAction,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DedupAction {
+    /// Drop the duplicate.
+    #[default]
+    Drop,
+    /// Append "also in: <source>" to the message of the original.
+    Annotate,
+}
+
+/// Downloads the images of items and uploads them as attachments, as many feeds hotlink images
+/// that Discord cannot embed.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct ImageConfig {
+    /// Number of images in the gallery of a message, at most 10.
+    #[serde(default = "default_max_images")]
+    pub max_images: usize,
+    /// Images that are larger are not uploaded, in bytes.
+    #[serde(default = "default_max_image_bytes")]
+    pub max_bytes: usize,
+    /// Total size of the images of a message, in bytes. The default leaves room for the message
+    /// in Discord's upload limit of servers without boosts.
+    #[serde(default = "default_max_total_image_bytes")]
+    pub max_total_bytes: usize,
+    /// Media types of the images that are uploaded.
+    #[serde(default = "default_image_types")]
+    pub types: Vec<String>,
+}
+
+impl Default for ImageConfig {
+    fn default() -> Self {
+        toml::from_str("").unwrap()
+    }
+}
+
+fn default_max_images() -> usize {
+    4
+}
+
+fn default_max_image_bytes() -> usize {
+    4 * 1024 * 1024
+}
+
+fn default_max_total_image_bytes() -> usize {
+    8 * 1024 * 1024
+}
+
+fn default_image_types() -> Vec<String> {
+    ["image/png", "image/jpeg", "image/gif", "image/webp"]
+        .map(String::from)
+        .to_vec()
+}
+
+/// Keeps the bridge from posting thousands of unread items when it runs for the first time, that
+/// is without a state file.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct FirstRunConfig {
+    #[serde(default)]
+    pub backlog: BacklogAction,
+    /// Number of unread items above which `ask` asks.
+    #[serde(default = "default_backlog_threshold")]
+    pub threshold: usize,
+}
+
+impl Default for FirstRunConfig {
+    fn default() -> Self {
+        toml::from_str("").unwrap()
+    }
+}
+
+fn default_backlog_threshold() -> usize {
+    100
+}
+
+#[derive(Deserialize, ValueEnum, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BacklogAction {
+    /// Post the items if there are at most `threshold` of them, otherwise ask on the terminal, or
+    /// fail without a terminal.
+    #[default]
+    #[value(skip)]
+    Ask,
+    /// Post all items.
+    Post,
+    /// Mark all items as read without posting them.
+    MarkRead,
+}
+
+/// Feed text is always posted without working mentions or masked links, these options sanitize
+/// it further.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct SanitizeConfig {
+    /// Escape Discord Markdown, so that feed text is shown as written.
+    #[serde(default)]
+    pub escape_markdown: bool,
+    /// Remove links that invite to Discord servers.
+    #[serde(default)]
+    pub strip_invites: bool,
+}
+
+/// How the publication time of an item is shown at the end of its message.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct TimestampConfig {
+    #[serde(default)]
+    pub style: TimestampStyle,
+    /// `strftime` format of the `formatted` style.
+    #[serde(default = "default_timestamp_format")]
+    pub format: String,
+    /// Timezone of the `formatted` style, Discord shows its own timestamps in the timezone of
+    /// the reader.
+    #[serde(default = "default_timezone")]
+    pub timezone: Tz,
+    /// Locale of the names of months and days in the `formatted` style, such as `nl_NL`.
+    #[serde(default = "default_locale", deserialize_with = "deserialize_locale")]
+    pub locale: Locale,
+    /// Note how long before delivery an item was published, if it was at least this many hours.
+    #[serde(default = "default_delay_note_hours")]
+    pub delay_note_hours: i64,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TimestampStyle {
+    /// "3 hours ago", kept up to date by Discord.
+    #[default]
+    Relative,
+    /// "15 December 2023 18:40", in the timezone and language of the reader.
+    Full,
+    /// Formatted by the bridge with `format`, `timezone` and `locale`.
+    Formatted,
+}
+
+fn default_timestamp_format() -> String {
+    String::from("%-d %B %Y %H:%M %Z")
+}
+
+fn default_locale() -> Locale {
+    Locale::en_US
+}
+
+fn deserialize_locale<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Locale, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    Locale::try_from(name.as_str())
+        .map_err(|_| serde::de::Error::custom(format!("unknown locale {:?}", name)))
+}
+
+fn default_delay_note_hours() -> i64 {
+    6
+}
+
+fn default_dedup_window_hours() -> i64 {
+    24
+}
+
+fn default_title_similarity() -> f64 {
+    0.9
+}
+
+fn default_interval_seconds() -> u64 {
+    300
+}
+
+fn default_log_level() -> String {
+    String::from("info")
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_timezone() -> Tz {
+    Tz::UTC
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        toml::from_str("").unwrap()
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        toml::from_str("").unwrap()
+    }
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        toml::from_str("").unwrap()
+    }
+}
+
+impl Settings {
+    /// Finds mistakes that parse fine but do not do what was intended.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+        for (i, route) in self.routes.iter().enumerate() {
+            let earlier = &self.routes[..i];
+            if earlier.iter().any(|r| r.name == route.name) {
+                problems.push(format!("Route {:?} is defined more than once", route.name));
+            }
+            if let Some(catch_all) = earlier.iter().find(|r| r.sources.is_empty()) {
+                problems.push(format!(
+                    "Route {:?} is never used, route {:?} before it matches all sources",
+                    route.name, catch_all.name
+                ));
+            }
+            if let Some(sink) = route.sink.as_ref().filter(|s| *s != DISCORD_SINK) {
+                if !self.sinks.iter().any(|s| &s.name == sink) {
+                    problems.push(format!(
+                        "Route {:?} uses unknown sink {:?}",
+                        route.name, sink
+                    ));
+                }
+                if route.digest.is_some() {
+                    problems.push(format!(
+                        "Route {:?} has a digest, which is only supported in Discord",
+                        route.name
+                    ));
+                }
+                if route
+                    .limit
+                    .as_ref()
+                    .is_some_and(|l| l.overflow == OverflowAction::Summarize)
+                {
+                    problems.push(format!(
+                        "Route {:?} summarizes overflowing items, which is only supported in \
+                         Discord",
+                        route.name
+                    ));
+                }
+            }
+            if let Some(window) = &route.window {
+                if window.start == window.end {
+                    problems.push(format!(
+                        "The window of route {:?} starts and ends at the same time",
+                        route.name
+                    ));
+                }
+                if route.digest.is_some() {
+                    problems.push(format!(
+                        "Route {:?} has a digest, its window is not used",
+                        route.name
+                    ));
+                }
+            }
+        }
+        let route_mentions = self.routes.iter().flat_map(|r| &r.mentions);
+        if self
+            .mentions
+            .iter()
+            .chain(route_mentions)
+            .any(|m| m.role.is_none() && m.user.is_none())
+        {
+            problems.push(String::from("A mention has neither a role nor a user"));
+        }
+        if let Some(timestamps) = &self.timestamps {
+            if !is_valid_format(&timestamps.format) {
+                problems.push(format!(
+                    "timestamps.format {:?} is not a valid format",
+                    timestamps.format
+                ));
+            }
+        }
+        if let Some(images) = &self.images {
+            if !(1..=MAX_ATTACHMENTS).contains(&images.max_images) {
+                problems.push(format!(
+                    "images.max_images must be between 1 and {}",
+                    MAX_ATTACHMENTS
+                ));
+            }
+        }
+        problems
+    }
+}
+
+impl Route {
+    pub fn channel_options(&self) -> ChannelOptions {
+        ChannelOptions {
+            kind: self.announcement.then_some(GUILD_ANNOUNCEMENT),
+            parent_id: self.category.clone(),
+            topic: self.topic.clone(),
+        }
+    }
+
+    /// The sink the items of this route are posted to, if it is not Discord.
+    pub fn other_sink(&self) -> Option<&str> {
+        self.sink.as_deref().filter(|s| *s != DISCORD_SINK)
+    }
+
+    pub fn matches_source(&self, source: &str) -> bool {
+        self.sources.is_empty() || self.sources.iter().any(|s| s == source)
+    }
+}
+
+impl Config {
+    pub fn route_for(&self, item: &SelfossItem) -> Option<&Route> {
+        self.route_for_source(&item.sourcetitle)
+    }
+
+    pub fn route_for_source(&self, source: &str) -> Option<&Route> {
+        self.routes
+            .iter()
+            .find(|route| route.matches_source(source))
+    }
+
+    /// The name of the guild the items of `source` are posted in.
+    pub fn guild_for_source(&self, source: &str) -> &str {
+        self.route_for_source(source)
+            .and_then(|r| r.guild.as_deref())
+            .unwrap_or(&self.default_guild)
+    }
+
+    /// Whether items of `source` are posted through this config, and not through the one of
+    /// another guild.
+    pub fn handles_source(&self, source: &str) -> bool {
+        self.guild_for_source(source) == self.guild
+    }
+
+    /// The name of the channel the items of `source` are posted in.
+    pub fn channel_name(&self, source: &str) -> String {
+        match self
+            .route_for_source(source)
+            .and_then(|r| r.channel.clone())
+        {
+            Some(channel) => channel,
+            None => channel_name_for_source(source),
+        }
+    }
+
+    pub fn channel_options_for(&self, item: &SelfossItem) -> ChannelOptions {
+        self.route_for(item)
+            .map(Route::channel_options)
+            .unwrap_or_default()
+    }
+
+    /// Returns the global filters followed by the filters of the item's route.
+    pub fn filters_for<'a>(&'a self, item: &SelfossItem) -> impl Iterator<Item = &'a Filter> {
+        let route_filters = self.route_for(item).map(|r| r.filters.iter());
+        self.filters
+            .iter()
+            .chain(route_filters.into_iter().flatten())
+    }
+
+    /// The top-level mentions, followed by those of the route of `item`.
+    pub fn mentions_for<'a>(&'a self, item: &SelfossItem) -> impl Iterator<Item = &'a Mention> {
+        let route_mentions = self.route_for(item).map(|r| r.mentions.iter());
+        self.mentions
+            .iter()
+            .chain(route_mentions.into_iter().flatten())
+    }
+}
+
+pub fn read_settings(path: &str) -> Result<Settings, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("Could not read config file {:?}: {}", path, e))?;
+    toml::from_str(&contents).map_err(|e| format!("Invalid config file {:?}: {}", path, e))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, sync::Arc};
+
+    use crate::{
+        config::{ChannelDeletedAction, Config, DigestSchedule, Settings, SinkKind},
+        test::{get_mock_item, start_server},
+    };
+    use chrono::NaiveTime;
+    use chrono_tz::Tz;
+
+    #[test]
+    fn test_parse_routes() {
+        let settings: Settings = toml::from_str(
+            r#"
+            state_path = "state.json"
+
+            on_channel_deleted = "unsubscribe"
+
+            [[routes]]
+            name = "news"
+            sources = ["my_channel"]
+            category = "42"
+
+            [routes.digest]
+            schedule = "daily"
+            at = "08:30:00"
+            timezone = "Europe/Amsterdam"
+            "#,
+        )
+        .unwrap();
+
+        let route = &settings.routes[0];
+        let digest = route.digest.as_ref().unwrap();
+        assert_eq!(settings.state_path.as_deref(), Some("state.json"));
+        assert_eq!(digest.schedule, DigestSchedule::Daily);
+        assert_eq!(digest.at, NaiveTime::from_hms_opt(8, 30, 0));
+        assert_eq!(digest.timezone, Tz::Europe__Amsterdam);
+        assert!(route.matches_source(&get_mock_item().sourcetitle));
+        assert_eq!(route.channel_options().parent_id.as_deref(), Some("42"));
+        assert_eq!(
+            settings.on_channel_deleted,
+            ChannelDeletedAction::Unsubscribe
+        );
+    }
+
+    #[test]
+    fn test_redact_secrets() {
+        let (_server, config) = start_server();
+        let error = "error sending request for url (http://selfoss/mark/1?password=test+password)";
+
+        assert_eq!(
+            config.redact("Bot test token and test password"),
+            "Bot [redacted] and [redacted]"
+        );
+        assert!(!format!("{:?}", config).contains("test token"));
+        assert_eq!(
+            config.redact(error),
+            "error sending request for url (http://selfoss/mark/1?password=[redacted])"
+        );
+    }
+
+    #[test]
+    fn test_redact_sink_secrets() {
+        let (_server, mut config) = start_server();
+        env::set_var(
+            "TEST_REDACT_WEBHOOK",
+            "https://hooks.example.com/T00/B00/secret",
+        );
+        config.sinks = toml::from_str::<Settings>(
+            r#"
+            [[sinks]]
+            name = "team"
+            type = "mattermost"
+            webhook_env = "TEST_REDACT_WEBHOOK"
+            "#,
+        )
+        .unwrap()
+        .sinks;
+
+        assert_eq!(
+            config
+                .redact("error sending request for url (https://hooks.example.com/T00/B00/secret)"),
+            "error sending request for url ([redacted])"
+        );
+    }
+
+    #[test]
+    fn test_validate_routes() {
+        let settings: Settings = toml::from_str(
+            r#"
+            [[routes]]
+            name = "news"
+            sources = ["my_channel"]
+
+            [[routes]]
+            name = "everything"
+
+            [[routes]]
+            name = "news"
+            sources = ["other_channel"]
+            sink = "slack"
+
+            [[sinks]]
+            name = "team"
+            type = "slack"
+            webhook_env = "SLACK_WEBHOOK_URL"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            settings.sinks[0].kind,
+            SinkKind::Slack {
+                base_url: String::from("https://slack.com/api"),
+                token_env: String::from("SLACK_TOKEN"),
+                webhook_env: Some(String::from("SLACK_WEBHOOK_URL")),
+            }
+        );
+        assert_eq!(
+            settings.validate(),
+            vec![
+                String::from(r#"Route "news" is defined more than once"#),
+                String::from(
+                    r#"Route "news" is never used, route "everything" before it matches all sources"#
+                ),
+                String::from(r#"Route "news" uses unknown sink "slack""#),
+            ]
+        );
+        assert!(Settings::default().validate().is_empty());
+    }
+
+    #[test]
+    fn test_validate_mentions() {
+        let settings: Settings = toml::from_str(
+            r#"
+            [[routes]]
+            name = "news"
+
+            [[routes.mentions]]
+            keyword = "release"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            settings.validate(),
+            vec![String::from("A mention has neither a role nor a user")]
+        );
+    }
+
+    #[test]
+    fn test_bridges() {
+        env::set_var("TEST_BRIDGES_PASSWORD", "shared password");
+        env::set_var("TEST_BRIDGES_TOKEN", "team token");
+        let settings: Settings = toml::from_str(
+            r#"
+            state_path = "/var/lib/selfoss-discord/state.json"
+
+            [[selfoss]]
+            name = "shared"
+            base_url = "https://selfoss.example.com"
+            username = "bot"
+            password_env = "TEST_BRIDGES_PASSWORD"
+            guild = "community"
+            type = "miniflux"
+
+            [[guilds]]
+            name = "community"
+            server_id = "1"
+            token_env = "TEST_BRIDGES_TOKEN"
+
+            [[guilds]]
+            name = "team"
+            server_id = "2"
+            token_env = "TEST_BRIDGES_TOKEN"
+
+            [[routes]]
+            name = "team-news"
+            sources = ["my_channel"]
+            guild = "team"
+            "#,
+        )
+        .unwrap();
+
+        let bridges = Config::bridges(&settings).unwrap();
+        let names: Vec<&str> = bridges.iter().map(|b| b.name.as_str()).collect();
+        assert_eq!(names, vec!["shared/community", "shared/team"]);
+        assert_eq!(
+            bridges[1].state_path,
+            "/var/lib/selfoss-discord/state.shared.team.json"
+        );
+        assert_eq!(bridges[1].discord_server_id, "2");
+        assert_eq!(bridges[1].selfoss_password, "shared password");
+        assert_eq!(bridges[1].source().name(), "Miniflux");
+        assert!(Arc::ptr_eq(
+            bridges[0].source.as_ref().unwrap(),
+            bridges[1].source.as_ref().unwrap()
+        ));
+        assert!(bridges[1].handles_source("my_channel"));
+        assert!(!bridges[0].handles_source("my_channel"));
+        assert!(bridges[0].handles_source("another_channel"));
+
+        // Both bridges count towards the health, a post failing in one is not hidden by the other.
+        bridges[0].metrics.record_discord_post(false);
+        bridges[1].metrics.record_discord_post(true);
+        let health = bridges[0].metrics.health();
+        assert_eq!(health["shared/community"].discord_ok, Some(false));
+        assert_eq!(health["shared/team"].discord_ok, Some(true));
+    }
+
+    #[test]
+    fn test_feeds_without_selfoss() {
+        env::set_var("TEST_FEEDS_TOKEN", "token");
+        let settings: Settings = toml::from_str(
+            r#"
+            [[guilds]]
+            name = "default"
+            server_id = "1"
+            token_env = "TEST_FEEDS_TOKEN"
+
+            [[feeds]]
+            url = "https://example.com/rss.xml"
+            tags = ["news"]
+            "#,
+        )
+        .unwrap();
+
+        let bridges = Config::bridges(&settings).unwrap();
+        let names: Vec<&str> = bridges.iter().map(|b| b.name.as_str()).collect();
+        assert_eq!(names, vec!["feeds/default"]);
+        assert_eq!(bridges[0].state_path, "state.feeds.default.json");
+        assert_eq!(bridges[0].source().name(), "Feeds");
+    }
 }
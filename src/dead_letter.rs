@@ -0,0 +1,119 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{selfoss::models::SelfossItem, state::State};
+
+/// Delay before the first retry, doubled for every further failed attempt.
+const BASE_BACKOFF_MINUTES: i64 = 5;
+const MAX_BACKOFF_MINUTES: i64 = 24 * 60;
+
+/// An item that could not be delivered, kept until a retry succeeds or it is discarded.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct DeadLetter {
+    pub item: SelfossItem,
+    pub error: String,
+    pub attempts: u32,
+    pub first_failed_at: DateTime<Utc>,
+    pub next_attempt_at: DateTime<Utc>,
+    /// Whether the item is retried automatically, or only through `failed retry`.
+    #[serde(default = "default_retryable")]
+    pub retryable: bool,
+    /// Whether the item was posted and only marking it as read failed, which is all that is left
+    /// to retry.
+    #[serde(default)]
+    pub posted: bool,
+}
+
+fn default_retryable() -> bool {
+    true
+}
+
+fn backoff(attempts: u32) -> Duration {
+    let minutes = BASE_BACKOFF_MINUTES.saturating_mul(1 << attempts.saturating_sub(1).min(16));
+    Duration::minutes(minutes.min(MAX_BACKOFF_MINUTES))
+}
+
+impl State {
+    pub fn dead_letter(&self, item_id: u64) -> Option<&DeadLetter> {
+        self.dead_letters.iter().find(|d| d.item.id == item_id)
+    }
+
+    /// Whether a previously failed item has to wait for its backoff to pass or a manual retry.
+    pub fn is_deferred(&self, item_id: u64, now: DateTime<Utc>) -> bool {
+        self.dead_letter(item_id)
+            .is_some_and(|d| !d.retryable || d.next_attempt_at > now)
+    }
+
+    pub fn record_failure(
+        &mut self,
+        item: &SelfossItem,
+        error: String,
+        retryable: bool,
+        posted: bool,
+        now: DateTime<Utc>,
+    ) {
+        match self.dead_letters.iter_mut().find(|d| d.item.id == item.id) {
+            Some(dead_letter) => {
+                dead_letter.attempts += 1;
+                dead_letter.error = error;
+                dead_letter.next_attempt_at = now + backoff(dead_letter.attempts);
+                dead_letter.retryable = retryable;
+                dead_letter.posted = posted;
+            }
+            None => self.dead_letters.push(DeadLetter {
+                item: item.clone(),
+                error,
+                attempts: 1,
+                first_failed_at: now,
+                next_attempt_at: now + backoff(1),
+                retryable,
+                posted,
+            }),
+        }
+    }
+
+    pub fn remove_dead_letter(&mut self, item_id: u64) -> Option<DeadLetter> {
+        let index = self
+            .dead_letters
+            .iter()
+            .position(|d| d.item.id == item_id)?;
+        Some(self.dead_letters.remove(index))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Duration;
+
+    use crate::{dead_letter::backoff, state::State, test::get_mock_item};
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(1), Duration::minutes(5));
+        assert_eq!(backoff(3), Duration::minutes(20));
+        assert_eq!(backoff(100), Duration::hours(24));
+    }
+
+    #[test]
+    fn test_record_failure() {
+        let mut state = State::default();
+        let item = get_mock_item();
+        let now = item.datetime;
+
+        state.record_failure(&item, String::from("first"), true, false, now);
+        state.record_failure(&item, String::from("second"), true, true, now);
+
+        let dead_letter = state.dead_letter(item.id).unwrap();
+        assert_eq!(dead_letter.attempts, 2);
+        assert_eq!(dead_letter.error, "second");
+        assert!(dead_letter.posted);
+        assert!(state.is_deferred(item.id, now + Duration::minutes(9)));
+        assert!(!state.is_deferred(item.id, now + Duration::minutes(10)));
+
+        state.record_failure(&item, String::from("third"), false, true, now);
+        assert!(state.is_deferred(item.id, now + Duration::days(10)));
+
+        assert!(state.remove_dead_letter(item.id).is_some());
+        assert!(state.dead_letters.is_empty());
+    }
+}
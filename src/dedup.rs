@@ -0,0 +1,226 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, Duration, Utc};
+use reqwest::Url;
+
+use crate::{
+    config::DedupConfig,
+    selfoss::models::SelfossItem,
+    state::PostedItem,
+    utils::{truncate, MAX_MESSAGE_LENGTH},
+};
+
+/// Query parameters that only track where a visitor came from.
+const TRACKING_PARAMS: [&str; 4] = ["fbclid", "gclid", "mc_cid", "mc_eid"];
+
+/// Normalizes a link so that the same article shared through different feeds compares equal.
+pub fn canonicalize_link(link: &str) -> String {
+    let mut url = match Url::parse(link.trim()) {
+        Ok(url) => url,
+        Err(_) => return link.trim().to_lowercase(),
+    };
+
+    let query: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(key, _)| !key.starts_with("utm_") && !TRACKING_PARAMS.contains(&key.as_ref()))
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect();
+    url.set_fragment(None);
+    url.set_query(None);
+    if !query.is_empty() {
+        url.query_pairs_mut().extend_pairs(query);
+    }
+
+    let host = url.host_str().unwrap_or_default();
+    let host = host.strip_prefix("www.").unwrap_or(host);
+    let path = url.path().trim_end_matches('/');
+    match url.query() {
+        Some(query) => format!("{}{}?{}", host, path, query),
+        None => format!("{}{}", host, path),
+    }
+}
+
+/// Lowercases a title and collapses everything but letters and digits into single spaces.
+pub fn normalize_title(title: &str) -> String {
+    title
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn bigrams(s: &str) -> HashSet<(char, char)> {
+    let chars: Vec<char> = s.chars().collect();
+    chars.windows(2).map(|w| (w[0], w[1])).collect()
+}
+
+/// Sørensen–Dice coefficient of the character bigrams of two normalized titles.
+pub fn title_similarity(a: &str, b: &str) -> f64 {
+    if a == b {
+        return 1.0;
+    }
+    let (a, b) = (bigrams(a), bigrams(b));
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    2.0 * a.intersection(&b).count() as f64 / (a.len() + b.len()) as f64
+}
+
+/// Finds a message posted within the configured window that `item` duplicates. Similar titles
+/// only count across feeds, as a single feed often posts items with similar titles, such as patch
+/// notes of consecutive versions.
+pub fn find_original<'a>(
+    dedup: &DedupConfig,
+    posted: &'a mut [PostedItem],
+    item: &SelfossItem,
+    now: DateTime<Utc>,
+) -> Option<&'a mut PostedItem> {
+    let link = canonicalize_link(&item.link);
+    let title = normalize_title(&item.title);
+    let window_start = now - Duration::hours(dedup.window_hours);
+
+    posted.iter_mut().find(|p| {
+        p.posted_at >= window_start
+            && ((!link.is_empty() && p.link == link)
+                || (!title.is_empty()
+                    && p.sourcetitle != item.sourcetitle
+                    && title_similarity(&p.title, &title) >= dedup.title_similarity))
+    })
+}
+
+impl PostedItem {
+    pub fn new(
+        item: &SelfossItem,
+        channel_id: &str,
+        message_id: &str,
+        content: &str,
+        now: DateTime<Utc>,
+    ) -> Self {
+        PostedItem {
+            link: canonicalize_link(&item.link),
+            title: normalize_title(&item.title),
+            sourcetitle: item.sourcetitle.clone(),
+            channel_id: channel_id.to_string(),
+            message_id: message_id.to_string(),
+            content: content.to_string(),
+            posted_at: now,
+            also_in: vec![],
+            sink: None,
+            item: None,
+        }
+    }
+
+    /// Records an item that was posted through `sink` instead of Discord.
+    pub fn for_sink(
+        item: &SelfossItem,
+        sink: &str,
+        destination_id: &str,
+        message_id: &str,
+        now: DateTime<Utc>,
+    ) -> Self {
+        PostedItem {
+            sink: Some(sink.to_string()),
+            item: Some(item.clone()),
+            ..PostedItem::new(item, destination_id, message_id, &item.content, now)
+        }
+    }
+
+    /// The original item with its content replaced by the annotated content.
+    pub fn annotated_item(&self) -> Option<SelfossItem> {
+        self.item.clone().map(|item| SelfossItem {
+            content: self.annotated_content(),
+            ..item
+        })
+    }
+
+    /// The original message content followed by the sources of its duplicates.
+    pub fn annotated_content(&self) -> String {
+        let suffix = format!("\n\nalso in: {}", self.also_in.join(", "));
+        let max_chars = MAX_MESSAGE_LENGTH.saturating_sub(suffix.chars().count());
+        format!("{}{}", truncate(&self.content, max_chars), suffix)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Duration;
+
+    use crate::{
+        config::{DedupAction, DedupConfig},
+        dedup::{canonicalize_link, find_original, normalize_title, title_similarity},
+        selfoss::models::SelfossItem,
+        state::PostedItem,
+        test::get_mock_item,
+    };
+
+    #[test]
+    fn test_canonicalize_link() {
+        assert_eq!(
+            canonicalize_link("http://WWW.Example.com/news/item/?utm_source=rss&id=4#comments"),
+            canonicalize_link("https://example.com/news/item?id=4&utm_medium=feed"),
+        );
+        assert_eq!(
+            canonicalize_link("https://example.com/news/item/?id=4"),
+            "example.com/news/item?id=4"
+        );
+        assert_eq!(canonicalize_link("My link"), "my link");
+    }
+
+    #[test]
+    fn test_title_similarity() {
+        let a = normalize_title("Microsoft Flight Simulator: World Update XV released!");
+        let b = normalize_title("Microsoft Flight Simulator World Update XV Released");
+        let c = normalize_title("Sim Update 14 beta now available");
+
+        assert_eq!(title_similarity(&a, &b), 1.0);
+        assert!(title_similarity(&a, &c) < 0.5);
+    }
+
+    #[test]
+    fn test_find_original() {
+        let dedup = DedupConfig {
+            window_hours: 24,
+            title_similarity: 0.9,
+            action: DedupAction::Drop,
+        };
+        let item = get_mock_item();
+        let mut posted = vec![PostedItem {
+            link: String::from("https://example.com/other"),
+            title: normalize_title("my title"),
+            sourcetitle: String::from("another_channel"),
+            channel_id: String::from("another_channel_id"),
+            message_id: String::from("4242"),
+            content: String::from("My content"),
+            posted_at: item.datetime,
+            also_in: vec![],
+            sink: None,
+            item: None,
+        }];
+
+        let now = item.datetime + Duration::hours(1);
+        assert!(find_original(&dedup, &mut posted, &item, now).is_some());
+
+        let now = item.datetime + Duration::hours(25);
+        assert!(find_original(&dedup, &mut posted, &item, now).is_none());
+
+        // Items of the same feed with similar titles are not duplicates, unless their link is.
+        let now = item.datetime + Duration::hours(1);
+        posted[0].sourcetitle = item.sourcetitle.clone();
+        posted[0].title = normalize_title("Patch notes 1.2.3");
+        let next_version = SelfossItem {
+            title: String::from("Patch notes 1.2.4"),
+            ..item.clone()
+        };
+        assert!(title_similarity(&posted[0].title, &normalize_title(&next_version.title)) >= 0.9);
+        assert!(find_original(&dedup, &mut posted, &next_version, now).is_none());
+        posted[0].link = canonicalize_link(&item.link);
+        assert!(find_original(&dedup, &mut posted, &next_version, now).is_some());
+
+        posted[0].also_in = vec![String::from("my_channel"), String::from("third")];
+        assert_eq!(
+            posted[0].annotated_content(),
+            "My content\n\nalso in: my_channel, third"
+        );
+    }
+}
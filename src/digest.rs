@@ -0,0 +1,160 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Duration, NaiveTime, TimeZone, Timelike, Utc};
+
+use crate::{
+    config::{DigestConfig, DigestSchedule, SanitizeConfig},
+    sanitize::{sanitize, sanitize_link_text},
+    selfoss::models::SelfossItem,
+    utils::{truncate, MAX_MESSAGE_LENGTH},
+};
+
+/// Returns the most recent moment at or before `now` at which a digest is scheduled.
+fn last_scheduled(digest: &DigestConfig, now: DateTime<Utc>) -> DateTime<Utc> {
+    let local = now.with_timezone(&digest.timezone);
+    let candidate = match digest.schedule {
+        DigestSchedule::Hourly => local.date_naive().and_hms_opt(local.hour(), 0, 0),
+        DigestSchedule::Daily => Some(
+            local
+                .date_naive()
+                .and_time(digest.at.unwrap_or(NaiveTime::MIN)),
+        ),
+    }
+    .unwrap();
+
+    // Daylight saving time transitions may skip the scheduled local time entirely.
+    let scheduled = digest
+        .timezone
+        .from_local_datetime(&candidate)
+        .earliest()
+        .or_else(|| {
+            digest
+                .timezone
+                .from_local_datetime(&(candidate + Duration::hours(1)))
+                .earliest()
+        })
+        .unwrap()
+        .with_timezone(&Utc);
+
+    match digest.schedule {
+        DigestSchedule::Daily if scheduled > now => scheduled - Duration::days(1),
+        _ => scheduled,
+    }
+}
+
+pub fn is_due(digest: &DigestConfig, last_sent: Option<DateTime<Utc>>, now: DateTime<Utc>) -> bool {
+    last_sent.is_none_or(|last_sent| last_sent < last_scheduled(digest, now))
+}
+
+/// Formats a digest as titles and links grouped by source, split over as many messages as needed.
+pub fn format_digest(config: &SanitizeConfig, items: &[SelfossItem]) -> Vec<String> {
+    let mut sources: BTreeMap<&str, Vec<&SelfossItem>> = BTreeMap::new();
+    for item in items {
+        sources.entry(&item.sourcetitle).or_default().push(item);
+    }
+
+    let mut lines = vec![];
+    for (source, items) in sources {
+        lines.push(format!("**{}**", sanitize(config, source)));
+        for item in items {
+            match item.link.is_empty() {
+                true => lines.push(format!("- {}", sanitize(config, &item.title))),
+                false => lines.push(format!(
+                    "- [{}]({})",
+                    sanitize_link_text(config, &item.title),
+                    item.link
+                )),
+            }
+        }
+    }
+
+    let mut messages: Vec<String> = vec![];
+    let mut message = String::new();
+    for line in lines {
+        let line = truncate(&line, MAX_MESSAGE_LENGTH);
+        if !message.is_empty()
+            && message.chars().count() + line.chars().count() >= MAX_MESSAGE_LENGTH
+        {
+            messages.push(message);
+            message = String::new();
+        }
+        if !message.is_empty() {
+            message.push('\n');
+        }
+        message.push_str(line);
+    }
+    if !message.is_empty() {
+        messages.push(message);
+    }
+    messages
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, NaiveTime, Utc};
+    use chrono_tz::Tz;
+
+    use crate::{
+        config::{DigestConfig, DigestSchedule, SanitizeConfig},
+        digest::{format_digest, is_due},
+        test::get_mock_item,
+    };
+
+    fn parse(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().into()
+    }
+
+    #[test]
+    fn test_daily_digest_due() {
+        let digest = DigestConfig {
+            schedule: DigestSchedule::Daily,
+            at: NaiveTime::from_hms_opt(8, 0, 0),
+            timezone: Tz::Europe__Amsterdam,
+        };
+        let last_sent = Some(parse("2023-12-15T07:00:00Z"));
+
+        assert!(!is_due(&digest, last_sent, parse("2023-12-16T06:59:00Z")));
+        assert!(is_due(&digest, last_sent, parse("2023-12-16T07:00:00Z")));
+        assert!(is_due(&digest, None, parse("2023-12-16T06:59:00Z")));
+    }
+
+    #[test]
+    fn test_hourly_digest_due() {
+        let digest = DigestConfig {
+            schedule: DigestSchedule::Hourly,
+            at: None,
+            timezone: Tz::UTC,
+        };
+        let last_sent = Some(parse("2023-12-15T17:00:10Z"));
+
+        assert!(!is_due(&digest, last_sent, parse("2023-12-15T17:59:59Z")));
+        assert!(is_due(&digest, last_sent, parse("2023-12-15T18:00:00Z")));
+    }
+
+    #[test]
+    fn test_format_digest() {
+        let mut other = get_mock_item();
+        other.sourcetitle = String::from("another_channel");
+        other.link = String::new();
+
+        let messages = format_digest(&SanitizeConfig::default(), &[get_mock_item(), other]);
+        assert_eq!(
+            messages,
+            vec!["**another_channel**\n- My title\n**my_channel**\n- [My title](My link)"]
+        );
+
+        let mut adversarial = get_mock_item();
+        adversarial.title = String::from("@everyone](https://phishing.example) [Free");
+        let messages = format_digest(&SanitizeConfig::default(), &[adversarial]);
+        assert_eq!(
+            messages,
+            vec![
+                "**my_channel**\n- [@\u{200B}everyone\\](https://phishing.example) \\[Free](My link)"
+            ]
+        );
+
+        let messages = format_digest(&SanitizeConfig::default(), &vec![get_mock_item(); 200]);
+        assert_eq!(messages.len(), 3);
+        assert!(messages.iter().all(|m| m.chars().count() <= 2000));
+    }
+}
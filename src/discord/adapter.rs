@@ -1,20 +1,76 @@
 use crate::config::Config;
-use reqwest::{header::AUTHORIZATION, Method};
+use reqwest::{
+    header::{AUTHORIZATION, CONTENT_TYPE},
+    Method, StatusCode,
+};
 use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
 use reqwest_retry::policies::ExponentialBackoff;
 use reqwest_retry::RetryTransientMiddleware;
 use serde::de::DeserializeOwned;
-use std::{collections::HashMap, fmt::Debug};
+use serde_json::{json, Value};
+use std::fmt::Debug;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use super::errors::RequestError;
+use super::instrumentation::InstrumentationMiddleware;
 use super::middleware::RetryAfterMiddleware;
-use super::models::{DiscordChannel, DiscordMessage};
+use super::models::{
+    AllowedMentions, Attachment, ChannelOptions, ChannelUpdate, DiscordChannel, DiscordMessage,
+    DiscordRole,
+};
+
+/// Body of a request to Discord.
+enum Body {
+    Json(Value),
+    /// A `multipart/form-data` body and its boundary. It is built in memory instead of with
+    /// `reqwest::multipart`, so that the request can be cloned for retries.
+    Multipart(String, Vec<u8>),
+}
+
+/// Encodes a message with `files` as `payload_json` and `files[n]` parts.
+fn multipart_body(payload: &Value, files: &[Attachment]) -> Body {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let boundary = format!("selfoss-discord-{:x}", nanos);
+    let mut body = format!(
+        "--{}\r\nContent-Disposition: form-data; name=\"payload_json\"\r\n\
+         Content-Type: application/json\r\n\r\n{}\r\n",
+        boundary, payload
+    )
+    .into_bytes();
+    for (i, file) in files.iter().enumerate() {
+        let header = format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"files[{}]\"; filename=\"{}\"\r\n\
+             Content-Type: {}\r\n\r\n",
+            boundary, i, file.filename, file.content_type
+        );
+        body.extend(header.into_bytes());
+        body.extend(&file.data);
+        body.extend(b"\r\n");
+    }
+    body.extend(format!("--{}--\r\n", boundary).into_bytes());
+    Body::Multipart(boundary, body)
+}
 
 async fn discord_request<D>(
     config: Config,
     method: Method,
     endpoint: &str,
-    json: Option<HashMap<&str, &str>>,
+    json: Option<Value>,
+) -> Result<D, RequestError>
+where
+    D: DeserializeOwned + Debug,
+{
+    send_discord_request(config, method, endpoint, json.map(Body::Json)).await
+}
+
+async fn send_discord_request<D>(
+    config: Config,
+    method: Method,
+    endpoint: &str,
+    body: Option<Body>,
 ) -> Result<D, RequestError>
 where
     D: DeserializeOwned + Debug,
@@ -25,23 +81,36 @@ where
     let client: ClientWithMiddleware = ClientBuilder::new(reqwest::Client::new())
         .with(RetryAfterMiddleware::new())
         .with(RetryTransientMiddleware::new_with_policy(retry_policy))
+        .with(InstrumentationMiddleware::new(config.metrics.clone()))
         .build();
 
     let base_request = client.request(method, endpoint);
 
-    let request = match json {
-        Some(x) => base_request.json(&x),
+    let request = match body {
+        Some(Body::Json(x)) => base_request.json(&x),
+        Some(Body::Multipart(boundary, body)) => base_request
+            .header(
+                CONTENT_TYPE,
+                format!("multipart/form-data; boundary={}", boundary),
+            )
+            .body(body),
         None => base_request,
     };
 
-    request
+    let response = request
         .header(AUTHORIZATION, format!("Bot {}", config.discord_token))
         .send()
-        .await?
-        .error_for_status()?
-        .json::<D>()
-        .await
-        .map_err(RequestError::Reqwest)
+        .await?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await?;
+        return Err(RequestError::from_discord_response(status, &body));
+    }
+    if status == StatusCode::NO_CONTENT {
+        return Ok(serde_json::from_value(Value::Null)?);
+    }
+    response.json::<D>().await.map_err(RequestError::Reqwest)
 }
 
 pub async fn get_channels(config: &Config) -> Result<Vec<DiscordChannel>, RequestError> {
@@ -57,9 +126,18 @@ pub async fn get_channels(config: &Config) -> Result<Vec<DiscordChannel>, Reques
 pub async fn create_channel(
     config: &Config,
     channel_name: &str,
+    options: &ChannelOptions,
 ) -> Result<DiscordChannel, RequestError> {
-    let mut payload = HashMap::new();
-    payload.insert("name", channel_name);
+    let mut payload = json!({"name": channel_name});
+    if let Some(kind) = options.kind {
+        payload["type"] = json!(kind);
+    }
+    if let Some(parent_id) = &options.parent_id {
+        payload["parent_id"] = json!(parent_id);
+    }
+    if let Some(topic) = &options.topic {
+        payload["topic"] = json!(topic);
+    }
     discord_request::<DiscordChannel>(
         config.clone(),
         Method::POST,
@@ -69,19 +147,149 @@ pub async fn create_channel(
     .await
 }
 
+pub async fn modify_channel(
+    config: &Config,
+    channel_id: &str,
+    update: &ChannelUpdate,
+) -> Result<DiscordChannel, RequestError> {
+    discord_request::<DiscordChannel>(
+        config.clone(),
+        Method::PATCH,
+        format!("channels/{}", channel_id).as_str(),
+        Some(serde_json::to_value(update)?),
+    )
+    .await
+}
+
+pub async fn get_roles(config: &Config) -> Result<Vec<DiscordRole>, RequestError> {
+    discord_request::<Vec<DiscordRole>>(
+        config.clone(),
+        Method::GET,
+        format!("guilds/{}/roles", config.discord_server_id).as_str(),
+        None,
+    )
+    .await
+}
+
+pub async fn create_role(
+    config: &Config,
+    name: &str,
+    color: u32,
+) -> Result<DiscordRole, RequestError> {
+    discord_request::<DiscordRole>(
+        config.clone(),
+        Method::POST,
+        format!("guilds/{}/roles", config.discord_server_id).as_str(),
+        Some(json!({"name": name, "color": color})),
+    )
+    .await
+}
+
+pub async fn modify_role(
+    config: &Config,
+    role_id: &str,
+    color: u32,
+) -> Result<DiscordRole, RequestError> {
+    discord_request::<DiscordRole>(
+        config.clone(),
+        Method::PATCH,
+        format!("guilds/{}/roles/{}", config.discord_server_id, role_id).as_str(),
+        Some(json!({"color": color})),
+    )
+    .await
+}
+
+/// Posts a message that may only ping the roles and users in `mentions`.
 pub async fn post_message(
     config: &Config,
     channel_id: &str,
     content: &str,
+    mentions: &AllowedMentions,
 ) -> Result<DiscordMessage, RequestError> {
-    let mut payload = HashMap::new();
-    payload.insert("content", content);
-    discord_request::<DiscordMessage>(
+    let payload = json!({"content": content, "allowed_mentions": mentions});
+    let result = discord_request::<DiscordMessage>(
         config.clone(),
         Method::POST,
         format!("channels/{}/messages", channel_id).as_str(),
         Some(payload),
     )
+    .await;
+    config.metrics.record_discord_post(result.is_ok());
+    result
+}
+
+/// Posts a message with `files` attached, which Discord shows as a gallery if they are images.
+pub async fn post_message_with_files(
+    config: &Config,
+    channel_id: &str,
+    content: &str,
+    mentions: &AllowedMentions,
+    files: &[Attachment],
+) -> Result<DiscordMessage, RequestError> {
+    let attachments: Vec<Value> = files
+        .iter()
+        .enumerate()
+        .map(|(i, file)| json!({"id": i, "filename": file.filename}))
+        .collect();
+    let payload = json!({
+        "content": content,
+        "allowed_mentions": mentions,
+        "attachments": attachments,
+    });
+    let result = send_discord_request::<DiscordMessage>(
+        config.clone(),
+        Method::POST,
+        format!("channels/{}/messages", channel_id).as_str(),
+        Some(multipart_body(&payload, files)),
+    )
+    .await;
+    config.metrics.record_discord_post(result.is_ok());
+    result
+}
+
+pub async fn edit_message(
+    config: &Config,
+    channel_id: &str,
+    message_id: &str,
+    content: &str,
+) -> Result<DiscordMessage, RequestError> {
+    // The edited content may not ping anyone either.
+    let payload = json!({"content": content, "allowed_mentions": AllowedMentions::default()});
+    discord_request::<DiscordMessage>(
+        config.clone(),
+        Method::PATCH,
+        format!("channels/{}/messages/{}", channel_id, message_id).as_str(),
+        Some(payload),
+    )
+    .await
+}
+
+/// Publishes a message in an announcement channel to the channels that follow it.
+pub async fn crosspost_message(
+    config: &Config,
+    channel_id: &str,
+    message_id: &str,
+) -> Result<DiscordMessage, RequestError> {
+    discord_request::<DiscordMessage>(
+        config.clone(),
+        Method::POST,
+        format!("channels/{}/messages/{}/crosspost", channel_id, message_id).as_str(),
+        None,
+    )
+    .await
+}
+
+pub async fn delete_message(
+    config: &Config,
+    channel_id: &str,
+    message_id: &str,
+) -> Result<(), RequestError> {
+    discord_request::<()>(
+        config.clone(),
+        Method::DELETE,
+        format!("channels/{}/messages/{}", channel_id, message_id).as_str(),
+        None,
+    )
     .await
 }
 
@@ -91,20 +299,24 @@ mod test {
 
     use crate::{
         discord::{
-            adapter::{create_channel, get_channels},
-            errors::RequestError,
-            models::DiscordChannel,
+            adapter::{create_channel, edit_message, get_channels},
+            models::{ChannelOptions, DiscordChannel},
         },
         send_messages,
+        state::State,
+        stats::RunStats,
         test::{get_mock_item, start_server},
     };
-    use httpmock::Method::{GET, POST};
-    use reqwest::StatusCode;
+    use httpmock::Method::{GET, PATCH, POST};
+    use serde_json::json;
 
     fn get_mock_channel() -> DiscordChannel {
         DiscordChannel {
             name: String::from("my_channel"),
             id: String::from("my_channel_id"),
+            kind: 0,
+            parent_id: None,
+            topic: None,
         }
     }
 
@@ -113,13 +325,20 @@ mod test {
         let (server, config) = start_server();
 
         let get_discord_channels_mock = server.mock(|when, then| {
-            when.method(POST).path("/guilds/123/channels");
+            when.method(POST)
+                .path("/guilds/123/channels")
+                .json_body(json!({"name": "my_channel", "parent_id": "42", "topic": "My topic"}));
             then.status(200)
                 .header("content-type", "application/json")
                 .body_from_file("src/assets/discord_create_channel_mock_response.json");
         });
 
-        let item_list = create_channel(&config, "my_channel").await;
+        let options = ChannelOptions {
+            parent_id: Some(String::from("42")),
+            topic: Some(String::from("My topic")),
+            ..Default::default()
+        };
+        let item_list = create_channel(&config, "my_channel", &options).await;
 
         assert_eq!(item_list.unwrap(), get_mock_channel());
         get_discord_channels_mock.assert_async().await;
@@ -142,6 +361,25 @@ mod test {
         get_discord_channels_mock.assert_async().await;
     }
 
+    #[tokio::test]
+    async fn test_edit_message() {
+        let (server, config) = start_server();
+
+        let edit_message_mock = server.mock(|when, then| {
+            when.method(PATCH)
+                .path("/channels/my_channel_id/messages/4242")
+                .json_body_partial(r#"{"content": "My content"}"#);
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/discord_send_message_mock_response.json");
+        });
+
+        let message = edit_message(&config, "my_channel_id", "4242", "My content").await;
+
+        assert_eq!(message.unwrap().id, "4242");
+        edit_message_mock.assert_async().await;
+    }
+
     #[tokio::test]
     async fn test_send_messages_ratelimited() {
         let (server, config) = start_server();
@@ -157,14 +395,26 @@ mod test {
         let channel_map =
             HashMap::from([(String::from("my_channel"), String::from("my_channel_id"))]);
         let item_list = vec![get_mock_item()];
-        let result = send_messages(&config, item_list, channel_map).await;
+        let mut state = State::default();
+        let mut stats = RunStats::default();
+        send_messages(&config, &mut state, &mut stats, item_list, channel_map)
+            .await
+            .expect("Ratelimited item aborted the run");
 
-        let error = result.expect_err("Did somehow send messages without being ratelimited");
-        match error {
-            RequestError::Reqwest(x) => assert_eq!(x.status(), Some(StatusCode::TOO_MANY_REQUESTS)),
-            _ => panic!("expected Reqwest error"),
-        }
+        // The item is not marked as read but kept for a later retry.
+        assert_eq!(stats.failed, 1);
+        assert_eq!(stats.marked_read, 0);
+        let dead_letter = state
+            .dead_letter(187204)
+            .expect("Did somehow send messages without being ratelimited");
+        assert_eq!(dead_letter.attempts, 1);
+        assert!(dead_letter.error.contains("429 Too Many Requests"));
         // We do three retries with exponential backoff.
         send_message_mock.assert_hits(4);
+        assert!(config
+            .metrics
+            .encode()
+            .contains(r#"selfoss_discord_discord_ratelimited_total{bridge="default"} 4"#));
+        assert_eq!(config.metrics.health()["default"].discord_ok, Some(false));
     }
 }
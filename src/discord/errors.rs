@@ -1,10 +1,145 @@
 use std::fmt;
 
+use reqwest::StatusCode;
+use serde_json::Value;
+
+use crate::{selfoss::errors::SelfossError, sinks::SinkError, sources::SourceError};
+
+/// JSON error codes, see https://discord.com/developers/docs/topics/opcodes-and-status-codes
+const UNKNOWN_CHANNEL: u64 = 10003;
+const MISSING_ACCESS: u64 = 50001;
+const MISSING_PERMISSIONS: u64 = 50013;
+const INVALID_FORM_BODY: u64 = 50035;
+
 #[derive(Debug)]
 pub enum RequestError {
     Reqwest(reqwest::Error),
     ReqwestMiddleware(reqwest_middleware::Error),
     Serde(serde_json::Error),
+    Selfoss(SelfossError),
+    /// Returned by a feed reader other than Selfoss.
+    Source(SourceError),
+    /// The channel does not exist (anymore).
+    UnknownChannel(DiscordError),
+    /// The bot is not allowed to do this in the channel or server.
+    MissingPermissions(DiscordError),
+    /// The payload was rejected, see `DiscordError::field_errors`.
+    InvalidFormBody(DiscordError),
+    /// Still rate limited after retrying.
+    RateLimited(DiscordError),
+    /// The bot token is invalid.
+    Unauthorized(DiscordError),
+    Discord(DiscordError),
+    /// Returned by a sink other than Discord.
+    Sink(SinkError),
+}
+
+/// Error body returned by the Discord API.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscordError {
+    pub status: StatusCode,
+    pub code: u64,
+    pub message: String,
+    pub field_errors: Vec<FieldError>,
+}
+
+/// Error for a single field of an invalid form body, e.g. `embeds.0.description`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldError {
+    pub path: String,
+    pub code: String,
+    pub message: String,
+}
+
+/// Flattens the nested `errors` object of a Discord error into one error per field.
+fn collect_field_errors(path: &str, value: &Value, field_errors: &mut Vec<FieldError>) {
+    let Value::Object(object) = value else {
+        return;
+    };
+    for (key, value) in object {
+        if key == "_errors" {
+            for error in value.as_array().into_iter().flatten() {
+                field_errors.push(FieldError {
+                    path: path.to_string(),
+                    code: error["code"].as_str().unwrap_or_default().to_string(),
+                    message: error["message"].as_str().unwrap_or_default().to_string(),
+                });
+            }
+        } else {
+            let path = match path.is_empty() {
+                true => key.clone(),
+                false => format!("{}.{}", path, key),
+            };
+            collect_field_errors(&path, value, field_errors);
+        }
+    }
+}
+
+impl DiscordError {
+    pub fn parse(status: StatusCode, body: &str) -> Self {
+        let json: Value = serde_json::from_str(body).unwrap_or_default();
+        let mut field_errors = vec![];
+        collect_field_errors("", &json["errors"], &mut field_errors);
+
+        DiscordError {
+            status,
+            code: json["code"].as_u64().unwrap_or_default(),
+            message: json["message"]
+                .as_str()
+                .map(str::to_string)
+                .unwrap_or_else(|| body.to_string()),
+            field_errors,
+        }
+    }
+}
+
+impl fmt::Display for DiscordError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Discord returned {}: {}", self.status, self.message)?;
+        if self.code != 0 {
+            write!(f, " (code {})", self.code)?;
+        }
+        for error in &self.field_errors {
+            write!(f, "; {}: {}", error.path, error.message)?;
+        }
+        Ok(())
+    }
+}
+
+impl RequestError {
+    /// Converts an unsuccessful Discord response into the matching variant.
+    pub fn from_discord_response(status: StatusCode, body: &str) -> Self {
+        let error = DiscordError::parse(status, body);
+        match (status, error.code) {
+            (_, UNKNOWN_CHANNEL) => RequestError::UnknownChannel(error),
+            (_, MISSING_ACCESS | MISSING_PERMISSIONS) => RequestError::MissingPermissions(error),
+            (_, INVALID_FORM_BODY) => RequestError::InvalidFormBody(error),
+            (StatusCode::TOO_MANY_REQUESTS, _) => RequestError::RateLimited(error),
+            (StatusCode::UNAUTHORIZED, _) => RequestError::Unauthorized(error),
+            _ => RequestError::Discord(error),
+        }
+    }
+
+    /// Errors that will fail for every item, so there is no point in continuing the run.
+    pub fn is_fatal(&self) -> bool {
+        match self {
+            RequestError::Unauthorized(_) => true,
+            RequestError::Selfoss(e) => e.is_fatal(),
+            RequestError::Source(e) => e.is_fatal(),
+            _ => false,
+        }
+    }
+
+    /// Errors that may go away by themselves, so the item is retried automatically.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            RequestError::MissingPermissions(_)
+            | RequestError::InvalidFormBody(_)
+            | RequestError::Serde(_) => false,
+            RequestError::Sink(e) => e.is_retryable(),
+            _ => true,
+        }
+    }
 }
 
 impl From<reqwest::Error> for RequestError {
@@ -25,12 +160,83 @@ impl From<serde_json::Error> for RequestError {
     }
 }
 
+impl From<SelfossError> for RequestError {
+    fn from(value: SelfossError) -> Self {
+        RequestError::Selfoss(value)
+    }
+}
+
 impl fmt::Display for RequestError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             RequestError::Reqwest(ref e) => e.fmt(f),
             RequestError::ReqwestMiddleware(ref e) => e.fmt(f),
             RequestError::Serde(ref e) => e.fmt(f),
+            RequestError::Selfoss(ref e) => e.fmt(f),
+            RequestError::Source(ref e) => e.fmt(f),
+            RequestError::Sink(ref e) => e.fmt(f),
+            RequestError::Unauthorized(ref e) => {
+                write!(f, "{}, check DISCORD_TOKEN", e)
+            }
+            RequestError::MissingPermissions(ref e) => {
+                write!(f, "{}, check the permissions of the bot", e)
+            }
+            RequestError::UnknownChannel(ref e)
+            | RequestError::InvalidFormBody(ref e)
+            | RequestError::RateLimited(ref e)
+            | RequestError::Discord(ref e) => e.fmt(f),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use reqwest::StatusCode;
+
+    use crate::discord::errors::{FieldError, RequestError};
+
+    #[test]
+    fn test_parse_invalid_form_body() {
+        let body = r#"{
+            "code": 50035,
+            "message": "Invalid Form Body",
+            "errors": {
+                "content": {
+                    "_errors": [{"code": "BASE_TYPE_MAX_LENGTH", "message": "Must be 2000 or fewer in length."}]
+                }
+            }
+        }"#;
+
+        match RequestError::from_discord_response(StatusCode::BAD_REQUEST, body) {
+            RequestError::InvalidFormBody(e) => assert_eq!(
+                e.field_errors,
+                vec![FieldError {
+                    path: String::from("content"),
+                    code: String::from("BASE_TYPE_MAX_LENGTH"),
+                    message: String::from("Must be 2000 or fewer in length."),
+                }]
+            ),
+            e => panic!("expected InvalidFormBody error, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_parse_error_variants() {
+        let unknown_channel = r#"{"message": "Unknown Channel", "code": 10003}"#;
+        let error = RequestError::from_discord_response(StatusCode::NOT_FOUND, unknown_channel);
+        assert!(matches!(error, RequestError::UnknownChannel(_)));
+        assert_eq!(
+            error.to_string(),
+            "Discord returned 404 Not Found: Unknown Channel (code 10003)"
+        );
+
+        let unauthorized = r#"{"message": "401: Unauthorized", "code": 0}"#;
+        let error = RequestError::from_discord_response(StatusCode::UNAUTHORIZED, unauthorized);
+        assert!(matches!(error, RequestError::Unauthorized(_)));
+        assert!(error.is_fatal());
+
+        let error = RequestError::from_discord_response(StatusCode::BAD_GATEWAY, "bad gateway");
+        assert!(matches!(error, RequestError::Discord(ref e) if e.message == "bad gateway"));
+        assert!(error.is_retryable());
+    }
+}
@@ -0,0 +1,76 @@
+use std::{sync::Arc, time::Instant};
+
+use reqwest::{header::RETRY_AFTER, Request, Response, StatusCode};
+use reqwest_middleware::{Middleware, Next, Result};
+use task_local_extensions::Extensions;
+use tracing::{debug, warn, Instrument};
+
+use crate::metrics::Metrics;
+
+/// Number of times a request has been sent, including retries.
+struct Attempt(u32);
+
+/// Logs and measures every attempt of a request. Must be added after the retry middleware so
+/// that it sees each retry separately.
+pub struct InstrumentationMiddleware {
+    metrics: Arc<Metrics>,
+}
+
+impl InstrumentationMiddleware {
+    pub fn new(metrics: Arc<Metrics>) -> Self {
+        Self { metrics }
+    }
+
+    fn record_ratelimit(&self, res: &Response) {
+        let wait = res
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok());
+        self.metrics.record_ratelimit(wait);
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for InstrumentationMiddleware {
+    async fn handle(
+        &self,
+        req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> Result<Response> {
+        let attempt = match extensions.get_mut::<Attempt>() {
+            Some(attempt) => {
+                attempt.0 += 1;
+                attempt.0
+            }
+            None => {
+                extensions.insert(Attempt(1));
+                1
+            }
+        };
+        let span = tracing::debug_span!(
+            "request",
+            method = %req.method(),
+            path = req.url().path(),
+            attempt
+        );
+
+        let start = Instant::now();
+        let res = next.run(req, extensions).instrument(span.clone()).await;
+        self.metrics
+            .observe_request("discord", start.elapsed().as_secs_f64());
+
+        span.in_scope(|| match &res {
+            Ok(res) if res.status().is_success() => debug!(status = %res.status(), "Request done"),
+            Ok(res) => warn!(status = %res.status(), "Request failed"),
+            Err(e) => warn!(error = %e, "Request failed"),
+        });
+        if let Ok(res) = &res {
+            if res.status() == StatusCode::TOO_MANY_REQUESTS {
+                self.record_ratelimit(res);
+            }
+        }
+        res
+    }
+}
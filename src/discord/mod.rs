@@ -1,4 +1,5 @@
 pub mod adapter;
 pub mod errors;
+mod instrumentation;
 mod middleware;
 pub mod models;
@@ -1,9 +1,83 @@
 use serde::{Deserialize, Serialize};
 
+/// Channel types, see https://discord.com/developers/docs/resources/channel#channel-object-channel-types
+pub const GUILD_CATEGORY: u8 = 4;
+pub const GUILD_ANNOUNCEMENT: u8 = 5;
+
+/// Messages that can be published per announcement channel per hour.
+pub const CROSSPOSTS_PER_HOUR: usize = 10;
+
+/// Number of files that can be attached to a message.
+pub const MAX_ATTACHMENTS: usize = 10;
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct DiscordChannel {
     pub name: String,
     pub id: String,
+    #[serde(rename = "type", default)]
+    pub kind: u8,
+    #[serde(default)]
+    pub parent_id: Option<String>,
+    #[serde(default)]
+    pub topic: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct DiscordRole {
+    pub id: String,
+    pub name: String,
+    /// RGB colour as an integer, 0 for no colour.
+    #[serde(default)]
+    pub color: u32,
+}
+
+/// Settings of a channel that is created for a feed.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ChannelOptions {
+    /// Channel type, a text channel if not set.
+    pub kind: Option<u8>,
+    /// Id of the category the channel is created in.
+    pub parent_id: Option<String>,
+    pub topic: Option<String>,
+}
+
+/// Changes to an existing channel, fields that are `None` are left as they are.
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct ChannelUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+}
+
+/// Who a message may ping, see
+/// https://discord.com/developers/docs/resources/message#allowed-mentions-object
+///
+/// Nothing is parsed from the content, so feed content can never ping `@everyone` or others.
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct AllowedMentions {
+    pub parse: Vec<String>,
+    pub roles: Vec<String>,
+    pub users: Vec<String>,
+}
+
+impl AllowedMentions {
+    /// The mentions of the roles and users, to put in front of the content.
+    pub fn prefix(&self) -> String {
+        let roles = self.roles.iter().map(|id| format!("<@&{}>", id));
+        let users = self.users.iter().map(|id| format!("<@{}>", id));
+        roles.chain(users).collect::<Vec<_>>().join(" ")
+    }
+}
+
+/// A file that is uploaded with a message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attachment {
+    pub filename: String,
+    pub content_type: String,
+    pub data: Vec<u8>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -0,0 +1,37 @@
+//! Errors of a run or command, of which failed requests are only one kind.
+
+use std::fmt;
+
+use crate::discord::errors::RequestError;
+
+#[derive(Debug)]
+pub enum AppError {
+    Request(RequestError),
+    /// The state file could not be read or written.
+    State(String),
+    /// An OPML file could not be read, or the routes could not be written to the settings.
+    Import(String),
+    /// This many unread items are waiting on the first run, and nobody decided what to do with them.
+    Backlog(usize),
+}
+
+impl From<RequestError> for AppError {
+    fn from(value: RequestError) -> Self {
+        AppError::Request(value)
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AppError::Request(e) => e.fmt(f),
+            AppError::State(message) | AppError::Import(message) => f.write_str(message),
+            AppError::Backlog(count) => write!(
+                f,
+                "{} unread items are waiting on the first run, pass --backlog post or \
+                 --backlog mark-read, or set first_run.backlog",
+                count
+            ),
+        }
+    }
+}
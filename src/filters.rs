@@ -0,0 +1,166 @@
+use std::fmt;
+
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+
+use crate::selfoss::models::SelfossItem;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterAction {
+    Include,
+    Exclude,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterField {
+    Title,
+    Content,
+    Author,
+    Link,
+    Tags,
+}
+
+/// Matches items whose `field` contains `keyword` (case-insensitive) or matches `regex`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Filter {
+    pub action: FilterAction,
+    pub field: FilterField,
+    pub keyword: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_regex")]
+    pub regex: Option<Regex>,
+}
+
+pub fn deserialize_regex<'de, D>(deserializer: D) -> Result<Option<Regex>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let pattern = String::deserialize(deserializer)?;
+    Regex::new(&pattern)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+/// Whether `value` contains `keyword` (case-insensitive) or matches `regex`.
+pub fn text_matches(keyword: Option<&String>, regex: Option<&Regex>, value: &str) -> bool {
+    let keyword_match = keyword.is_some_and(|k| value.to_lowercase().contains(&k.to_lowercase()));
+    let regex_match = regex.is_some_and(|r| r.is_match(value));
+    keyword_match || regex_match
+}
+
+impl FilterField {
+    /// Whether `matches_value` is true for this field of `item`, or any of its tags.
+    pub fn matches(self, item: &SelfossItem, matches_value: impl Fn(&str) -> bool) -> bool {
+        match self {
+            FilterField::Title => matches_value(&item.title),
+            FilterField::Content => matches_value(&item.content),
+            FilterField::Author => item.author.as_deref().is_some_and(matches_value),
+            FilterField::Link => matches_value(&item.link),
+            FilterField::Tags => item.tags.iter().any(|t| matches_value(t)),
+        }
+    }
+}
+
+impl Filter {
+    pub fn matches(&self, item: &SelfossItem) -> bool {
+        self.field.matches(item, |value| {
+            text_matches(self.keyword.as_ref(), self.regex.as_ref(), value)
+        })
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} {:?}", self.action, self.field)?;
+        if let Some(keyword) = &self.keyword {
+            write!(f, " containing {:?}", keyword)?;
+        }
+        if let Some(regex) = &self.regex {
+            write!(f, " matching /{}/", regex)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns a description of the rule that drops `item`, or `None` if the item passes.
+///
+/// An item is dropped if it matches any exclude filter, or if include filters are configured
+/// and it matches none of them.
+pub fn find_blocking_rule<'a>(
+    filters: impl Iterator<Item = &'a Filter>,
+    item: &SelfossItem,
+) -> Option<String> {
+    let mut includes = vec![];
+    for filter in filters {
+        match filter.action {
+            FilterAction::Exclude if filter.matches(item) => return Some(filter.to_string()),
+            FilterAction::Exclude => {}
+            FilterAction::Include => includes.push(filter),
+        }
+    }
+
+    match includes.is_empty() || includes.iter().any(|f| f.matches(item)) {
+        true => None,
+        false => Some(format!(
+            "none of the include filters matched ({})",
+            includes
+                .iter()
+                .map(|f| f.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        config::Settings,
+        filters::{find_blocking_rule, Filter},
+        test::get_mock_item,
+    };
+
+    fn parse_filters(toml: &str) -> Vec<Filter> {
+        toml::from_str::<Settings>(toml).unwrap().filters
+    }
+
+    #[test]
+    fn test_exclude_filter() {
+        let filters = parse_filters(
+            r#"
+            [[filters]]
+            action = "exclude"
+            field = "title"
+            keyword = "MY TITLE"
+            "#,
+        );
+
+        assert_eq!(
+            find_blocking_rule(filters.iter(), &get_mock_item()).as_deref(),
+            Some(r#"Exclude Title containing "MY TITLE""#)
+        );
+    }
+
+    #[test]
+    fn test_include_filters() {
+        let filters = parse_filters(
+            r#"
+            [[filters]]
+            action = "include"
+            field = "tags"
+            regex = "^news$"
+
+            [[filters]]
+            action = "include"
+            field = "author"
+            keyword = "someone else"
+            "#,
+        );
+        let mut item = get_mock_item();
+        assert_eq!(find_blocking_rule(filters.iter(), &item), None);
+
+        item.tags = vec![String::from("sports")];
+        assert!(find_blocking_rule(filters.iter(), &item).is_some());
+    }
+}
@@ -0,0 +1,249 @@
+//! Images of items that are uploaded to Discord, because many feeds hotlink images that Discord
+//! cannot embed (referrer checks, expiring URLs).
+
+use std::time::Duration;
+
+use reqwest::{header::CONTENT_TYPE, Url};
+use scraper::{Html, Selector};
+use tracing::{debug, warn};
+
+use crate::{
+    config::{Config, ImageConfig},
+    discord::models::Attachment,
+    selfoss::models::SelfossItem,
+};
+
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// URLs of the images of an item, its thumbnail first and then the images in its content, without
+/// duplicates. Relative URLs in the content are resolved against the link of the item.
+pub fn image_urls(config: &Config, item: &SelfossItem) -> Vec<Url> {
+    let base = Url::parse(&item.link).ok();
+    let thumbnail = item.thumbnail.as_ref().and_then(|thumbnail| {
+        Url::parse(thumbnail).ok().or_else(|| {
+            // Selfoss only returns the file name of the thumbnails it keeps.
+            let url = format!("{}/thumbnails/{}", config.selfoss_base_url, thumbnail);
+            Url::parse(&url).ok()
+        })
+    });
+    let selector = Selector::parse("img[src]").unwrap();
+    let html = Html::parse_fragment(&item.content);
+    let images = html.select(&selector).filter_map(|img| {
+        let src = img.value().attr("src")?;
+        Url::parse(src)
+            .ok()
+            .or_else(|| base.as_ref()?.join(src).ok())
+    });
+
+    let mut urls: Vec<Url> = vec![];
+    for url in thumbnail.into_iter().chain(images) {
+        if matches!(url.scheme(), "http" | "https") && !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+    urls
+}
+
+fn extension(content_type: &str) -> &str {
+    match content_type {
+        "image/jpeg" => "jpg",
+        _ => content_type.rsplit('/').next().unwrap_or("bin"),
+    }
+}
+
+/// Downloads an image if it has one of the allowed types and is at most `max_bytes`.
+async fn download(
+    images: &ImageConfig,
+    url: &Url,
+    name: &str,
+    max_bytes: usize,
+) -> Result<Attachment, String> {
+    let mut response = reqwest::Client::new()
+        .get(url.clone())
+        .timeout(DOWNLOAD_TIMEOUT)
+        .send()
+        .await
+        .map_err(|e| e.without_url().to_string())?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(format!("returned {}", status));
+    }
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+    if !images.types.contains(&content_type) {
+        return Err(format!("has unsupported type {:?}", content_type));
+    }
+    let too_large = || format!("is larger than {} bytes", max_bytes);
+    if response
+        .content_length()
+        .is_some_and(|length| length > max_bytes as u64)
+    {
+        return Err(too_large());
+    }
+    // The length may be missing or wrong, so it is checked while downloading as well.
+    let mut data = vec![];
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| e.without_url().to_string())?
+    {
+        data.extend_from_slice(&chunk);
+        if data.len() > max_bytes {
+            return Err(too_large());
+        }
+    }
+    Ok(Attachment {
+        filename: format!("{}.{}", name, extension(&content_type)),
+        content_type,
+        data,
+    })
+}
+
+/// Downloads up to `max_images` images of an item, of at most `max_total_bytes` together. Images
+/// that cannot be downloaded are skipped, so the message is posted with the others, or without
+/// images if none could be downloaded.
+pub async fn download_images(
+    config: &Config,
+    images: &ImageConfig,
+    item: &SelfossItem,
+) -> Vec<Attachment> {
+    let mut attachments = vec![];
+    let mut remaining = images.max_total_bytes;
+    for url in image_urls(config, item) {
+        if attachments.len() >= images.max_images || remaining == 0 {
+            break;
+        }
+        let name = format!("image-{}", attachments.len() + 1);
+        match download(images, &url, &name, images.max_bytes.min(remaining)).await {
+            Ok(attachment) => {
+                remaining -= attachment.data.len();
+                attachments.push(attachment);
+            }
+            Err(e) => warn!(url = %url, error = e, "Could not download image"),
+        }
+    }
+    debug!(images = attachments.len(), "Downloaded images");
+    attachments
+}
+
+#[cfg(test)]
+mod test {
+    use httpmock::{Method::GET, MockServer};
+
+    use crate::{
+        config::{Config, ImageConfig},
+        images::{download_images, image_urls},
+        selfoss::models::SelfossItem,
+        test::get_mock_item,
+    };
+
+    #[test]
+    fn test_image_urls() {
+        let config = Config {
+            selfoss_base_url: String::from("https://selfoss.example.com"),
+            ..Default::default()
+        };
+        let item = SelfossItem {
+            link: String::from("https://example.com/posts/1"),
+            content: String::from(
+                r#"<p><img src="/a.png"><img src="https://cdn.example.com/b.jpg">
+                <img src="data:image/png;base64,AAAA"><img src="/a.png"></p>"#,
+            ),
+            thumbnail: Some(String::from("1234.jpg")),
+            ..get_mock_item()
+        };
+        let urls: Vec<String> = image_urls(&config, &item)
+            .iter()
+            .map(|url| url.to_string())
+            .collect();
+        assert_eq!(
+            urls,
+            vec![
+                "https://selfoss.example.com/thumbnails/1234.jpg",
+                "https://example.com/a.png",
+                "https://cdn.example.com/b.jpg",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_download_images() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/missing.png");
+            then.status(404);
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/page.html");
+            then.status(200)
+                .header("content-type", "text/html")
+                .body("<html></html>");
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/large.png");
+            then.status(200)
+                .header("content-type", "image/png")
+                .body(vec![0; 2048]);
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/photo.jpg");
+            then.status(200)
+                .header("content-type", "image/jpeg; charset=binary")
+                .body("jpeg");
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/second.gif");
+            then.status(200)
+                .header("content-type", "image/gif")
+                .body("gif");
+        });
+
+        let content = [
+            "missing.png",
+            "page.html",
+            "large.png",
+            "photo.jpg",
+            "second.gif",
+        ]
+        .map(|path| format!(r#"<img src="{}">"#, server.url(format!("/{}", path))))
+        .concat();
+        let item = SelfossItem {
+            content,
+            ..get_mock_item()
+        };
+        let images = ImageConfig {
+            max_images: 1,
+            max_bytes: 1024,
+            ..Default::default()
+        };
+        let attachments = download_images(&Config::default(), &images, &item).await;
+        assert_eq!(attachments.len(), 1);
+        assert_eq!(attachments[0].filename, "image-1.jpg");
+        assert_eq!(attachments[0].content_type, "image/jpeg");
+        assert_eq!(attachments[0].data, b"jpeg");
+
+        let images = ImageConfig {
+            max_bytes: 1024,
+            ..Default::default()
+        };
+        let attachments = download_images(&Config::default(), &images, &item).await;
+        let names: Vec<&str> = attachments.iter().map(|a| a.filename.as_str()).collect();
+        assert_eq!(names, vec!["image-1.jpg", "image-2.gif"]);
+
+        // The last image does not fit in the total size anymore.
+        let images = ImageConfig {
+            max_bytes: 4096,
+            max_total_bytes: 2054,
+            ..Default::default()
+        };
+        let attachments = download_images(&Config::default(), &images, &item).await;
+        let sizes: Vec<usize> = attachments.iter().map(|a| a.data.len()).collect();
+        assert_eq!(sizes, vec![2048, 4]);
+    }
+}
@@ -0,0 +1,165 @@
+use chrono::{DateTime, Duration, Utc};
+
+use crate::{
+    config::{Cap, Config, LimitConfig, SanitizeConfig},
+    sanitize::sanitize_link_text,
+    selfoss::models::SelfossItem,
+    state::{Delivery, State},
+    utils::MAX_MESSAGE_LENGTH,
+};
+
+fn is_reached(
+    cap: Option<Cap>,
+    now: DateTime<Utc>,
+    deliveries: impl Iterator<Item = DateTime<Utc>>,
+) -> bool {
+    cap.is_some_and(|cap| {
+        let since = now - Duration::minutes(cap.minutes);
+        deliveries.filter(|at| *at > since).count() >= cap.items
+    })
+}
+
+/// Whether another item of `source` can be posted in `channel` without exceeding the caps.
+pub fn allows(
+    limit: &LimitConfig,
+    deliveries: &[Delivery],
+    channel: &str,
+    source: &str,
+    now: DateTime<Utc>,
+) -> bool {
+    let in_channel = deliveries.iter().filter(|d| d.channel == channel);
+    let of_source = deliveries.iter().filter(|d| d.source == source);
+    !is_reached(limit.channel, now, in_channel.map(|d| d.at))
+        && !is_reached(limit.source, now, of_source.map(|d| d.at))
+}
+
+/// Counts a posted item against the caps of its route, if it has any.
+pub fn record(config: &Config, state: &mut State, item: &SelfossItem, now: DateTime<Utc>) {
+    if config.route_for(item).is_some_and(|r| r.limit.is_some()) {
+        state.deliveries.push(Delivery {
+            channel: config.channel_name(&item.sourcetitle),
+            source: item.sourcetitle.clone(),
+            at: now,
+        });
+    }
+}
+
+/// Forgets deliveries that no cap counts anymore.
+pub fn prune(config: &Config, state: &mut State, now: DateTime<Utc>) {
+    let minutes = config
+        .routes
+        .iter()
+        .filter_map(|r| r.limit.as_ref())
+        .flat_map(|limit| [limit.channel, limit.source])
+        .flatten()
+        .map(|cap| cap.minutes)
+        .max()
+        .unwrap_or_default();
+    let since = now - Duration::minutes(minutes);
+    state.deliveries.retain(|d| d.at > since);
+}
+
+/// A single message with the links of the items over a cap. Links that do not fit are left out,
+/// but still counted.
+pub fn format_summary(config: &SanitizeConfig, items: &[SelfossItem]) -> String {
+    let mut message = format!("…and {} more:", items.len());
+    for item in items {
+        let line = match item.link.is_empty() {
+            true => format!("\n- {}", sanitize_link_text(config, &item.title)),
+            // Without embeds, which would take up most of the channel again.
+            false => format!(
+                "\n- [{}](<{}>)",
+                sanitize_link_text(config, &item.title),
+                item.link
+            ),
+        };
+        if message.chars().count() + line.chars().count() > MAX_MESSAGE_LENGTH {
+            break;
+        }
+        message.push_str(&line);
+    }
+    message
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, Duration, Utc};
+
+    use crate::{
+        config::{Cap, LimitConfig, SanitizeConfig},
+        limits::{allows, format_summary},
+        selfoss::models::SelfossItem,
+        state::Delivery,
+        test::get_mock_item,
+    };
+
+    #[test]
+    fn test_allows() {
+        let limit: LimitConfig = toml::from_str(
+            r#"
+            channel = { items = 3, minutes = 60 }
+            source = { items = 2, minutes = 10 }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            limit.channel,
+            Some(Cap {
+                items: 3,
+                minutes: 60
+            })
+        );
+
+        let now: DateTime<Utc> = DateTime::parse_from_rfc3339("2023-12-15T18:00:00Z")
+            .unwrap()
+            .into();
+        let delivery = |source: &str, minutes_ago: i64| Delivery {
+            channel: String::from("news"),
+            source: source.to_string(),
+            at: now - Duration::minutes(minutes_ago),
+        };
+
+        let deliveries = vec![delivery("a", 5), delivery("a", 20)];
+        assert!(allows(&limit, &deliveries, "news", "a", now));
+
+        let deliveries = vec![delivery("a", 5), delivery("a", 9)];
+        assert!(!allows(&limit, &deliveries, "news", "a", now));
+        assert!(allows(&limit, &deliveries, "news", "b", now));
+        assert!(allows(&limit, &deliveries, "other", "b", now));
+
+        let deliveries = vec![delivery("a", 5), delivery("b", 30), delivery("c", 59)];
+        assert!(!allows(&limit, &deliveries, "news", "d", now));
+        assert!(allows(&limit, &deliveries, "other", "d", now));
+        assert!(allows(
+            &limit,
+            &deliveries,
+            "news",
+            "d",
+            now + Duration::minutes(1)
+        ));
+    }
+
+    #[test]
+    fn test_format_summary() {
+        let mut items = vec![
+            SelfossItem {
+                link: String::from("https://example.com/1"),
+                ..get_mock_item()
+            },
+            SelfossItem {
+                title: String::from("[Second]"),
+                link: String::new(),
+                ..get_mock_item()
+            },
+        ];
+        assert_eq!(
+            format_summary(&SanitizeConfig::default(), &items),
+            "…and 2 more:\n- [My title](<https://example.com/1>)\n- \\[Second\\]"
+        );
+
+        items.extend(vec![get_mock_item(); 150]);
+        let summary = format_summary(&SanitizeConfig::default(), &items);
+        assert!(summary.starts_with("…and 152 more:"));
+        assert!(summary.chars().count() <= 2000);
+    }
+}
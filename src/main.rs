@@ -1,92 +1,830 @@
 extern crate dotenv;
 
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    env, process,
+    time::Duration as StdDuration,
+};
 
-use config::Config;
+use chrono::{DateTime, Duration, Utc};
+use clap::Parser;
+use cli::{Cli, Command, FailedAction};
+use config::{
+    read_settings, ChannelDeletedAction, Config, DaemonConfig, DedupAction, LoggingConfig,
+    OverflowAction, Route, Settings,
+};
 use dotenv::dotenv;
+use tokio::time::{self, MissedTickBehavior};
+use tracing::{debug, error, field, info, info_span, warn, Instrument, Span};
 
+mod backfill;
+mod backlog;
+mod cli;
+mod commands;
 mod config;
+mod dead_letter;
+mod dedup;
+mod digest;
 mod discord;
+mod errors;
+mod filters;
+mod images;
+mod limits;
+mod logging;
+mod mentions;
+mod metrics;
+mod opml;
+mod publish;
+mod sanitize;
 mod selfoss;
+mod server;
+mod sinks;
+mod source_sync;
+mod sources;
+mod state;
+mod stats;
+mod timestamps;
 mod utils;
+mod window;
 
-use discord::{
-    adapter::{get_channels, post_message},
-    errors::RequestError,
-};
-use selfoss::{
-    adapter::{get_tree, mark_items_as_read},
-    models::SelfossItem,
-};
+use discord::{adapter::get_channels, errors::RequestError, models::GUILD_CATEGORY};
+use errors::AppError;
+use selfoss::models::SelfossItem;
+use sinks::{discord::DiscordSink, Sinks, DISCORD_SINK};
+use state::{PostedItem, QueuedItem, State};
+use stats::RunStats;
+
+/// Stops posting the items of `source` because its channel has been deleted.
+fn unsubscribe(state: &mut State, source: &str) {
+    if !state.unsubscribed.iter().any(|s| s == source) {
+        warn!(
+            source,
+            "Unsubscribing from source whose channel has been deleted"
+        );
+        state.unsubscribed.push(source.to_string());
+    }
+}
+
+async fn send_digest(
+    config: &Config,
+    state: &mut State,
+    stats: &mut RunStats,
+    route: &Route,
+    items: Vec<SelfossItem>,
+    discord: &DiscordSink,
+) -> Result<(), RequestError> {
+    let now = config.clock.now();
+    let digest = route.digest.as_ref().unwrap();
+    if !digest::is_due(digest, state.digests.get(&route.name).cloned(), now) {
+        info!(
+            items = items.len(),
+            "Holding back items for the next digest"
+        );
+        stats.held_back += items.len();
+        return Ok(());
+    }
+
+    post_digest(config, state, stats, route, &items, discord, now).await?;
+    state.digests.insert(route.name.clone(), now);
+    Ok(())
+}
+
+/// Posts `items` as a digest in the channel of `route` and marks them as read.
+async fn post_digest(
+    config: &Config,
+    state: &mut State,
+    stats: &mut RunStats,
+    route: &Route,
+    items: &[SelfossItem],
+    discord: &DiscordSink,
+    now: DateTime<Utc>,
+) -> Result<(), RequestError> {
+    let name = match &route.channel {
+        Some(channel) => channel.clone(),
+        None => items[0].clone().get_discord_channel_name(),
+    };
+    Span::current().record("channel", &name);
+    let options = route.channel_options();
+    for content in digest::format_digest(&config.sanitize, items) {
+        let (channel, message_id) = match discord.post_content(&name, &options, &content).await {
+            Err(RequestError::UnknownChannel(_))
+                if config.on_channel_deleted == ChannelDeletedAction::Unsubscribe =>
+            {
+                for item in items {
+                    unsubscribe(state, &item.sourcetitle);
+                    config.source().mark_read(item.id).await?;
+                    stats.marked_read += 1;
+                }
+                stats.filtered += items.len();
+                return Ok(());
+            }
+            result => result?,
+        };
+        if route.announcement {
+            publish::publish(config, state, stats, discord, &channel, &message_id, now).await?;
+        }
+    }
+    info!(items = items.len(), "Posted digest");
+    stats.posted += items.len();
+
+    for item in items {
+        config.source().mark_read(item.id).await?;
+        stats.marked_read += 1;
+    }
+    Ok(())
+}
+
+/// Posts an item, or handles it as duplicate or item of a deleted channel. It is marked as read
+/// by `deliver_item`.
+async fn send_item(
+    config: &Config,
+    state: &mut State,
+    stats: &mut RunStats,
+    item: &SelfossItem,
+    sinks: &mut Sinks,
+    now: DateTime<Utc>,
+) -> Result<(), RequestError> {
+    if let Some(dedup) = &config.dedup {
+        if let Some(original) = dedup::find_original(dedup, &mut state.posted, item, now) {
+            info!(original = original.sourcetitle, "Item is a duplicate");
+            stats.duplicates += 1;
+            if dedup.action == DedupAction::Annotate
+                && original.sourcetitle != item.sourcetitle
+                && !original.also_in.contains(&item.sourcetitle)
+            {
+                original.also_in.push(item.sourcetitle.clone());
+                match (&original.sink, original.annotated_item()) {
+                    // Webhooks do not return the message, so it cannot be annotated.
+                    _ if original.message_id.is_empty() => {}
+                    (sink, Some(annotated)) => {
+                        let sink = sink.as_deref().unwrap_or(DISCORD_SINK);
+                        sinks
+                            .update(
+                                config,
+                                sink,
+                                &original.channel_id,
+                                &original.message_id,
+                                &annotated,
+                            )
+                            .await?
+                    }
+                    // Recorded with only the content of the message.
+                    (_, None) => {
+                        sinks
+                            .discord()
+                            .edit_content(
+                                &original.channel_id,
+                                &original.message_id,
+                                &original.annotated_content(),
+                            )
+                            .await?
+                    }
+                }
+            }
+            return Ok(());
+        }
+    }
+
+    let sink = config
+        .route_for(item)
+        .and_then(Route::other_sink)
+        .unwrap_or(DISCORD_SINK);
+    if !sinks.get(config, sink)?.has_content(item) {
+        debug!("Skipping item without content");
+        return Ok(());
+    }
+    let name = config.channel_name(&item.sourcetitle);
+    Span::current().record("channel", &name);
+    let options = config.channel_options_for(item);
+    let (destination, message_id) = match sinks.post_item(config, sink, &name, &options, item).await
+    {
+        Err(RequestError::UnknownChannel(_))
+            if config.on_channel_deleted == ChannelDeletedAction::Unsubscribe =>
+        {
+            unsubscribe(state, &item.sourcetitle);
+            stats.filtered += 1;
+            return Ok(());
+        }
+        result => result?,
+    };
+    info!(sink, destination, message_id, "Posted item");
+    stats.posted += 1;
+    limits::record(config, state, item, now);
+    if config.route_for(item).is_some_and(|r| r.announcement) {
+        let sink = sinks.get(config, sink)?;
+        publish::publish(config, state, stats, sink, &destination, &message_id, now).await?;
+    }
+    if config.dedup.is_some() {
+        let posted = PostedItem::for_sink(item, sink, &destination, &message_id, now);
+        state.posted.push(posted);
+    }
+    Ok(())
+}
+
+/// Posts the links of the items over the cap of `channel` in a single message, and marks them as
+/// read.
+async fn send_summary(
+    config: &Config,
+    stats: &mut RunStats,
+    channel: &str,
+    items: &[SelfossItem],
+    discord: &DiscordSink,
+) -> Result<(), RequestError> {
+    let options = config.channel_options_for(&items[0]);
+    let content = limits::format_summary(&config.sanitize, items);
+    discord.post_content(channel, &options, &content).await?;
+    info!(
+        items = items.len(),
+        "Posted summary of items over the limit"
+    );
+    stats.posted += items.len();
+    for item in items {
+        config.source().mark_read(item.id).await?;
+        stats.marked_read += 1;
+    }
+    Ok(())
+}
+
+/// Marks an item that is not posted as read. Only fatal errors are returned, as the item is
+/// dropped again on the next run.
+async fn mark_dropped_read(
+    config: &Config,
+    stats: &mut RunStats,
+    item: &SelfossItem,
+) -> Result<(), RequestError> {
+    match config.source().mark_read(item.id).await {
+        Ok(_) => stats.marked_read += 1,
+        Err(e) if e.is_fatal() => return Err(e),
+        Err(e) => {
+            error!(
+                error = config.redact(&e.to_string()),
+                "Could not mark item as read"
+            );
+            stats.failed += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Sends an item and records it in the dead-letter queue if that fails. An item that was posted
+/// but could not be marked as read is only marked as read when it is retried, so that it is not
+/// posted twice.
+async fn deliver_item(
+    config: &Config,
+    state: &mut State,
+    stats: &mut RunStats,
+    item: &SelfossItem,
+    sinks: &mut Sinks,
+    now: DateTime<Utc>,
+) -> Result<(), RequestError> {
+    if !state.dead_letter(item.id).is_some_and(|d| d.posted) {
+        if let Err(e) = send_item(config, state, stats, item, sinks, now).await {
+            return record_delivery_failure(config, state, stats, item, e, false, now);
+        }
+    }
+    match config.source().mark_read(item.id).await {
+        Ok(()) => {
+            stats.marked_read += 1;
+            if state.remove_dead_letter(item.id).is_some() {
+                info!("Delivered previously failed item");
+            }
+            Ok(())
+        }
+        Err(e) => record_delivery_failure(config, state, stats, item, e, true, now),
+    }
+}
+
+/// Records an item that could not be delivered in the dead-letter queue. Only fatal errors are
+/// returned, items that were already posted are recorded as such before that.
+fn record_delivery_failure(
+    config: &Config,
+    state: &mut State,
+    stats: &mut RunStats,
+    item: &SelfossItem,
+    e: RequestError,
+    posted: bool,
+    now: DateTime<Utc>,
+) -> Result<(), RequestError> {
+    if e.is_fatal() && !posted {
+        return Err(e);
+    }
+    let error = config.redact(&e.to_string());
+    stats.failed += 1;
+    state.record_failure(item, error.clone(), e.is_retryable(), posted, now);
+    let dead_letter = state.dead_letter(item.id).unwrap();
+    error!(
+        error,
+        attempts = dead_letter.attempts,
+        retryable = dead_letter.retryable,
+        posted,
+        next_attempt_at = %dead_letter.next_attempt_at,
+        "Could not deliver item"
+    );
+    match e.is_fatal() {
+        true => Err(e),
+        false => Ok(()),
+    }
+}
+
+fn item_span(item: &SelfossItem) -> Span {
+    info_span!(
+        "item",
+        id = item.id,
+        source = item.sourcetitle,
+        channel = field::Empty
+    )
+}
+
+/// Posts the queued items of routes whose delivery window has opened, in order of arrival, or as
+/// a digest if more of them are waiting than the threshold of the window. Returns the ids of the
+/// items that are no longer queued.
+async fn release_queued(
+    config: &Config,
+    state: &mut State,
+    stats: &mut RunStats,
+    sinks: &mut Sinks,
+    now: DateTime<Utc>,
+) -> Result<HashSet<u64>, RequestError> {
+    let mut routes: Vec<String> = vec![];
+    for queued in &state.queued {
+        if !routes.contains(&queued.route) {
+            routes.push(queued.route.clone());
+        }
+    }
+
+    let mut released = HashSet::new();
+    for name in routes {
+        // Items of a route that has been removed are posted right away.
+        let route = config.routes.iter().find(|r| r.name == name);
+        let window = route.and_then(|r| r.window.as_ref());
+        if window.is_some_and(|w| !window::is_open(w, now)) {
+            continue;
+        }
+        let items: Vec<SelfossItem> = state
+            .queued
+            .iter()
+            .filter(|q| q.route == name)
+            .map(|q| q.item.clone())
+            .collect();
+        info!(route = name, items = items.len(), "Delivery window opened");
 
-use crate::{discord::adapter::create_channel, utils::deserialize_string_from_env};
+        match (route, window.and_then(|w| w.digest_threshold)) {
+            (Some(route), Some(threshold)) if items.len() > threshold => {
+                let span = info_span!("digest", route = route.name, channel = field::Empty);
+                let result = post_digest(config, state, stats, route, &items, sinks.discord(), now)
+                    .instrument(span.clone())
+                    .await;
+                match result {
+                    Ok(()) => {
+                        state.queued.retain(|q| q.route != name);
+                        released.extend(items.iter().map(|item| item.id));
+                    }
+                    Err(e) if e.is_fatal() => return Err(e),
+                    Err(e) => {
+                        // The items stay queued, so they are part of the next attempt.
+                        span.in_scope(|| {
+                            error!(
+                                error = config.redact(&e.to_string()),
+                                "Could not send digest"
+                            )
+                        });
+                        stats.failed += items.len();
+                        continue;
+                    }
+                }
+            }
+            _ => {
+                for item in &items {
+                    // Failed items are retried from the dead-letter queue.
+                    deliver_item(config, state, stats, item, sinks, now)
+                        .instrument(item_span(item))
+                        .await?;
+                    // Dequeued right away, so a fatal error for a later item does not post it
+                    // again.
+                    state
+                        .queued
+                        .retain(|q| q.route != name || q.item.id != item.id);
+                    released.insert(item.id);
+                }
+            }
+        }
+    }
+    Ok(released)
+}
 
 async fn send_messages(
     config: &Config,
+    state: &mut State,
+    stats: &mut RunStats,
     item_list: Vec<SelfossItem>,
-    mut channel_map: HashMap<String, String>,
+    channel_map: HashMap<String, String>,
 ) -> Result<(), RequestError> {
-    println!("Found max {} messages to send", item_list.len());
+    info!(items = item_list.len(), "Found messages to send");
+
+    stats.fetched += item_list.len();
+    let mut digests: Vec<(&Route, Vec<SelfossItem>)> = vec![];
+    // Items over the cap of their route, by channel.
+    let mut summaries: Vec<(String, Vec<SelfossItem>)> = vec![];
+    let mut sinks = Sinks::new(config, Some(channel_map));
+    let now = config.clock.now();
+    if let Some(dedup) = &config.dedup {
+        let window_start = now - Duration::hours(dedup.window_hours);
+        state.posted.retain(|p| p.posted_at >= window_start);
+    }
+    limits::prune(config, state, now);
+    publish::publish_unpublished(config, state, stats, sinks.discord(), now).await?;
+    let released = release_queued(config, state, stats, &mut sinks, now).await?;
 
     for item in &item_list {
-        let name = item.clone().get_discord_channel_name();
-        let channel = channel_map.get(name.clone().as_str());
-        let content = item.clone().get_discord_message_content();
+        let span = item_span(item);
+        if released.contains(&item.id) {
+            continue;
+        }
+        if state.is_queued(item.id) {
+            stats.held_back += 1;
+            continue;
+        }
 
-        if channel.is_none() {
-            let c = create_channel(config, name.clone().as_str()).await?;
-            channel_map.insert(name.clone(), c.id);
+        let unsubscribed = state.unsubscribed.contains(&item.sourcetitle);
+        let rule = match unsubscribed {
+            true => Some(String::from("channel deleted, unsubscribed")),
+            false => filters::find_blocking_rule(config.filters_for(item), item),
+        };
+        if let Some(rule) = rule {
+            span.in_scope(|| info!(rule, "Dropping filtered item"));
+            stats.filtered += 1;
+            if unsubscribed || config.mark_filtered_as_read {
+                mark_dropped_read(config, stats, item)
+                    .instrument(span)
+                    .await?;
+            }
+            continue;
+        }
+
+        let max_age = config.max_item_age_hours.map(Duration::hours);
+        if max_age.is_some_and(|max_age| item.datetime < now - max_age) {
+            span.in_scope(|| debug!("Skipping item older than the maximum age"));
+            stats.filtered += 1;
+            if config.mark_old_as_read {
+                mark_dropped_read(config, stats, item)
+                    .instrument(span)
+                    .await?;
+            }
+            continue;
+        }
+
+        if let Some(route) = config.route_for(item).filter(|r| r.digest.is_some()) {
+            match digests.iter_mut().find(|(r, _)| r.name == route.name) {
+                Some((_, items)) => items.push(item.clone()),
+                None => digests.push((route, vec![item.clone()])),
+            }
+            continue;
+        }
+
+        let closed = |route: &&Route| {
+            route
+                .window
+                .as_ref()
+                .is_some_and(|w| !window::is_open(w, now))
+        };
+        if let Some(route) = config.route_for(item).filter(closed) {
+            span.in_scope(|| debug!(route = route.name, "Queueing item until the window opens"));
+            state.queued.push(QueuedItem {
+                route: route.name.clone(),
+                item: item.clone(),
+                queued_at: now,
+            });
+            stats.held_back += 1;
+            continue;
         }
 
-        if !item.content.is_empty() && !content.is_empty() {
-            post_message(
-                config,
-                channel_map.get(name.clone().as_str()).unwrap(),
-                &content,
-            )
+        if state.is_deferred(item.id, now) {
+            span.in_scope(|| debug!("Waiting for the backoff of a previously failed item"));
+            stats.held_back += 1;
+            continue;
+        }
+
+        let channel = config.channel_name(&item.sourcetitle);
+        if let Some(limit) = config.route_for(item).and_then(|r| r.limit.as_ref()) {
+            if !limits::allows(limit, &state.deliveries, &channel, &item.sourcetitle, now) {
+                match limit.overflow {
+                    OverflowAction::Defer => {
+                        span.in_scope(|| debug!("Deferring item over the limit"));
+                        stats.held_back += 1;
+                    }
+                    OverflowAction::Summarize => {
+                        match summaries.iter_mut().find(|(c, _)| *c == channel) {
+                            Some((_, items)) => items.push(item.clone()),
+                            None => summaries.push((channel, vec![item.clone()])),
+                        }
+                    }
+                    OverflowAction::Drop => {
+                        span.in_scope(|| info!("Dropping item over the limit"));
+                        stats.filtered += 1;
+                        mark_dropped_read(config, stats, item)
+                            .instrument(span)
+                            .await?;
+                    }
+                }
+                continue;
+            }
+        }
+
+        deliver_item(config, state, stats, item, &mut sinks, now)
+            .instrument(span)
             .await?;
+    }
+
+    for (route, items) in digests {
+        let span = info_span!("digest", route = route.name, channel = field::Empty);
+        let count = items.len();
+        let result = send_digest(config, state, stats, route, items, sinks.discord())
+            .instrument(span.clone())
+            .await;
+        match result {
+            Ok(()) => {}
+            Err(e) if e.is_fatal() => return Err(e),
+            Err(e) => {
+                // The items stay unread, so they are part of the next digest.
+                span.in_scope(|| {
+                    error!(
+                        error = config.redact(&e.to_string()),
+                        "Could not send digest"
+                    )
+                });
+                stats.failed += count;
+            }
         }
+    }
 
-        mark_items_as_read(config, item.id).await?;
+    for (channel, items) in summaries {
+        let span = info_span!("summary", channel);
+        let result = send_summary(config, stats, &channel, &items, sinks.discord())
+            .instrument(span.clone())
+            .await;
+        match result {
+            Ok(()) => {}
+            Err(e) if e.is_fatal() => return Err(e),
+            Err(e) => {
+                // The items stay unread, so they are posted or summarized on the next run.
+                span.in_scope(|| {
+                    error!(
+                        error = config.redact(&e.to_string()),
+                        "Could not send summary"
+                    )
+                });
+                stats.failed += items.len();
+            }
+        }
     }
     Ok(())
 }
 
+async fn get_channel_map(config: &Config) -> Result<HashMap<String, String>, RequestError> {
+    Ok(get_channels(config)
+        .await?
+        .into_iter()
+        .filter(|x| x.kind != GUILD_CATEGORY)
+        .map(|x| (x.name, x.id))
+        .collect())
+}
+
+async fn sync(config: &Config, stats: &mut RunStats) -> Result<(), AppError> {
+    let mut state = State::load(&config.state_path).map_err(AppError::State)?;
+
+    let item_list: Vec<SelfossItem> = config
+        .source()
+        .fetch_unread()
+        .await?
+        .into_iter()
+        .filter(|item| config.handles_source(&item.sourcetitle))
+        .collect();
+    if let Some(source_sync) = &config.source_sync {
+        match source_sync::sync_sources(config, source_sync, &mut state).await {
+            Ok(stats) => info!(
+                created = stats.created,
+                renamed = stats.renamed,
+                archived = stats.archived,
+                "Synced channels with sources"
+            ),
+            Err(e) if e.is_fatal() => return Err(e.into()),
+            // Channels of new items are still created when they are posted.
+            Err(e) => error!(
+                error = config.redact(&e.to_string()),
+                "Could not sync channels with sources"
+            ),
+        }
+    }
+    let item_list = backlog::handle_first_run(config, &state, stats, item_list).await?;
+    let channel_map = get_channel_map(config).await?;
+
+    let result = send_messages(config, &mut state, stats, item_list, channel_map).await;
+    state.save(&config.state_path).map_err(AppError::State)?;
+    config.source().save().await?;
+    Ok(result?)
+}
+
+/// Runs a single sync, logs its outcome and records it in the metrics.
+async fn run(config: &Config) -> (RunStats, Result<(), AppError>) {
+    let mut stats = RunStats::default();
+    let span = info_span!("run", bridge = config.name);
+    let result = sync(config, &mut stats).instrument(span).await;
+    config.metrics.record_run(&stats, result.is_ok());
+
+    match &result {
+        Ok(()) => info!(
+            fetched = stats.fetched,
+            posted = stats.posted,
+            filtered = stats.filtered,
+            duplicates = stats.duplicates,
+            held_back = stats.held_back,
+            failed = stats.failed,
+            marked_read = stats.marked_read,
+            published = stats.published,
+            publish_failed = stats.publish_failed,
+            "Done"
+        ),
+        Err(e) => error!(
+            error = config.redact(&e.to_string()),
+            failed = stats.failed,
+            "Run failed"
+        ),
+    }
+    (stats, result)
+}
+
+async fn run_daemon(bridges: &[Config], daemon: &DaemonConfig) {
+    if let Some(address) = daemon.metrics_address {
+        // The metrics of all bridges are served together, labelled with the name of the bridge.
+        let metrics = bridges[0].metrics.clone();
+        if let Err(e) = server::start(address, metrics) {
+            error!(error = %e, %address, "Could not start metrics server");
+            process::exit(1);
+        }
+    }
+
+    let mut interval = time::interval(StdDuration::from_secs(daemon.interval_seconds));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        // Failures are logged and exposed through the health endpoints, the next run retries.
+        for config in bridges {
+            let _ = run(config).await;
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
+    let cli = Cli::parse();
     dotenv().ok();
-    let config = Config {
-        discord_base_url: String::from("https://discord.com/api/v10"),
-        discord_token: deserialize_string_from_env("DISCORD_TOKEN"),
-        discord_server_id: deserialize_string_from_env("DISCORD_SERVER_ID"),
-        selfoss_base_url: deserialize_string_from_env("SELFOSS_BASE_URL"),
-        selfoss_username: deserialize_string_from_env("SELFOSS_USERNAME"),
-        selfoss_password: deserialize_string_from_env("SELFOSS_PASSWORD"),
+    let options = cli.options;
+    let settings_path = options
+        .config
+        .clone()
+        .or_else(|| env::var("CONFIG_PATH").ok());
+    let settings = settings_path.as_deref().map(read_settings).transpose();
+
+    let mut logging = match &settings {
+        Ok(Some(settings)) => settings.logging.clone(),
+        _ => LoggingConfig::default(),
     };
+    if options.verbose {
+        logging.level = String::from("debug");
+    }
+    if let Err(e) = logging::init(&logging) {
+        // Nothing is logged without a subscriber.
+        eprintln!("{}", e);
+        process::exit(1);
+    }
 
-    let item_list = get_tree(&config)
-        .await
-        .expect("Could not fetch Selfoss items");
+    // Without a command, keep the behaviour of a plain run: daemon if configured, else one sync.
+    let command = cli.command.unwrap_or(match &settings {
+        Ok(Some(Settings {
+            daemon: Some(_), ..
+        })) => Command::Daemon,
+        _ => Command::Sync,
+    });
+    if let Command::CheckConfig = command {
+        let ok = commands::check_config(settings_path.as_deref(), settings, options.json).await;
+        process::exit(if ok { 0 } else { 1 });
+    }
 
-    let channels = get_channels(&config).await;
+    let settings = settings
+        .unwrap_or_else(|e| {
+            error!(error = e, "Could not load settings");
+            process::exit(1);
+        })
+        .unwrap_or_default();
+    let mut bridges = Config::bridges(&settings).unwrap_or_else(|e| {
+        error!(error = e, "Could not load config");
+        process::exit(1);
+    });
+    if let Some(backlog) = options.backlog {
+        for config in &mut bridges {
+            config.first_run.backlog = backlog;
+        }
+    }
+    for config in &bridges {
+        debug!(?config, "Loaded config");
+    }
 
-    let channel_map = channels
-        .expect("Could not get channels")
-        .into_iter()
-        .map(|x| (x.name, x.id))
-        .collect();
+    let json = options.json;
+    match command {
+        Command::Sync => {
+            let mut ok = true;
+            for config in &bridges {
+                let (stats, result) = run(config).await;
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&stats).unwrap());
+                }
+                ok &= result.is_ok();
+            }
+            // Errors are already logged by `run`.
+            process::exit(if ok { 0 } else { 1 });
+        }
+        Command::Daemon => run_daemon(&bridges, &settings.daemon.unwrap_or_default()).await,
+        command => {
+            let config = select_bridge(&bridges, options.bridge.as_deref());
+            if let Err(e) = run_command(config, command, settings_path.as_deref(), json).await {
+                error!(error = config.redact(&e.to_string()), "Command failed");
+                process::exit(1);
+            }
+        }
+    }
+}
+
+fn select_bridge<'a>(bridges: &'a [Config], name: Option<&str>) -> &'a Config {
+    let config = match name {
+        Some(name) => bridges.iter().find(|b| b.name == name),
+        None => bridges.first(),
+    };
+    config.unwrap_or_else(|| {
+        let names: Vec<&str> = bridges.iter().map(|b| b.name.as_str()).collect();
+        error!(bridges = names.join(", "), "No bridge with this name");
+        process::exit(1);
+    })
+}
 
-    let result = send_messages(&config, item_list, channel_map).await;
-    result.expect("Could not send a message");
+/// Runs a command that applies to a single bridge.
+async fn run_command(
+    config: &Config,
+    command: Command,
+    settings_path: Option<&str>,
+    json: bool,
+) -> Result<(), AppError> {
+    match command {
+        Command::ListSources => commands::list_sources(config, json).await?,
+        Command::ListChannels => commands::list_channels(config, json).await?,
+        Command::Map => commands::map(config, json).await?,
+        Command::SendTest {
+            channel,
+            sink,
+            delete,
+        } => commands::send_test(config, &channel, sink.as_deref(), delete, json).await?,
+        Command::MarkRead { source } => commands::mark_read(config, &source, json).await?,
+        Command::Backfill(args) => backfill::backfill(config, &args, json).await?,
+        Command::SyncSources => commands::sync_sources(config, json).await?,
+        Command::ImportOpml(args) => opml::import_opml(config, &args, settings_path, json).await?,
+        Command::Failed { action } => match action.unwrap_or(FailedAction::List) {
+            FailedAction::List => commands::list_failed(config, json)?,
+            FailedAction::Retry { ids } => commands::retry_failed(config, &ids).await?,
+            FailedAction::Discard { ids, star } => {
+                commands::discard_failed(config, &ids, star).await?
+            }
+        },
+        Command::Sync | Command::Daemon | Command::CheckConfig => {
+            unreachable!("runs for all bridges")
+        }
+    }
+    Ok(())
 }
 
 #[cfg(test)]
 mod test {
-    use std::collections::HashMap;
+    use std::{collections::HashMap, env, fs};
 
-    use crate::{config::Config, selfoss::models::SelfossItem, send_messages};
-    use chrono::DateTime;
-    use httpmock::{Method::POST, MockServer};
+    use crate::{
+        config::{
+            ChannelDeletedAction, Config, DedupAction, DedupConfig, DigestConfig, DigestSchedule,
+            ImageConfig, Route, Settings, SinkConfig, SinkKind,
+        },
+        discord::errors::RequestError,
+        errors::AppError,
+        run,
+        selfoss::models::SelfossItem,
+        send_messages,
+        state::{QueuedItem, State},
+        stats::RunStats,
+        utils::Clock,
+    };
+    use chrono::{DateTime, Utc};
+    use chrono_tz::Tz;
+    use httpmock::{
+        Method::{GET, PATCH, POST},
+        MockServer,
+    };
+    use serde_json::json;
 
     pub fn start_server() -> (MockServer, Config) {
         let server = MockServer::start();
@@ -97,6 +835,7 @@ mod test {
             selfoss_base_url: server.base_url(),
             selfoss_username: String::from("test username"),
             selfoss_password: String::from("test password"),
+            ..Default::default()
         };
         (server, config)
     }
@@ -110,6 +849,12 @@ mod test {
                 .unwrap()
                 .into(),
             id: 187204,
+            link: String::from("My link"),
+            author: Some(String::from("Me")),
+            tags: vec![String::from("news")],
+            unread: false,
+            starred: false,
+            thumbnail: None,
         }
     }
 
@@ -137,10 +882,1060 @@ mod test {
         let channel_map =
             HashMap::from([(String::from("my_channel"), String::from("my_channel_id"))]);
         let item_list = vec![get_mock_item()];
-        let result = send_messages(&config, item_list, channel_map).await;
-
-        result.expect("Did not send messages correctly");
+        send_messages(
+            &config,
+            &mut State::default(),
+            &mut RunStats::default(),
+            item_list,
+            channel_map,
+        )
+        .await
+        .expect("Did not send messages correctly");
         send_message_mock.assert_async().await;
         mark_item_read_mock.assert_async().await;
     }
+
+    #[tokio::test]
+    async fn test_routes_post_to_their_sink() {
+        let (server, mut config) = start_server();
+        env::set_var("TEST_SINK_WEBHOOK", server.url("/hooks/abc"));
+        config.sinks = vec![SinkConfig {
+            name: String::from("team"),
+            kind: SinkKind::Mattermost {
+                webhook_env: String::from("TEST_SINK_WEBHOOK"),
+            },
+        }];
+        config.routes = vec![Route {
+            name: String::from("news"),
+            sources: vec![],
+            channel: Some(String::from("town-square")),
+            selfoss: None,
+            guild: None,
+            category: None,
+            topic: None,
+            announcement: false,
+            sink: Some(String::from("team")),
+            digest: None,
+            window: None,
+            limit: None,
+            filters: vec![],
+            mentions: vec![],
+        }];
+
+        let webhook_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/hooks/abc")
+                .json_body_partial(r#"{"channel": "town-square"}"#);
+            then.status(200).body("ok");
+        });
+        let send_message_mock = server.mock(|when, then| {
+            when.method(POST).path_contains("/messages");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/discord_send_message_mock_response.json");
+        });
+        let mark_item_read_mock = server.mock(|when, then| {
+            when.method(POST).path("/mark/187204");
+            then.status(200);
+        });
+
+        let mut stats = RunStats::default();
+        send_messages(
+            &config,
+            &mut State::default(),
+            &mut stats,
+            vec![get_mock_item()],
+            HashMap::new(),
+        )
+        .await
+        .expect("Did not send messages correctly");
+
+        assert_eq!(stats.posted, 1);
+        webhook_mock.assert_async().await;
+        send_message_mock.assert_hits_async(0).await;
+        mark_item_read_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_mentions_only_ping_configured_roles() {
+        let (server, mut config) = start_server();
+        config.mentions = toml::from_str::<Settings>(
+            r#"
+            [[mentions]]
+            keyword = "advisory"
+            role = "1001"
+            "#,
+        )
+        .unwrap()
+        .mentions;
+        let item = SelfossItem {
+            content: String::from("Security advisory for @everyone"),
+            ..get_mock_item()
+        };
+
+        let send_message_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/channels/my_channel_id/messages")
+                .json_body(json!({
+                    "content": "<@&1001>\nSecurity advisory for @\u{200B}everyone",
+                    "allowed_mentions": {"parse": [], "roles": ["1001"], "users": []}
+                }));
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/discord_send_message_mock_response.json");
+        });
+        server.mock(|when, then| {
+            when.method(POST).path("/mark/187204");
+            then.status(200);
+        });
+
+        let channel_map =
+            HashMap::from([(String::from("my_channel"), String::from("my_channel_id"))]);
+        let mut stats = RunStats::default();
+        send_messages(
+            &config,
+            &mut State::default(),
+            &mut stats,
+            vec![item],
+            channel_map,
+        )
+        .await
+        .expect("Did not send messages correctly");
+        send_message_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_mentions_fit_in_long_messages() {
+        let (server, mut config) = start_server();
+        config.mentions = toml::from_str::<Settings>(
+            r#"
+            [[mentions]]
+            keyword = "advisory"
+            role = "1001"
+            "#,
+        )
+        .unwrap()
+        .mentions;
+        config.timestamps = Some(toml::from_str("").unwrap());
+        config.clock = Clock::Fixed(get_mock_item().datetime);
+        let item = SelfossItem {
+            content: format!("Security advisory {}", "a".repeat(3000)),
+            ..get_mock_item()
+        };
+
+        let send_message_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/channels/my_channel_id/messages")
+                .matches(|request| {
+                    let body = request.body.as_deref().unwrap_or_default();
+                    let body: serde_json::Value = serde_json::from_slice(body).unwrap();
+                    let content = body["content"].as_str().unwrap();
+                    content.starts_with("<@&1001>\nSecurity advisory")
+                        && content.ends_with("a\n<t:1702662036:R>")
+                        && content.chars().count() == 2000
+                });
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/discord_send_message_mock_response.json");
+        });
+        server.mock(|when, then| {
+            when.method(POST).path("/mark/187204");
+            then.status(200);
+        });
+
+        let channel_map =
+            HashMap::from([(String::from("my_channel"), String::from("my_channel_id"))]);
+        send_messages(
+            &config,
+            &mut State::default(),
+            &mut RunStats::default(),
+            vec![item],
+            channel_map,
+        )
+        .await
+        .expect("Did not send messages correctly");
+        send_message_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_announcement_channels_publish_items() {
+        let (server, mut config) = start_server();
+        config.routes = toml::from_str::<Settings>(
+            r#"
+            [[routes]]
+            name = "news"
+            announcement = true
+            "#,
+        )
+        .unwrap()
+        .routes;
+
+        let create_channel_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/guilds/123/channels")
+                .json_body_partial(r#"{"name": "my_channel", "type": 5}"#);
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/discord_create_channel_mock_response.json");
+        });
+        server.mock(|when, then| {
+            when.method(POST).path("/channels/my_channel_id/messages");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/discord_send_message_mock_response.json");
+        });
+        let crosspost_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/channels/my_channel_id/messages/4242/crosspost");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/discord_send_message_mock_response.json");
+        });
+        server.mock(|when, then| {
+            when.method(POST).path("/mark/187204");
+            then.status(200);
+        });
+
+        let mut stats = RunStats::default();
+        send_messages(
+            &config,
+            &mut State::default(),
+            &mut stats,
+            vec![get_mock_item()],
+            HashMap::new(),
+        )
+        .await
+        .expect("Did not send messages correctly");
+        create_channel_mock.assert_async().await;
+        crosspost_mock.assert_async().await;
+        assert_eq!(stats.posted, 1);
+        assert_eq!(stats.published, 1);
+    }
+
+    #[tokio::test]
+    async fn test_images_are_uploaded() {
+        let (server, mut config) = start_server();
+        config.images = Some(ImageConfig::default());
+        let item = SelfossItem {
+            content: format!(
+                r#"<p>My content</p><img src="{}">"#,
+                server.url("/photo.png")
+            ),
+            ..get_mock_item()
+        };
+        let channel_map =
+            HashMap::from([(String::from("my_channel"), String::from("my_channel_id"))]);
+
+        server.mock(|when, then| {
+            when.method(GET).path("/photo.png");
+            then.status(200)
+                .header("content-type", "image/png")
+                .body("png");
+        });
+        server.mock(|when, then| {
+            when.method(POST).path("/mark/187204");
+            then.status(200);
+        });
+        let mut upload_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/channels/my_channel_id/messages")
+                .body_contains(r#"name="files[0]"; filename="image-1.png""#)
+                .body_contains(r#""attachments":[{"filename":"image-1.png","id":0}]"#);
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/discord_send_message_mock_response.json");
+        });
+        let mut stats = RunStats::default();
+        let items = vec![item.clone()];
+        send_messages(
+            &config,
+            &mut State::default(),
+            &mut stats,
+            items,
+            channel_map.clone(),
+        )
+        .await
+        .expect("Did not send messages correctly");
+        assert_eq!(stats.posted, 1);
+        upload_mock.assert_async().await;
+        upload_mock.delete();
+
+        // Without the images if Discord does not accept them.
+        let too_large_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/channels/my_channel_id/messages")
+                .body_contains("payload_json");
+            then.status(413)
+                .json_body(json!({"message": "Request entity too large", "code": 40005}));
+        });
+        let send_message_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/channels/my_channel_id/messages")
+                .json_body_partial(r#"{"content": "My content"}"#);
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/discord_send_message_mock_response.json");
+        });
+        let mut stats = RunStats::default();
+        send_messages(
+            &config,
+            &mut State::default(),
+            &mut stats,
+            vec![item],
+            channel_map,
+        )
+        .await
+        .expect("Did not send messages correctly");
+        assert_eq!(stats.posted, 1);
+        too_large_mock.assert_async().await;
+        send_message_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_send_digest_and_mark_read() {
+        let (server, mut config) = start_server();
+        config.routes = vec![Route {
+            name: String::from("news"),
+            sources: vec![],
+            channel: None,
+            selfoss: None,
+            guild: None,
+            category: None,
+            topic: None,
+            announcement: false,
+            sink: None,
+            filters: vec![],
+            mentions: vec![],
+            digest: Some(DigestConfig {
+                schedule: DigestSchedule::Hourly,
+                at: None,
+                timezone: Tz::UTC,
+            }),
+            window: None,
+            limit: None,
+        }];
+
+        let send_message_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/channels/my_channel_id/messages")
+                .json_body_partial(r#"{"content": "**my_channel**\n- [My title](My link)\n- [My title](My link)"}"#);
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/discord_send_message_mock_response.json");
+        });
+
+        let mark_item_read_mock = server.mock(|when, then| {
+            when.method(POST).path("/mark/187204");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body("");
+        });
+
+        let channel_map =
+            HashMap::from([(String::from("my_channel"), String::from("my_channel_id"))]);
+        let mut state = State::default();
+        let item_list = vec![get_mock_item(); 2];
+        let mut stats = RunStats::default();
+        send_messages(
+            &config,
+            &mut state,
+            &mut stats,
+            item_list.clone(),
+            channel_map.clone(),
+        )
+        .await
+        .expect("Did not send digest correctly");
+
+        send_message_mock.assert_hits_async(1).await;
+        mark_item_read_mock.assert_hits_async(2).await;
+        assert!(state.digests.contains_key("news"));
+
+        // The digest was just sent, so the next run holds the items back.
+        send_messages(&config, &mut state, &mut stats, item_list, channel_map)
+            .await
+            .expect("Did not hold back digest correctly");
+        send_message_mock.assert_hits_async(1).await;
+        mark_item_read_mock.assert_hits_async(2).await;
+    }
+
+    fn parse(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().into()
+    }
+
+    fn windowed_routes(threshold: &str) -> Vec<Route> {
+        toml::from_str::<Settings>(&format!(
+            r#"
+            [[routes]]
+            name = "news"
+            [routes.window]
+            days = ["mon", "tue", "wed", "thu", "fri"]
+            start = "08:00:00"
+            end = "20:00:00"
+            {}
+            "#,
+            threshold
+        ))
+        .unwrap()
+        .routes
+    }
+
+    #[tokio::test]
+    async fn test_items_wait_for_delivery_window() {
+        let (server, mut config) = start_server();
+        config.routes = windowed_routes("");
+        config.clock = Clock::Fixed(parse("2023-12-16T12:00:00Z"));
+
+        let send_message_mock = server.mock(|when, then| {
+            when.method(POST).path("/channels/my_channel_id/messages");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/discord_send_message_mock_response.json");
+        });
+        let mark_item_read_mock = server.mock(|when, then| {
+            when.method(POST).path("/mark/187204");
+            then.status(200);
+        });
+
+        let channel_map =
+            HashMap::from([(String::from("my_channel"), String::from("my_channel_id"))]);
+        let mut state = State::default();
+        let mut stats = RunStats::default();
+        send_messages(
+            &config,
+            &mut state,
+            &mut stats,
+            vec![get_mock_item()],
+            channel_map.clone(),
+        )
+        .await
+        .expect("Did not queue messages correctly");
+        assert_eq!(stats.held_back, 1);
+        assert_eq!(state.queued.len(), 1);
+        send_message_mock.assert_hits_async(0).await;
+        mark_item_read_mock.assert_hits_async(0).await;
+
+        // The item is still unread, so it is fetched again when the window opens on Monday.
+        config.clock = Clock::Fixed(parse("2023-12-18T09:00:00Z"));
+        let mut stats = RunStats::default();
+        send_messages(
+            &config,
+            &mut state,
+            &mut stats,
+            vec![get_mock_item()],
+            channel_map,
+        )
+        .await
+        .expect("Did not release messages correctly");
+        assert_eq!(stats.posted, 1);
+        assert!(state.queued.is_empty());
+        send_message_mock.assert_async().await;
+        mark_item_read_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_delivered_items_are_dequeued_before_fatal_error() {
+        let (server, mut config) = start_server();
+        config.routes = windowed_routes("");
+        config.clock = Clock::Fixed(parse("2023-12-18T09:00:00Z"));
+
+        let unauthorized_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/channels/my_channel_id/messages")
+                .body_contains("Other content");
+            then.status(401);
+        });
+        let send_message_mock = server.mock(|when, then| {
+            when.method(POST).path("/channels/my_channel_id/messages");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/discord_send_message_mock_response.json");
+        });
+        let mark_item_read_mock = server.mock(|when, then| {
+            when.method(POST).path_contains("/mark/");
+            then.status(200);
+        });
+
+        let other = SelfossItem {
+            id: 187205,
+            content: String::from("Other content"),
+            ..get_mock_item()
+        };
+        let mut state = State::default();
+        for item in [get_mock_item(), other] {
+            state.queued.push(QueuedItem {
+                route: String::from("news"),
+                item,
+                queued_at: parse("2023-12-16T12:00:00Z"),
+            });
+        }
+        let channel_map =
+            HashMap::from([(String::from("my_channel"), String::from("my_channel_id"))]);
+        let mut stats = RunStats::default();
+        let result = send_messages(&config, &mut state, &mut stats, vec![], channel_map).await;
+        assert!(result.is_err_and(|e| e.is_fatal()));
+        send_message_mock.assert_async().await;
+        unauthorized_mock.assert_async().await;
+        mark_item_read_mock.assert_async().await;
+        let queued: Vec<u64> = state.queued.iter().map(|q| q.item.id).collect();
+        assert_eq!(queued, vec![187205]);
+    }
+
+    #[tokio::test]
+    async fn test_queued_items_are_collapsed_into_digest() {
+        let (server, mut config) = start_server();
+        config.routes = windowed_routes("digest_threshold = 1");
+        config.clock = Clock::Fixed(parse("2023-12-18T09:00:00Z"));
+
+        let send_message_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/channels/my_channel_id/messages")
+                .json_body_partial(
+                    r#"{"content": "**my_channel**\n- [My title](My link)\n- [Other title](My link)"}"#,
+                );
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/discord_send_message_mock_response.json");
+        });
+        let mark_item_read_mock = server.mock(|when, then| {
+            when.method(POST).path_contains("/mark/");
+            then.status(200);
+        });
+
+        let other = SelfossItem {
+            id: 187205,
+            title: String::from("Other title"),
+            ..get_mock_item()
+        };
+        let mut state = State::default();
+        for item in [get_mock_item(), other] {
+            state.queued.push(QueuedItem {
+                route: String::from("news"),
+                item,
+                queued_at: parse("2023-12-16T12:00:00Z"),
+            });
+        }
+        let channel_map =
+            HashMap::from([(String::from("my_channel"), String::from("my_channel_id"))]);
+        let mut stats = RunStats::default();
+        send_messages(&config, &mut state, &mut stats, vec![], channel_map)
+            .await
+            .expect("Did not release messages correctly");
+        send_message_mock.assert_async().await;
+        mark_item_read_mock.assert_hits_async(2).await;
+        assert_eq!(stats.posted, 2);
+        assert!(state.queued.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_items_over_limit_are_summarized() {
+        let (server, mut config) = start_server();
+        config.routes = toml::from_str::<Settings>(
+            r#"
+            [[routes]]
+            name = "news"
+            [routes.limit]
+            channel = { items = 2, minutes = 60 }
+            overflow = "summarize"
+            "#,
+        )
+        .unwrap()
+        .routes;
+
+        let send_message_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/channels/my_channel_id/messages")
+                .json_body_partial(r#"{"content": "My content"}"#);
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/discord_send_message_mock_response.json");
+        });
+        let summary_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/channels/my_channel_id/messages")
+                .body_contains("and 3 more:");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/discord_send_message_mock_response.json");
+        });
+        let mark_item_read_mock = server.mock(|when, then| {
+            when.method(POST).path_contains("/mark/");
+            then.status(200);
+        });
+
+        let items: Vec<SelfossItem> = (0..5)
+            .map(|i| SelfossItem {
+                id: 187204 + i,
+                ..get_mock_item()
+            })
+            .collect();
+        let channel_map =
+            HashMap::from([(String::from("my_channel"), String::from("my_channel_id"))]);
+        let mut state = State::default();
+        let mut stats = RunStats::default();
+        send_messages(&config, &mut state, &mut stats, items, channel_map)
+            .await
+            .expect("Did not send messages correctly");
+        send_message_mock.assert_hits_async(2).await;
+        summary_mock.assert_async().await;
+        mark_item_read_mock.assert_hits_async(5).await;
+        assert_eq!(stats.posted, 5);
+        assert_eq!(state.deliveries.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_old_items_are_skipped() {
+        let (server, mut config) = start_server();
+        config.max_item_age_hours = Some(24);
+        config.mark_old_as_read = true;
+        config.clock = Clock::Fixed(parse("2023-12-16T18:00:00Z"));
+
+        let send_message_mock = server.mock(|when, then| {
+            when.method(POST).path("/channels/my_channel_id/messages");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/discord_send_message_mock_response.json");
+        });
+        let mark_item_read_mock = server.mock(|when, then| {
+            when.method(POST).path_contains("/mark/");
+            then.status(200);
+        });
+
+        let recent = SelfossItem {
+            id: 187205,
+            datetime: parse("2023-12-15T20:00:00Z"),
+            ..get_mock_item()
+        };
+        let channel_map =
+            HashMap::from([(String::from("my_channel"), String::from("my_channel_id"))]);
+        let mut stats = RunStats::default();
+        send_messages(
+            &config,
+            &mut State::default(),
+            &mut stats,
+            vec![get_mock_item(), recent],
+            channel_map,
+        )
+        .await
+        .expect("Did not send messages correctly");
+        send_message_mock.assert_async().await;
+        mark_item_read_mock.assert_hits_async(2).await;
+        assert_eq!(stats.filtered, 1);
+        assert_eq!(stats.posted, 1);
+    }
+
+    #[tokio::test]
+    async fn test_messages_show_publication_time() {
+        let (server, mut config) = start_server();
+        config.timestamps = Some(toml::from_str("delay_note_hours = 12").unwrap());
+        config.clock = Clock::Fixed(parse("2023-12-16T07:00:00Z"));
+
+        let send_message_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/channels/my_channel_id/messages")
+                .json_body_partial(
+                    r#"{"content": "My content\n<t:1702662036:R> (published 13 hours before delivery)"}"#,
+                );
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/discord_send_message_mock_response.json");
+        });
+        let mark_item_read_mock = server.mock(|when, then| {
+            when.method(POST).path("/mark/187204");
+            then.status(200);
+        });
+
+        let channel_map =
+            HashMap::from([(String::from("my_channel"), String::from("my_channel_id"))]);
+        send_messages(
+            &config,
+            &mut State::default(),
+            &mut RunStats::default(),
+            vec![get_mock_item()],
+            channel_map,
+        )
+        .await
+        .expect("Did not send messages correctly");
+        send_message_mock.assert_async().await;
+        mark_item_read_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_filtered_items_are_marked_read() {
+        let (server, mut config) = start_server();
+        config.mark_filtered_as_read = true;
+        config.filters = toml::from_str::<Settings>(
+            r#"
+            [[filters]]
+            action = "exclude"
+            field = "content"
+            keyword = "content"
+            "#,
+        )
+        .unwrap()
+        .filters;
+
+        let send_message_mock = server.mock(|when, then| {
+            when.method(POST).path("/channels/my_channel_id/messages");
+            then.status(200);
+        });
+        let mark_item_read_mock = server.mock(|when, then| {
+            when.method(POST).path("/mark/187204");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body("");
+        });
+
+        let channel_map =
+            HashMap::from([(String::from("my_channel"), String::from("my_channel_id"))]);
+        let mut stats = RunStats::default();
+        send_messages(
+            &config,
+            &mut State::default(),
+            &mut stats,
+            vec![get_mock_item()],
+            channel_map,
+        )
+        .await
+        .expect("Did not filter messages correctly");
+
+        send_message_mock.assert_hits_async(0).await;
+        mark_item_read_mock.assert_async().await;
+        assert_eq!(
+            stats,
+            RunStats {
+                fetched: 1,
+                filtered: 1,
+                marked_read: 1,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invalid_state_fails_run() {
+        let (_server, mut config) = start_server();
+        let path = env::temp_dir().join(format!(
+            "selfoss-discord-invalid-{}.json",
+            std::process::id()
+        ));
+        fs::write(&path, "not json").unwrap();
+        config.state_path = path.to_string_lossy().into_owned();
+
+        let (_, result) = run(&config).await;
+        assert!(matches!(result, Err(AppError::State(_))));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_duplicates_are_annotated() {
+        let (server, mut config) = start_server();
+        config.dedup = Some(DedupConfig {
+            window_hours: 24,
+            title_similarity: 0.9,
+            action: DedupAction::Annotate,
+        });
+        config.clock = Clock::Fixed(parse("2023-12-16T12:00:00Z"));
+
+        let send_message_mock = server.mock(|when, then| {
+            when.method(POST).path("/channels/my_channel_id/messages");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/discord_send_message_mock_response.json");
+        });
+        let edit_message_mock = server.mock(|when, then| {
+            when.method(PATCH)
+                .path("/channels/my_channel_id/messages/4242")
+                .json_body_partial(r#"{"content": "My content\n\nalso in: another_channel"}"#);
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/discord_send_message_mock_response.json");
+        });
+        let mark_item_read_mock = server.mock(|when, then| {
+            when.method(POST).path_contains("/mark/");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body("");
+        });
+
+        let mut duplicate = get_mock_item();
+        duplicate.id = 187205;
+        duplicate.sourcetitle = String::from("another_channel");
+        duplicate.link = String::from("My Link");
+
+        let channel_map =
+            HashMap::from([(String::from("my_channel"), String::from("my_channel_id"))]);
+        let mut state = State::default();
+        let mut stats = RunStats::default();
+        send_messages(
+            &config,
+            &mut state,
+            &mut stats,
+            vec![get_mock_item(), duplicate],
+            channel_map,
+        )
+        .await
+        .expect("Did not deduplicate messages correctly");
+
+        send_message_mock.assert_async().await;
+        edit_message_mock.assert_async().await;
+        mark_item_read_mock.assert_hits_async(2).await;
+        assert_eq!(stats.duplicates, 1);
+        assert_eq!(state.posted[0].also_in, vec!["another_channel"]);
+        assert_eq!(state.posted[0].posted_at, parse("2023-12-16T12:00:00Z"));
+    }
+
+    #[tokio::test]
+    async fn test_failed_items_wait_for_backoff() {
+        let (server, config) = start_server();
+
+        let send_message_mock = server.mock(|when, then| {
+            when.method(POST).path("/channels/my_channel_id/messages");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/discord_send_message_mock_response.json");
+        });
+        let mark_item_read_mock = server.mock(|when, then| {
+            when.method(POST).path("/mark/187204");
+            then.status(200).body("");
+        });
+
+        let channel_map =
+            HashMap::from([(String::from("my_channel"), String::from("my_channel_id"))]);
+        let mut state = State::default();
+        let mut stats = RunStats::default();
+        state.record_failure(
+            &get_mock_item(),
+            String::from("error"),
+            true,
+            false,
+            Utc::now(),
+        );
+
+        send_messages(
+            &config,
+            &mut state,
+            &mut stats,
+            vec![get_mock_item()],
+            channel_map.clone(),
+        )
+        .await
+        .expect("Did not hold back failed item");
+        send_message_mock.assert_hits_async(0).await;
+        assert_eq!(stats.held_back, 1);
+
+        state.dead_letters[0].next_attempt_at = Utc::now();
+        send_messages(
+            &config,
+            &mut state,
+            &mut stats,
+            vec![get_mock_item()],
+            channel_map,
+        )
+        .await
+        .expect("Did not retry failed item");
+        send_message_mock.assert_hits_async(1).await;
+        mark_item_read_mock.assert_hits_async(1).await;
+        assert!(state.dead_letters.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_posted_items_are_not_posted_again() {
+        let (server, config) = start_server();
+
+        let send_message_mock = server.mock(|when, then| {
+            when.method(POST).path("/channels/my_channel_id/messages");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/discord_send_message_mock_response.json");
+        });
+        let mut mark_item_read_mock = server.mock(|when, then| {
+            when.method(POST).path("/mark/187204");
+            then.status(500).body("Internal Server Error");
+        });
+
+        let channel_map =
+            HashMap::from([(String::from("my_channel"), String::from("my_channel_id"))]);
+        let mut state = State::default();
+        let mut stats = RunStats::default();
+        send_messages(
+            &config,
+            &mut state,
+            &mut stats,
+            vec![get_mock_item()],
+            channel_map.clone(),
+        )
+        .await
+        .expect("Error sending messages");
+        send_message_mock.assert_async().await;
+        mark_item_read_mock.assert_async().await;
+        assert_eq!(stats.posted, 1);
+        assert_eq!(stats.failed, 1);
+        assert!(state.dead_letter(187204).unwrap().posted);
+
+        // The retry only marks the item as read.
+        mark_item_read_mock.delete_async().await;
+        mark_item_read_mock = server.mock(|when, then| {
+            when.method(POST).path("/mark/187204");
+            then.status(200).body("");
+        });
+        state.dead_letters[0].next_attempt_at = Utc::now();
+        send_messages(
+            &config,
+            &mut state,
+            &mut stats,
+            vec![get_mock_item()],
+            channel_map,
+        )
+        .await
+        .expect("Did not retry failed item");
+        send_message_mock.assert_hits_async(1).await;
+        mark_item_read_mock.assert_async().await;
+        assert_eq!(stats.marked_read, 1);
+        assert!(state.dead_letters.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_discord_errors_are_handled_per_variant() {
+        let (server, config) = start_server();
+
+        let missing_permissions_mock = server.mock(|when, then| {
+            when.method(POST).path("/channels/my_channel_id/messages");
+            then.status(403)
+                .header("content-type", "application/json")
+                .body(r#"{"message": "Missing Permissions", "code": 50013}"#);
+        });
+
+        let channel_map =
+            HashMap::from([(String::from("my_channel"), String::from("my_channel_id"))]);
+        let mut state = State::default();
+        let mut stats = RunStats::default();
+        send_messages(
+            &config,
+            &mut state,
+            &mut stats,
+            vec![get_mock_item()],
+            channel_map.clone(),
+        )
+        .await
+        .expect("Missing permissions aborted the run");
+
+        // Retrying will not help until the permissions are fixed.
+        let dead_letter = state.dead_letter(187204).unwrap();
+        assert!(!dead_letter.retryable);
+        assert!(dead_letter
+            .error
+            .contains("check the permissions of the bot"));
+        missing_permissions_mock.delete_async().await;
+
+        server.mock(|when, then| {
+            when.method(POST).path("/channels/my_channel_id/messages");
+            then.status(401)
+                .header("content-type", "application/json")
+                .body(r#"{"message": "401: Unauthorized", "code": 0}"#);
+        });
+        let mut item = get_mock_item();
+        item.id = 187205;
+        let error = send_messages(&config, &mut state, &mut stats, vec![item], channel_map)
+            .await
+            .expect_err("Invalid token did not abort the run");
+        assert!(matches!(error, RequestError::Unauthorized(_)));
+    }
+
+    #[tokio::test]
+    async fn test_deleted_channel_is_resolved_again() {
+        let (server, config) = start_server();
+
+        let deleted_channel_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/channels/deleted_channel_id/messages");
+            then.status(404)
+                .header("content-type", "application/json")
+                .body(r#"{"message": "Unknown Channel", "code": 10003}"#);
+        });
+        let get_channels_mock = server.mock(|when, then| {
+            when.method(GET).path("/guilds/123/channels");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/discord_items_mock_response.json");
+        });
+        let send_message_mock = server.mock(|when, then| {
+            when.method(POST).path("/channels/my_channel_id/messages");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/discord_send_message_mock_response.json");
+        });
+        let mark_item_read_mock = server.mock(|when, then| {
+            when.method(POST).path("/mark/187204");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(r#"{"success": true}"#);
+        });
+
+        let channel_map = HashMap::from([(
+            String::from("my_channel"),
+            String::from("deleted_channel_id"),
+        )]);
+        let mut state = State::default();
+        let mut stats = RunStats::default();
+        send_messages(
+            &config,
+            &mut state,
+            &mut stats,
+            vec![get_mock_item()],
+            channel_map,
+        )
+        .await
+        .expect("Error sending messages");
+
+        deleted_channel_mock.assert_async().await;
+        get_channels_mock.assert_async().await;
+        send_message_mock.assert_async().await;
+        mark_item_read_mock.assert_async().await;
+        assert_eq!(stats.posted, 1);
+        assert!(state.dead_letters.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_deleted_channel_unsubscribes() {
+        let (server, mut config) = start_server();
+        config.on_channel_deleted = ChannelDeletedAction::Unsubscribe;
+
+        let deleted_channel_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/channels/deleted_channel_id/messages");
+            then.status(404)
+                .header("content-type", "application/json")
+                .body(r#"{"message": "Unknown Channel", "code": 10003}"#);
+        });
+        let mark_item_read_mock = server.mock(|when, then| {
+            when.method(POST).path_contains("/mark/18720");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(r#"{"success": true}"#);
+        });
+
+        let channel_map = HashMap::from([(
+            String::from("my_channel"),
+            String::from("deleted_channel_id"),
+        )]);
+        let mut second_item = get_mock_item();
+        second_item.id = 187205;
+        let mut state = State::default();
+        let mut stats = RunStats::default();
+        send_messages(
+            &config,
+            &mut state,
+            &mut stats,
+            vec![get_mock_item(), second_item],
+            channel_map,
+        )
+        .await
+        .expect("Error sending messages");
+
+        deleted_channel_mock.assert_async().await;
+        mark_item_read_mock.assert_hits_async(2).await;
+        assert_eq!(state.unsubscribed, vec![String::from("my_channel")]);
+        assert_eq!(stats.filtered, 2);
+        assert_eq!(stats.posted, 0);
+    }
 }
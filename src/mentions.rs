@@ -0,0 +1,119 @@
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::{
+    discord::models::AllowedMentions,
+    filters::{deserialize_regex, text_matches, FilterField},
+    selfoss::models::SelfossItem,
+};
+
+/// Pings a role or user when the `field` of an item contains `keyword` (case-insensitive) or
+/// matches `regex`, or for every item without either of them.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Mention {
+    /// The title and the content if not set.
+    pub field: Option<FilterField>,
+    pub keyword: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_regex")]
+    pub regex: Option<Regex>,
+    /// Id of the role that is mentioned.
+    pub role: Option<String>,
+    /// Id of the user that is mentioned.
+    pub user: Option<String>,
+}
+
+impl Mention {
+    pub fn matches(&self, item: &SelfossItem) -> bool {
+        if self.keyword.is_none() && self.regex.is_none() {
+            return true;
+        }
+        let matches_value =
+            |value: &str| text_matches(self.keyword.as_ref(), self.regex.as_ref(), value);
+        match self.field {
+            Some(field) => field.matches(item, matches_value),
+            None => [FilterField::Title, FilterField::Content]
+                .into_iter()
+                .any(|field| field.matches(item, matches_value)),
+        }
+    }
+}
+
+/// The roles and users of the mentions that match `item`, which are the only ones its message
+/// is allowed to ping.
+pub fn allowed_mentions<'a>(
+    mentions: impl Iterator<Item = &'a Mention>,
+    item: &SelfossItem,
+) -> AllowedMentions {
+    let mut allowed = AllowedMentions::default();
+    for mention in mentions.filter(|m| m.matches(item)) {
+        for (id, ids) in [
+            (&mention.role, &mut allowed.roles),
+            (&mention.user, &mut allowed.users),
+        ] {
+            if let Some(id) = id.as_ref().filter(|id| !ids.contains(id)) {
+                ids.push(id.clone());
+            }
+        }
+    }
+    allowed
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        config::Settings, mentions::allowed_mentions, selfoss::models::SelfossItem,
+        test::get_mock_item,
+    };
+
+    #[test]
+    fn test_allowed_mentions() {
+        let settings: Settings = toml::from_str(
+            r#"
+            [[mentions]]
+            keyword = "security advisory"
+            role = "1001"
+
+            [[mentions]]
+            field = "tags"
+            regex = "^news$"
+            role = "1001"
+            user = "2002"
+
+            [[mentions]]
+            field = "author"
+            keyword = "someone else"
+            user = "3003"
+            "#,
+        )
+        .unwrap();
+
+        let item = SelfossItem {
+            content: String::from("<p>A Security Advisory was published</p>"),
+            ..get_mock_item()
+        };
+        let allowed = allowed_mentions(settings.mentions.iter(), &item);
+        assert_eq!(allowed.roles, vec!["1001"]);
+        assert_eq!(allowed.users, vec!["2002"]);
+        assert!(allowed.parse.is_empty());
+        assert_eq!(allowed.prefix(), "<@&1001> <@2002>");
+
+        let item = SelfossItem {
+            tags: vec![],
+            ..get_mock_item()
+        };
+        let allowed = allowed_mentions(settings.mentions.iter(), &item);
+        assert_eq!(allowed, Default::default());
+        assert_eq!(allowed.prefix(), "");
+
+        // Without a keyword or regex, every item pings.
+        let settings: Settings = toml::from_str(
+            r#"
+            [[mentions]]
+            role = "4004"
+            "#,
+        )
+        .unwrap();
+        let allowed = allowed_mentions(settings.mentions.iter(), &item);
+        assert_eq!(allowed.roles, vec!["4004"]);
+    }
+}
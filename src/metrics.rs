@@ -0,0 +1,238 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use chrono::Utc;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, TextEncoder,
+};
+use serde::Serialize;
+
+use crate::{config::DEFAULT_NAME, stats::RunStats};
+
+/// Outcome of the most recent Selfoss fetch and Discord post, `None` until one happened.
+#[derive(Serialize, Debug, Default, Clone, Copy, PartialEq)]
+pub struct Health {
+    pub selfoss_ok: Option<bool>,
+    pub discord_ok: Option<bool>,
+}
+
+impl Health {
+    /// Healthy unless the last fetch or post failed.
+    pub fn is_healthy(&self) -> bool {
+        self.selfoss_ok != Some(false) && self.discord_ok != Some(false)
+    }
+
+    /// Ready once items have been fetched successfully and posting is not failing.
+    pub fn is_ready(&self) -> bool {
+        self.selfoss_ok == Some(true) && self.discord_ok != Some(false)
+    }
+}
+
+/// The metrics of all bridges, which are served together.
+struct Registry {
+    registry: prometheus::Registry,
+    items: IntCounterVec,
+    runs: IntCounterVec,
+    discord_ratelimits: IntCounterVec,
+    discord_ratelimit_wait_seconds: IntCounterVec,
+    request_duration: HistogramVec,
+    last_successful_poll: IntGaugeVec,
+    /// Health of every bridge, by name.
+    health: Mutex<BTreeMap<String, Health>>,
+}
+
+/// Records metrics labelled with the name of a bridge, or of all bridges that share a feed reader.
+/// Every handle made with `for_bridges` shares the registry of the handle it was made from.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Arc<Registry>,
+    bridges: Vec<String>,
+}
+
+impl Metrics {
+    /// A new registry, recording for the bridge `default`.
+    pub fn new() -> Self {
+        let items = IntCounterVec::new(
+            Opts::new("selfoss_discord_items_total", "Items by outcome"),
+            &["bridge", "outcome"],
+        )
+        .unwrap();
+        let runs = IntCounterVec::new(
+            Opts::new("selfoss_discord_runs_total", "Sync runs by result"),
+            &["bridge", "result"],
+        )
+        .unwrap();
+        let discord_ratelimits = IntCounterVec::new(
+            Opts::new(
+                "selfoss_discord_discord_ratelimited_total",
+                "Discord responses with status 429",
+            ),
+            &["bridge"],
+        )
+        .unwrap();
+        let discord_ratelimit_wait_seconds = IntCounterVec::new(
+            Opts::new(
+                "selfoss_discord_discord_ratelimit_wait_seconds_total",
+                "Seconds Discord asked us to wait before retrying",
+            ),
+            &["bridge"],
+        )
+        .unwrap();
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "selfoss_discord_request_duration_seconds",
+                "Latency of requests to Selfoss and Discord",
+            ),
+            &["bridge", "service"],
+        )
+        .unwrap();
+        let last_successful_poll = IntGaugeVec::new(
+            Opts::new(
+                "selfoss_discord_last_successful_poll_timestamp_seconds",
+                "Unix time of the last successful Selfoss fetch",
+            ),
+            &["bridge"],
+        )
+        .unwrap();
+
+        let registry = prometheus::Registry::new();
+        registry.register(Box::new(items.clone())).unwrap();
+        registry.register(Box::new(runs.clone())).unwrap();
+        registry
+            .register(Box::new(discord_ratelimits.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(discord_ratelimit_wait_seconds.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(request_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(last_successful_poll.clone()))
+            .unwrap();
+
+        Metrics {
+            registry: Arc::new(Registry {
+                registry,
+                items,
+                runs,
+                discord_ratelimits,
+                discord_ratelimit_wait_seconds,
+                request_duration,
+                last_successful_poll,
+                health: Mutex::new(BTreeMap::new()),
+            }),
+            bridges: vec![String::from(DEFAULT_NAME)],
+        }
+    }
+
+    /// A handle that records for `bridges`, which count towards the health from now on.
+    pub fn for_bridges(&self, bridges: &[String]) -> Self {
+        let mut health = self.registry.health.lock().unwrap();
+        for bridge in bridges {
+            health.entry(bridge.clone()).or_default();
+        }
+        Metrics {
+            registry: self.registry.clone(),
+            bridges: bridges.to_vec(),
+        }
+    }
+
+    fn update_health(&self, update: impl Fn(&mut Health)) {
+        let mut health = self.registry.health.lock().unwrap();
+        for bridge in &self.bridges {
+            update(health.entry(bridge.clone()).or_default());
+        }
+    }
+
+    pub fn record_selfoss_fetch(&self, ok: bool) {
+        self.update_health(|health| health.selfoss_ok = Some(ok));
+        if ok {
+            for bridge in &self.bridges {
+                self.registry
+                    .last_successful_poll
+                    .with_label_values(&[bridge])
+                    .set(Utc::now().timestamp());
+            }
+        }
+    }
+
+    pub fn record_discord_post(&self, ok: bool) {
+        self.update_health(|health| health.discord_ok = Some(ok));
+    }
+
+    /// Counts a 429 response, and the seconds Discord asked to wait if it said so.
+    pub fn record_ratelimit(&self, wait_seconds: Option<u64>) {
+        for bridge in &self.bridges {
+            self.registry
+                .discord_ratelimits
+                .with_label_values(&[bridge])
+                .inc();
+            if let Some(secs) = wait_seconds {
+                self.registry
+                    .discord_ratelimit_wait_seconds
+                    .with_label_values(&[bridge])
+                    .inc_by(secs);
+            }
+        }
+    }
+
+    /// Records the latency of a request to `service`, `selfoss` or `discord`.
+    pub fn observe_request(&self, service: &str, seconds: f64) {
+        for bridge in &self.bridges {
+            self.registry
+                .request_duration
+                .with_label_values(&[bridge, service])
+                .observe(seconds);
+        }
+    }
+
+    pub fn record_run(&self, stats: &RunStats, ok: bool) {
+        let outcomes = [
+            ("fetched", stats.fetched),
+            ("posted", stats.posted),
+            ("filtered", stats.filtered),
+            ("duplicate", stats.duplicates),
+            ("held_back", stats.held_back),
+            ("failed", stats.failed),
+            ("marked_read", stats.marked_read),
+            ("published", stats.published),
+            ("publish_failed", stats.publish_failed),
+        ];
+        let result = if ok { "success" } else { "error" };
+        for bridge in &self.bridges {
+            for (outcome, count) in outcomes {
+                self.registry
+                    .items
+                    .with_label_values(&[bridge, outcome])
+                    .inc_by(count as u64);
+            }
+            self.registry
+                .runs
+                .with_label_values(&[bridge, result])
+                .inc();
+        }
+    }
+
+    /// The health of every bridge, by name.
+    pub fn health(&self) -> BTreeMap<String, Health> {
+        self.registry.health.lock().unwrap().clone()
+    }
+
+    /// Renders the metrics of all bridges in the Prometheus text format.
+    pub fn encode(&self) -> String {
+        let mut buffer = vec![];
+        TextEncoder::new()
+            .encode(&self.registry.registry.gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
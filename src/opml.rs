@@ -0,0 +1,535 @@
+//! Imports the feeds of an OPML export, e.g. when migrating from another feed reader.
+//!
+//! Every folder becomes a category with a channel per feed. The feeds are routed to their channels
+//! in the settings file, or subscribed to in Selfoss.
+
+use std::fs;
+
+use serde::Serialize;
+use tracing::info;
+
+use crate::{
+    cli::ImportOpmlArgs,
+    commands::{lines, print},
+    config::Config,
+    discord::{
+        adapter::{create_channel, get_channels},
+        errors::RequestError,
+        models::{ChannelOptions, DiscordChannel, GUILD_CATEGORY},
+    },
+    errors::AppError,
+    selfoss::adapter::{add_source, get_sources},
+    source_sync::find_or_create_category,
+    sources::SourceError,
+};
+
+/// A feed of an OPML file.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct OpmlFeed {
+    pub title: String,
+    pub url: String,
+    /// The folder that directly contains the feed.
+    pub folder: Option<String>,
+}
+
+/// What `import-opml` does for a feed.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ImportedFeed {
+    #[serde(flatten)]
+    pub feed: OpmlFeed,
+    pub channel: String,
+    /// Whether the channel is created, and otherwise exists already.
+    pub new_channel: bool,
+    /// Whether the feed is routed or subscribed to, which is skipped if it already is.
+    pub added: bool,
+}
+
+/// Returns the feeds in the outlines of an OPML file, in order.
+pub fn parse(contents: &str) -> Result<Vec<OpmlFeed>, String> {
+    let document = roxmltree::Document::parse(contents).map_err(|e| e.to_string())?;
+    let title = |node: roxmltree::Node| {
+        node.attribute("title")
+            .or(node.attribute("text"))
+            .map(str::trim)
+            .filter(|t| !t.is_empty())
+            .map(str::to_string)
+    };
+    Ok(document
+        .descendants()
+        .filter(|node| node.has_tag_name("outline"))
+        .filter_map(|node| {
+            let url = node.attribute("xmlUrl")?.trim().to_string();
+            let folder = node
+                .ancestors()
+                .skip(1)
+                .find(|a| a.has_tag_name("outline"))
+                .and_then(title);
+            Some(OpmlFeed {
+                title: title(node).unwrap_or_else(|| url.clone()),
+                url,
+                folder,
+            })
+        })
+        .collect())
+}
+
+/// A TOML route that sends the items of `feed` to its channel.
+fn route(feed: &ImportedFeed, category: Option<&String>) -> String {
+    let string = |s: &str| toml::Value::String(s.to_string()).to_string();
+    let mut route = format!(
+        "\n[[routes]]\nname = {}\nsources = [{}]\nchannel = {}\n",
+        string(&feed.feed.title),
+        string(&feed.feed.title),
+        string(&feed.channel)
+    );
+    if let Some(category) = category {
+        route += &format!("category = {}\n", string(category));
+    }
+    route
+}
+
+/// The byte offset in the settings file `contents` of the route at `index`, including the comment
+/// lines right above it.
+fn route_offset(contents: &str, index: usize) -> Option<usize> {
+    let mut offset = 0;
+    let mut comment_start = None;
+    let mut routes = 0;
+    for line in contents.split_inclusive('\n') {
+        let trimmed = line.trim();
+        if trimmed == "[[routes]]" {
+            if routes == index {
+                return Some(comment_start.unwrap_or(offset));
+            }
+            routes += 1;
+        }
+        match trimmed.starts_with('#') {
+            true => comment_start = comment_start.or(Some(offset)),
+            false => comment_start = None,
+        }
+        offset += line.len();
+    }
+    None
+}
+
+/// Writes the imported `routes` to the settings file, before the first route that matches all
+/// sources so that they are used.
+fn write_routes(
+    settings_path: &str,
+    source: &str,
+    routes: &[String],
+    offset: Option<usize>,
+) -> Result<(), AppError> {
+    let error =
+        |e: std::io::Error| AppError::Import(format!("Could not write {}: {}", settings_path, e));
+    let mut contents = fs::read_to_string(settings_path).map_err(error)?;
+    let imported = format!("# Imported from {}\n{}", source, routes.concat());
+    match offset {
+        Some(offset) => contents.insert_str(offset, &format!("{}\n", imported)),
+        None => contents += &format!("\n{}", imported),
+    }
+    fs::write(settings_path, contents).map_err(error)
+}
+
+/// Creates a category per folder and a channel per feed of an OPML file, and routes the feeds to
+/// their channels in the settings file or subscribes to them in Selfoss.
+pub async fn import_opml(
+    config: &Config,
+    args: &ImportOpmlArgs,
+    settings_path: Option<&str>,
+    json: bool,
+) -> Result<(), AppError> {
+    if let (true, Some(source)) = (args.subscribe, &config.source) {
+        let message = "Subscribing to feeds is only supported for Selfoss";
+        return Err(RequestError::Source(SourceError::new(source.name(), message)).into());
+    }
+    if !args.subscribe && settings_path.is_none() {
+        return Err(AppError::Import(String::from(
+            "Routes are written to the settings file, set one with --config or CONFIG_PATH",
+        )));
+    }
+    let contents = fs::read_to_string(&args.path)
+        .map_err(|e| AppError::Import(format!("Could not read {}: {}", args.path, e)))?;
+    let feeds = parse(&contents)
+        .map_err(|e| AppError::Import(format!("Invalid OPML file {}: {}", args.path, e)))?;
+
+    let existing: Vec<String> = match args.subscribe {
+        true => get_sources(config)
+            .await
+            .map_err(RequestError::from)?
+            .into_iter()
+            .map(|source| source.title)
+            .collect(),
+        false => config
+            .routes
+            .iter()
+            .flat_map(|route| route.sources.iter().cloned())
+            .collect(),
+    };
+    // Routes after a catch-all route would never be used.
+    let catch_all = match (args.subscribe, settings_path) {
+        (false, Some(settings_path)) => config
+            .routes
+            .iter()
+            .position(|r| r.sources.is_empty())
+            .map(|index| (settings_path, index)),
+        _ => None,
+    };
+    let offset = match catch_all {
+        Some((settings_path, index)) => {
+            let name = &config.routes[index].name;
+            let contents = fs::read_to_string(settings_path).map_err(|e| {
+                AppError::Import(format!("Could not read {}: {}", settings_path, e))
+            })?;
+            let offset = route_offset(&contents, index).ok_or_else(|| {
+                AppError::Import(format!(
+                    "Could not find route {:?} in {}, move it below the imported routes",
+                    name, settings_path
+                ))
+            })?;
+            info!(
+                route = name,
+                "Inserting the routes before the route that matches all sources"
+            );
+            Some(offset)
+        }
+        None => None,
+    };
+    let mut channels = get_channels(config).await?;
+    let channel_exists = |channels: &[DiscordChannel], name: &str| {
+        channels
+            .iter()
+            .any(|c| c.kind != GUILD_CATEGORY && c.name == name)
+    };
+    let imported: Vec<ImportedFeed> = feeds
+        .into_iter()
+        .map(|feed| {
+            let channel = config.channel_name(&feed.title);
+            ImportedFeed {
+                new_channel: !channel_exists(&channels, &channel),
+                added: !existing.contains(&feed.title),
+                channel,
+                feed,
+            }
+        })
+        .collect();
+
+    let summary = |imported: &Vec<ImportedFeed>| {
+        lines(imported, "No feeds in the OPML file", |feed| {
+            let action = match (feed.added, args.subscribe) {
+                (false, _) => "exists",
+                (true, true) => "subscribe",
+                (true, false) => "route",
+            };
+            let channel = match feed.new_channel {
+                true => "new channel",
+                false => "channel",
+            };
+            format!(
+                "{}\t{} #{}\t{}\t{} ({})",
+                feed.feed.folder.as_deref().unwrap_or("-"),
+                channel,
+                feed.channel,
+                action,
+                feed.feed.title,
+                feed.feed.url
+            )
+        })
+    };
+    if args.dry_run {
+        print(json, &imported, summary);
+        return Ok(());
+    }
+
+    let mut routes = vec![];
+    for feed in &imported {
+        let category = match &feed.feed.folder {
+            Some(folder) => Some(find_or_create_category(config, &mut channels, folder).await?),
+            None => None,
+        };
+        if feed.new_channel && !channel_exists(&channels, &feed.channel) {
+            let options = ChannelOptions {
+                parent_id: category.clone(),
+                ..Default::default()
+            };
+            let channel = create_channel(config, &feed.channel, &options).await?;
+            info!(
+                channel = feed.channel,
+                feed = feed.feed.title,
+                "Created channel"
+            );
+            channels.push(channel);
+        }
+        if !feed.added {
+            continue;
+        }
+        match args.subscribe {
+            true => {
+                let tags: Vec<String> = feed.feed.folder.iter().cloned().collect();
+                add_source(config, &feed.feed.title, &feed.feed.url, &tags)
+                    .await
+                    .map_err(RequestError::from)?;
+                info!(feed = feed.feed.title, "Subscribed to feed");
+            }
+            false => routes.push(route(feed, category.as_ref())),
+        }
+    }
+    if let (Some(settings_path), false) = (settings_path, routes.is_empty()) {
+        write_routes(settings_path, &args.path, &routes, offset)?;
+        info!(
+            routes = routes.len(),
+            settings = settings_path,
+            "Added routes"
+        );
+    }
+    print(json, &imported, summary);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs};
+
+    use httpmock::Method::{GET, POST};
+
+    use crate::{
+        cli::ImportOpmlArgs,
+        opml::{import_opml, parse, OpmlFeed},
+        test::start_server,
+    };
+
+    const OPML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<opml version="2.0">
+  <head><title>Subscriptions</title></head>
+  <body>
+    <outline text="Flight sim" title="Flight sim">
+      <outline type="rss" text="MSFS News" xmlUrl="https://example.com/msfs.xml"/>
+      <outline type="rss" text="my_channel" title="my_channel" xmlUrl="https://example.com/my.xml"/>
+    </outline>
+    <outline type="rss" text="Loose feeds" xmlUrl="https://example.com/loose.xml"/>
+  </body>
+</opml>"#;
+
+    #[test]
+    fn test_parse() {
+        let feed = |title: &str, url: &str, folder: Option<&str>| OpmlFeed {
+            title: title.to_string(),
+            url: url.to_string(),
+            folder: folder.map(str::to_string),
+        };
+        assert_eq!(
+            parse(OPML).unwrap(),
+            vec![
+                feed(
+                    "MSFS News",
+                    "https://example.com/msfs.xml",
+                    Some("Flight sim")
+                ),
+                feed(
+                    "my_channel",
+                    "https://example.com/my.xml",
+                    Some("Flight sim")
+                ),
+                feed("Loose feeds", "https://example.com/loose.xml", None),
+            ]
+        );
+        assert!(parse("<opml>").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_import_opml_writes_routes() {
+        let (server, config) = start_server();
+        let dir = env::temp_dir();
+        let opml_path = dir.join(format!("selfoss-discord-{}.opml", std::process::id()));
+        let settings_path = dir.join(format!("selfoss-discord-{}.toml", std::process::id()));
+        fs::write(&opml_path, OPML).unwrap();
+        fs::write(&settings_path, "state_path = \"state.json\"\n").unwrap();
+
+        server.mock(|when, then| {
+            when.method(GET).path("/guilds/123/channels");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/discord_items_mock_response.json");
+        });
+        let category_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/guilds/123/channels")
+                .json_body_partial(r#"{"name": "Flight sim", "type": 4}"#);
+            then.status(200)
+                .json_body(serde_json::json!({"id": "cat1", "name": "Flight sim", "type": 4}));
+        });
+        let channel_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/guilds/123/channels")
+                .json_body_partial(r#"{"name": "msfs-news", "parent_id": "cat1"}"#);
+            then.status(200)
+                .json_body(serde_json::json!({"id": "c1", "name": "msfs-news"}));
+        });
+        let loose_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/guilds/123/channels")
+                .json_body_partial(r#"{"name": "loose-feeds"}"#);
+            then.status(200)
+                .json_body(serde_json::json!({"id": "c2", "name": "loose-feeds"}));
+        });
+
+        let mut args = ImportOpmlArgs {
+            path: opml_path.to_string_lossy().into_owned(),
+            subscribe: false,
+            dry_run: true,
+        };
+        let settings = settings_path.to_string_lossy().into_owned();
+        import_opml(&config, &args, Some(&settings), false)
+            .await
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(&settings_path).unwrap(),
+            "state_path = \"state.json\"\n"
+        );
+        category_mock.assert_hits_async(0).await;
+
+        args.dry_run = false;
+        import_opml(&config, &args, Some(&settings), false)
+            .await
+            .unwrap();
+        let written = fs::read_to_string(&settings_path).unwrap();
+        let parsed: crate::config::Settings = toml::from_str(&written).unwrap();
+        let routes: Vec<(&str, Option<&str>)> = parsed
+            .routes
+            .iter()
+            .map(|r| (r.channel.as_deref().unwrap(), r.category.as_deref()))
+            .collect();
+        assert_eq!(
+            routes,
+            vec![
+                ("msfs-news", Some("cat1")),
+                // The channel of this feed exists already, outside of the category.
+                ("my_channel", Some("cat1")),
+                ("loose-feeds", None),
+            ]
+        );
+        category_mock.assert_async().await;
+        channel_mock.assert_async().await;
+        loose_mock.assert_async().await;
+
+        fs::remove_file(&opml_path).unwrap();
+        fs::remove_file(&settings_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_import_opml_before_catch_all_route() {
+        let (server, mut config) = start_server();
+        let dir = env::temp_dir();
+        let opml_path = dir.join(format!("selfoss-discord-{}-all.opml", std::process::id()));
+        let settings_path = dir.join(format!("selfoss-discord-{}-all.toml", std::process::id()));
+        let settings = r#"state_path = "state.json"
+
+[[routes]]
+name = "other"
+sources = ["other_channel"]
+channel = "other"
+
+# Everything else
+[[routes]]
+name = "everything"
+channel = "everything"
+"#;
+        fs::write(&opml_path, OPML).unwrap();
+        fs::write(&settings_path, settings).unwrap();
+        config.routes = toml::from_str::<crate::config::Settings>(settings)
+            .unwrap()
+            .routes;
+
+        server.mock(|when, then| {
+            when.method(GET).path("/guilds/123/channels");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/discord_items_mock_response.json");
+        });
+        server.mock(|when, then| {
+            when.method(POST).path("/guilds/123/channels");
+            then.status(200)
+                .json_body(serde_json::json!({"id": "cat1", "name": "Flight sim", "type": 4}));
+        });
+
+        let args = ImportOpmlArgs {
+            path: opml_path.to_string_lossy().into_owned(),
+            subscribe: false,
+            dry_run: false,
+        };
+        let path = settings_path.to_string_lossy().into_owned();
+        import_opml(&config, &args, Some(&path), false)
+            .await
+            .unwrap();
+        let written = fs::read_to_string(&settings_path).unwrap();
+        assert!(written.ends_with(
+            "\n# Everything else\n[[routes]]\nname = \"everything\"\nchannel = \"everything\"\n"
+        ));
+        let parsed: crate::config::Settings = toml::from_str(&written).unwrap();
+        let routes: Vec<&str> = parsed.routes.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(
+            routes,
+            vec![
+                "other",
+                "MSFS News",
+                "my_channel",
+                "Loose feeds",
+                "everything"
+            ]
+        );
+        assert!(parsed.validate().is_empty());
+
+        fs::remove_file(&opml_path).unwrap();
+        fs::remove_file(&settings_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_import_opml_subscribes() {
+        let (server, config) = start_server();
+        let opml_path = env::temp_dir().join(format!(
+            "selfoss-discord-subscribe-{}.opml",
+            std::process::id()
+        ));
+        fs::write(
+            &opml_path,
+            r#"<opml version="2.0"><body>
+                <outline text="my channel" xmlUrl="https://example.com/my.xml"/>
+                <outline text="New feed" xmlUrl="https://example.com/new.xml"/>
+            </body></opml>"#,
+        )
+        .unwrap();
+
+        server.mock(|when, then| {
+            when.method(GET).path("/sources/list");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/selfoss_sources_mock_response.json");
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/guilds/123/channels");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/discord_items_mock_response.json");
+        });
+        server.mock(|when, then| {
+            when.method(POST).path("/guilds/123/channels");
+            then.status(200)
+                .json_body(serde_json::json!({"id": "c1", "name": "new-feed"}));
+        });
+        let add_source_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/source")
+                .query_param("username", "test username")
+                .body("title=New+feed&spout=spouts%5Crss%5Cfeed&url=https%3A%2F%2Fexample.com%2Fnew.xml");
+            then.status(200).body(r#"{"success": true, "id": 13}"#);
+        });
+
+        let args = ImportOpmlArgs {
+            path: opml_path.to_string_lossy().into_owned(),
+            subscribe: true,
+            dry_run: false,
+        };
+        import_opml(&config, &args, None, false).await.unwrap();
+        add_source_mock.assert_async().await;
+        fs::remove_file(&opml_path).unwrap();
+    }
+}
@@ -0,0 +1,229 @@
+//! Publishing of messages in announcement channels to the channels that follow them. Discord only
+//! allows a few messages per channel per hour to be published, so the others wait in the state.
+
+use chrono::{DateTime, Duration, Utc};
+use tracing::{debug, error, info};
+
+use crate::{
+    config::Config,
+    discord::{errors::RequestError, models::CROSSPOSTS_PER_HOUR},
+    sinks::Sink,
+    state::{State, UnpublishedMessage},
+    stats::RunStats,
+};
+
+/// Whether another message can be published in `channel_id`, forgetting crossposts older than an
+/// hour.
+fn has_capacity(state: &mut State, channel_id: &str, now: DateTime<Utc>) -> bool {
+    let crossposts = state.crossposts.entry(channel_id.to_string()).or_default();
+    crossposts.retain(|at| *at > now - Duration::hours(1));
+    crossposts.len() < CROSSPOSTS_PER_HOUR
+}
+
+/// Publishes `message` if the rate limit allows it. Returns false if it has to wait.
+///
+/// A message that cannot be published is only counted in `publish_failed`, as it has been posted.
+async fn crosspost(
+    config: &Config,
+    state: &mut State,
+    stats: &mut RunStats,
+    sink: &dyn Sink,
+    message: &UnpublishedMessage,
+    now: DateTime<Utc>,
+) -> Result<bool, RequestError> {
+    if !has_capacity(state, &message.channel_id, now) {
+        return Ok(false);
+    }
+    match sink.publish(&message.channel_id, &message.message_id).await {
+        Ok(_) => {
+            info!(
+                channel_id = message.channel_id,
+                message_id = message.message_id,
+                "Published message"
+            );
+            stats.published += 1;
+            let crossposts = state.crossposts.entry(message.channel_id.clone());
+            crossposts.or_default().push(now);
+        }
+        Err(RequestError::RateLimited(_)) => return Ok(false),
+        Err(e) if e.is_fatal() => return Err(e),
+        Err(e) => {
+            error!(
+                error = config.redact(&e.to_string()),
+                channel_id = message.channel_id,
+                message_id = message.message_id,
+                "Could not publish message"
+            );
+            stats.publish_failed += 1;
+        }
+    }
+    Ok(true)
+}
+
+/// Publishes a message that was posted in an announcement channel, or queues it until the rate
+/// limit of the channel allows it.
+pub async fn publish(
+    config: &Config,
+    state: &mut State,
+    stats: &mut RunStats,
+    sink: &dyn Sink,
+    channel_id: &str,
+    message_id: &str,
+    now: DateTime<Utc>,
+) -> Result<(), RequestError> {
+    let message = UnpublishedMessage {
+        channel_id: channel_id.to_string(),
+        message_id: message_id.to_string(),
+    };
+    // Messages are published in the order in which they were posted.
+    let waiting = state.unpublished.iter().any(|m| m.channel_id == channel_id);
+    if waiting || !crosspost(config, state, stats, sink, &message, now).await? {
+        debug!(channel_id, message_id, "Waiting to publish message");
+        state.unpublished.push(message);
+    }
+    Ok(())
+}
+
+/// Publishes the queued messages as far as the rate limits of their channels allow.
+pub async fn publish_unpublished(
+    config: &Config,
+    state: &mut State,
+    stats: &mut RunStats,
+    sink: &dyn Sink,
+    now: DateTime<Utc>,
+) -> Result<(), RequestError> {
+    let mut i = 0;
+    while i < state.unpublished.len() {
+        let message = state.unpublished[i].clone();
+        let waiting = state.unpublished[..i]
+            .iter()
+            .any(|m| m.channel_id == message.channel_id);
+        if !waiting && crosspost(config, state, stats, sink, &message, now).await? {
+            state.unpublished.remove(i);
+        } else {
+            i += 1;
+        }
+    }
+    state
+        .crossposts
+        .retain(|_, crossposts| !crossposts.is_empty());
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, Utc};
+    use httpmock::Method::POST;
+
+    use crate::{
+        publish::{publish, publish_unpublished},
+        sinks::discord::DiscordSink,
+        state::{State, UnpublishedMessage},
+        stats::RunStats,
+        test::start_server,
+    };
+
+    #[tokio::test]
+    async fn test_publish_respects_rate_limit() {
+        let (server, config) = start_server();
+        let crosspost_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path_contains("/channels/news_id/messages/")
+                .path_contains("/crosspost");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/discord_send_message_mock_response.json");
+        });
+
+        let sink = DiscordSink::new(config.clone(), None);
+        let mut state = State::default();
+        let mut stats = RunStats::default();
+        let now = Utc::now();
+        for i in 0..12 {
+            let message_id = i.to_string();
+            publish(
+                &config,
+                &mut state,
+                &mut stats,
+                &sink,
+                "news_id",
+                &message_id,
+                now,
+            )
+            .await
+            .unwrap();
+        }
+        crosspost_mock.assert_hits_async(10).await;
+        assert_eq!(stats.published, 10);
+        assert_eq!(
+            state.unpublished,
+            ["10", "11"].map(|message_id| UnpublishedMessage {
+                channel_id: String::from("news_id"),
+                message_id: String::from(message_id),
+            })
+        );
+
+        publish_unpublished(
+            &config,
+            &mut state,
+            &mut stats,
+            &sink,
+            now + Duration::minutes(30),
+        )
+        .await
+        .unwrap();
+        assert_eq!(state.unpublished.len(), 2);
+
+        publish_unpublished(
+            &config,
+            &mut state,
+            &mut stats,
+            &sink,
+            now + Duration::minutes(61),
+        )
+        .await
+        .unwrap();
+        assert!(state.unpublished.is_empty());
+        assert_eq!(stats.published, 12);
+        assert_eq!(state.crossposts["news_id"].len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_publish_failures_are_counted_separately() {
+        let (server, config) = start_server();
+        let crosspost_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/channels/text_id/messages/1/crosspost");
+            then.status(400)
+                .header("content-type", "application/json")
+                .body(
+                    r#"{"code": 40033, "message": "This message has already been crossposted."}"#,
+                );
+        });
+
+        let sink = DiscordSink::new(config.clone(), None);
+        let mut state = State::default();
+        let mut stats = RunStats::default();
+        publish(
+            &config,
+            &mut state,
+            &mut stats,
+            &sink,
+            "text_id",
+            "1",
+            Utc::now(),
+        )
+        .await
+        .unwrap();
+        crosspost_mock.assert_async().await;
+        assert_eq!(
+            stats,
+            RunStats {
+                publish_failed: 1,
+                ..Default::default()
+            }
+        );
+        assert!(state.unpublished.is_empty());
+        assert!(config.metrics.health().is_empty());
+    }
+}
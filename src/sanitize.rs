@@ -0,0 +1,201 @@
+//! Makes feed text safe to post in Discord, where it would otherwise be able to ping members,
+//! disguise links or advertise other servers.
+//!
+//! Messages are also sent with `allowed_mentions` that only allow the configured mentions (none by
+//! default), so a mention that slips through here still does not ping anyone.
+
+use std::sync::LazyLock;
+
+use regex::{Captures, Regex};
+
+use crate::{
+    config::SanitizeConfig,
+    selfoss::models::SelfossItem,
+    utils::{truncate, MAX_MESSAGE_LENGTH},
+};
+
+const ZERO_WIDTH_SPACE: char = '\u{200B}';
+
+static INVITE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)<?\b(?:https?://)?(?:www\.)?(?:discord(?:app)?\.com/invite|discord\.gg)/[\w-]+>?",
+    )
+    .unwrap()
+});
+static MASKED_LINK: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\[([^\]\n]*)\]\(\s*<?([^)\s>]+)>?[^)\n]*\)").unwrap());
+static URL: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"https?://\S+").unwrap());
+static EVERYONE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"@(everyone|here)").unwrap());
+static MENTION: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"<(@[!&]?\d+|#\d+|/[^<>\s]+:\d+)>").unwrap());
+
+/// Replaces masked links (`[text](url)`) by their text followed by the URL, so that a link cannot
+/// pretend to go somewhere else. The URL is left out if it is an invite that is stripped.
+fn unmask_links(config: &SanitizeConfig, text: &str) -> String {
+    MASKED_LINK
+        .replace_all(text, |caps: &Captures| {
+            let (text, url) = (caps[1].trim(), &caps[2]);
+            match (
+                config.strip_invites && INVITE.is_match(url),
+                text.is_empty(),
+            ) {
+                (true, _) => text.to_string(),
+                (false, true) => url.to_string(),
+                (false, false) => format!("{} ({})", text, url),
+            }
+        })
+        .into_owned()
+}
+
+/// Escapes Discord Markdown outside of URLs, which would no longer work if they were escaped.
+fn escape_markdown(text: &str) -> String {
+    let urls: Vec<_> = URL.find_iter(text).map(|url| url.range()).collect();
+    let mut escaped = String::with_capacity(text.len());
+    let mut line_start = true;
+    for (i, c) in text.char_indices() {
+        if !urls.iter().any(|url| url.contains(&i)) {
+            match c {
+                '\\' | '*' | '_' | '~' | '`' | '|' | '[' | ']' => escaped.push('\\'),
+                // Headings, lists and quotes only start at the beginning of a line.
+                '#' | '-' | '>' if line_start => escaped.push('\\'),
+                _ => {}
+            }
+        }
+        escaped.push(c);
+        line_start = c == '\n' || (line_start && c == ' ');
+    }
+    escaped
+}
+
+/// Breaks `@everyone`, `@here` and mentions of users, roles, channels and commands with a
+/// zero-width space, so they are shown as text.
+fn neutralize_mentions(text: &str) -> String {
+    let text = EVERYONE.replace_all(text, format!("@{}${{1}}", ZERO_WIDTH_SPACE));
+    MENTION
+        .replace_all(&text, format!("<{}${{1}}>", ZERO_WIDTH_SPACE))
+        .into_owned()
+}
+
+/// Sanitizes feed text before it is posted in Discord.
+pub fn sanitize(config: &SanitizeConfig, text: &str) -> String {
+    let mut text = unmask_links(config, text);
+    if config.strip_invites {
+        text = INVITE.replace_all(&text, "").into_owned();
+    }
+    if config.escape_markdown {
+        text = escape_markdown(&text);
+    }
+    neutralize_mentions(&text)
+}
+
+/// Sanitizes text that is shown inside a masked link, which it must not be able to close.
+pub fn sanitize_link_text(config: &SanitizeConfig, text: &str) -> String {
+    let text = sanitize(config, text);
+    match config.escape_markdown {
+        true => text,
+        false => text.replace('[', "\\[").replace(']', "\\]"),
+    }
+}
+
+/// The sanitized content of the message of an item.
+pub fn message_content(config: &SanitizeConfig, item: &SelfossItem) -> String {
+    let content = sanitize(config, &item.clone().get_discord_message_content());
+    truncate(&content, MAX_MESSAGE_LENGTH).to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        config::SanitizeConfig,
+        sanitize::{message_content, sanitize, sanitize_link_text},
+        selfoss::models::SelfossItem,
+        test::get_mock_item,
+    };
+
+    const DEFAULT: SanitizeConfig = SanitizeConfig {
+        escape_markdown: false,
+        strip_invites: false,
+    };
+    const ESCAPE: SanitizeConfig = SanitizeConfig {
+        escape_markdown: true,
+        strip_invites: false,
+    };
+    const STRIP: SanitizeConfig = SanitizeConfig {
+        escape_markdown: false,
+        strip_invites: true,
+    };
+
+    /// Feed text that tries to ping, disguise links or advertise, and how it should be posted.
+    const FIXTURES: &[(SanitizeConfig, &str, &str)] = &[
+        (
+            DEFAULT,
+            "Hey @everyone and @here, ping <@123>, <@!456>, <@&789>, <#1011> and </help:1213>",
+            "Hey @\u{200B}everyone and @\u{200B}here, ping <\u{200B}@123>, <\u{200B}@!456>, \
+             <\u{200B}@&789>, <\u{200B}#1011> and <\u{200B}/help:1213>",
+        ),
+        (
+            DEFAULT,
+            "[https://example.com](https://phishing.example/login) and [](<https://x.example>)",
+            "https://example.com (https://phishing.example/login) and https://x.example",
+        ),
+        (
+            DEFAULT,
+            "[Docs](https://example.com/docs \"Official docs\")",
+            "Docs (https://example.com/docs)",
+        ),
+        (
+            STRIP,
+            "Join https://discord.gg/abc-123 or discord.com/invite/xyz and \
+             <https://discordapp.com/invite/q>",
+            "Join  or  and ",
+        ),
+        (
+            STRIP,
+            "[Free nitro](https://discord.gg/scam) and https://example.com/discord.gg",
+            "Free nitro and https://example.com/discord.gg",
+        ),
+        (
+            ESCAPE,
+            "# Big\n> quote\n- item\n**bold** _it_ ~~s~~ `code` ||spoiler|| 1-2 a>b \\o/",
+            "\\# Big\n\\> quote\n\\- item\n\\*\\*bold\\*\\* \\_it\\_ \\~\\~s\\~\\~ \\`code\\` \
+             \\|\\|spoiler\\|\\| 1-2 a>b \\\\o/",
+        ),
+        (
+            ESCAPE,
+            "See https://example.com/a_b*c for [@everyone](https://x.example/_)",
+            "See https://example.com/a_b*c for @\u{200B}everyone (https://x.example/_)",
+        ),
+        (ESCAPE, "<@&789> *", "<\u{200B}@&789> \\*"),
+    ];
+
+    #[test]
+    fn test_sanitize() {
+        for (config, text, expected) in FIXTURES {
+            assert_eq!(sanitize(config, text), *expected, "{:?} {:?}", config, text);
+        }
+    }
+
+    #[test]
+    fn test_sanitize_link_text() {
+        assert_eq!(
+            sanitize_link_text(&DEFAULT, "a](https://evil.example) [b"),
+            "a\\](https://evil.example) \\[b"
+        );
+        assert_eq!(sanitize_link_text(&ESCAPE, "[a]"), "\\[a\\]");
+    }
+
+    #[test]
+    fn test_message_content() {
+        let item = SelfossItem {
+            content: format!(
+                "<p>&lt;@&amp;789&gt; @everyone <a href=\"https://x.example\">x</a></p>{}",
+                "@here ".repeat(400)
+            ),
+            ..get_mock_item()
+        };
+        let content = message_content(&DEFAULT, &item);
+        assert!(content.starts_with("<\u{200B}@&789> @\u{200B}everyone x@\u{200B}here"));
+        assert_eq!(content.chars().count(), 2000);
+        assert!(!content.contains("@here"));
+    }
+}
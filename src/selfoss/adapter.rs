@@ -1,46 +1,236 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Instant};
 
 use reqwest::header::ACCEPT;
+use tracing::{debug, instrument};
 
 use crate::config::Config;
-use crate::selfoss::models::SelfossItem;
-
-pub async fn get_tree(config: &Config) -> Result<Vec<SelfossItem>, reqwest::Error> {
-    reqwest::Client::new()
-        .get(config.selfoss_base_url.clone() + "/items")
-        .query(&[("type", "unread"), ("items", "200")])
-        .header(ACCEPT, "application/json")
-        .send()
-        .await?
-        .json::<Vec<SelfossItem>>()
-        .await
+use crate::selfoss::errors::SelfossError;
+use crate::selfoss::models::{SelfossItem, SelfossSource, SelfossTag};
+
+fn observe_duration(config: &Config, start: Instant) {
+    config
+        .metrics
+        .observe_request("selfoss", start.elapsed().as_secs_f64());
 }
 
-pub async fn mark_items_as_read(config: &Config, item_id: u64) -> Result<String, reqwest::Error> {
-    let endpoint = config.selfoss_base_url.clone() + "/mark/" + &item_id.to_string();
-    let client = reqwest::Client::new();
+/// Which items the `/items` endpoint returns, newest first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ItemType {
+    Newest,
+    Unread,
+    Starred,
+}
+
+/// Filters and paging of the `/items` endpoint.
+#[derive(Debug, Clone)]
+pub struct ItemQuery {
+    pub item_type: ItemType,
+    pub tag: Option<String>,
+    /// Id of the source.
+    pub source: Option<u64>,
+    pub offset: usize,
+    pub items: usize,
+}
+
+impl Default for ItemQuery {
+    fn default() -> Self {
+        ItemQuery {
+            item_type: ItemType::Unread,
+            tag: None,
+            source: None,
+            offset: 0,
+            items: 200,
+        }
+    }
+}
 
+impl ItemQuery {
+    fn params(&self) -> Vec<(&'static str, String)> {
+        let item_type = match self.item_type {
+            ItemType::Newest => "newest",
+            ItemType::Unread => "unread",
+            ItemType::Starred => "starred",
+        };
+        let mut params = vec![
+            ("type", item_type.to_string()),
+            ("items", self.items.to_string()),
+        ];
+        if self.offset > 0 {
+            params.push(("offset", self.offset.to_string()));
+        }
+        if let Some(tag) = &self.tag {
+            params.push(("tag", tag.clone()));
+        }
+        if let Some(source) = self.source {
+            params.push(("source", source.to_string()));
+        }
+        params
+    }
+}
+
+#[instrument(skip(config))]
+pub async fn get_items(
+    config: &Config,
+    query: &ItemQuery,
+) -> Result<Vec<SelfossItem>, SelfossError> {
+    let start = Instant::now();
+    let result: Result<Vec<SelfossItem>, SelfossError> = async {
+        let response = reqwest::Client::new()
+            .get(config.selfoss_base_url.clone() + "/items")
+            .query(&query.params())
+            .header(ACCEPT, "application/json")
+            .send()
+            .await?;
+        Ok(SelfossError::check(response)
+            .await?
+            .json::<Vec<SelfossItem>>()
+            .await?)
+    }
+    .await;
+    observe_duration(config, start);
+    result
+}
+
+#[instrument(skip_all)]
+pub async fn get_tree(config: &Config) -> Result<Vec<SelfossItem>, SelfossError> {
+    let result = get_items(config, &ItemQuery::default()).await;
+    config.metrics.record_selfoss_fetch(result.is_ok());
+
+    let items = result?;
+    debug!(items = items.len(), "Fetched unread items");
+    Ok(items)
+}
+
+fn credentials(config: &Config) -> HashMap<&'static str, String> {
     let mut query = HashMap::new();
     query.insert("username", config.selfoss_username.clone());
     query.insert("password", config.selfoss_password.clone());
+    query
+}
+
+#[instrument(skip_all)]
+pub async fn get_sources(config: &Config) -> Result<Vec<SelfossSource>, SelfossError> {
+    let start = Instant::now();
+    let result: Result<Vec<SelfossSource>, SelfossError> = async {
+        let response = reqwest::Client::new()
+            .get(config.selfoss_base_url.clone() + "/sources/list")
+            .header(ACCEPT, "application/json")
+            .query(&credentials(config))
+            .send()
+            .await?;
+        Ok(SelfossError::check(response)
+            .await?
+            .json::<Vec<SelfossSource>>()
+            .await?)
+    }
+    .await;
+    observe_duration(config, start);
+
+    let sources = result?;
+    debug!(sources = sources.len(), "Fetched sources");
+    Ok(sources)
+}
+
+#[instrument(skip_all)]
+pub async fn get_tags(config: &Config) -> Result<Vec<SelfossTag>, SelfossError> {
+    let start = Instant::now();
+    let result: Result<Vec<SelfossTag>, SelfossError> = async {
+        let response = reqwest::Client::new()
+            .get(config.selfoss_base_url.clone() + "/tags")
+            .header(ACCEPT, "application/json")
+            .query(&credentials(config))
+            .send()
+            .await?;
+        Ok(SelfossError::check(response)
+            .await?
+            .json::<Vec<SelfossTag>>()
+            .await?)
+    }
+    .await;
+    observe_duration(config, start);
+    result
+}
+
+#[instrument(skip(config))]
+pub async fn mark_items_as_read(config: &Config, item_id: u64) -> Result<String, SelfossError> {
+    let endpoint = config.selfoss_base_url.clone() + "/mark/" + &item_id.to_string();
+    let client = reqwest::Client::new();
+    let query = credentials(config);
+
+    let start = Instant::now();
+    let result: Result<String, SelfossError> = async {
+        let response = client
+            .post(endpoint)
+            .header(ACCEPT, "application/json")
+            .query(&query)
+            .send()
+            .await?;
+        Ok(SelfossError::check(response).await?.text().await?)
+    }
+    .await;
+    observe_duration(config, start);
+    result.inspect(|_| debug!("Marked item as read"))
+}
+
+#[instrument(skip(config))]
+pub async fn star_item(config: &Config, item_id: u64) -> Result<String, SelfossError> {
+    let endpoint = config.selfoss_base_url.clone() + "/starr/" + &item_id.to_string();
+    let start = Instant::now();
+    let result: Result<String, SelfossError> = async {
+        let response = reqwest::Client::new()
+            .post(endpoint)
+            .header(ACCEPT, "application/json")
+            .query(&credentials(config))
+            .send()
+            .await?;
+        Ok(SelfossError::check(response).await?.text().await?)
+    }
+    .await;
+    observe_duration(config, start);
+    result.inspect(|_| debug!("Starred item"))
+}
 
-    client
-        .post(endpoint)
-        .header(ACCEPT, "application/json")
-        .query(&query)
-        .send()
-        .await?
-        .text()
-        .await
+/// Subscribes to an RSS feed, which is returned as source with the given title and tags.
+#[instrument(skip(config))]
+pub async fn add_source(
+    config: &Config,
+    title: &str,
+    url: &str,
+    tags: &[String],
+) -> Result<String, SelfossError> {
+    let mut form = vec![
+        ("title", title),
+        ("spout", "spouts\\rss\\feed"),
+        ("url", url),
+    ];
+    form.extend(tags.iter().map(|tag| ("tags[]", tag.as_str())));
+    let start = Instant::now();
+    let result: Result<String, SelfossError> = async {
+        let response = reqwest::Client::new()
+            .post(config.selfoss_base_url.clone() + "/source")
+            .header(ACCEPT, "application/json")
+            .query(&credentials(config))
+            .form(&form)
+            .send()
+            .await?;
+        Ok(SelfossError::check(response).await?.text().await?)
+    }
+    .await;
+    observe_duration(config, start);
+    result.inspect(|_| debug!("Added source"))
 }
 
 #[cfg(test)]
 mod test {
     use crate::{
-        selfoss::adapter::get_tree,
+        selfoss::{
+            adapter::{get_sources, get_tree, mark_items_as_read},
+            errors::SelfossError,
+            models::SelfossSource,
+        },
         test::{get_mock_item, start_server},
     };
-    use httpmock::Method::GET;
+    use httpmock::Method::{GET, POST};
 
     #[tokio::test]
     async fn test_get_tree() {
@@ -61,4 +251,46 @@ mod test {
 
         get_selfoss_items_mock.assert_async().await;
     }
+
+    #[tokio::test]
+    async fn test_get_sources() {
+        let (server, config) = start_server();
+
+        let get_sources_mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/sources/list")
+                .query_param("username", "test username");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/selfoss_sources_mock_response.json");
+        });
+
+        let sources = get_sources(&config).await.unwrap();
+        assert_eq!(
+            sources,
+            vec![SelfossSource {
+                id: 12,
+                title: String::from("my channel"),
+                tags: vec![String::from("news")],
+                spout: String::from("spouts\\rss\\feed"),
+            }]
+        );
+
+        get_sources_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_mark_items_as_read_unauthorized() {
+        let (server, config) = start_server();
+
+        let mark_item_read_mock = server.mock(|when, then| {
+            when.method(POST).path("/mark/187204");
+            then.status(403).body("");
+        });
+
+        let error = mark_items_as_read(&config, 187204).await.unwrap_err();
+        assert!(matches!(error, SelfossError::Unauthorized(_)));
+        assert!(error.is_fatal());
+        mark_item_read_mock.assert_async().await;
+    }
 }
@@ -0,0 +1,63 @@
+use std::fmt;
+
+use reqwest::{Response, StatusCode};
+
+#[derive(Debug)]
+pub enum SelfossError {
+    Reqwest(reqwest::Error),
+    /// The username or password is wrong, or the instance requires a login.
+    Unauthorized(StatusCode),
+    /// The item or endpoint does not exist, which usually means the base url is wrong.
+    NotFound(String),
+    Status(StatusCode, String),
+}
+
+impl SelfossError {
+    /// Passes successful responses through and converts the others into the matching variant.
+    pub async fn check(response: Response) -> Result<Response, SelfossError> {
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+
+        let url = response.url().path().to_string();
+        let body = response.text().await.unwrap_or_default();
+        Err(match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => SelfossError::Unauthorized(status),
+            StatusCode::NOT_FOUND => SelfossError::NotFound(url),
+            _ => SelfossError::Status(status, body),
+        })
+    }
+
+    /// Errors that will fail for every request, so there is no point in continuing.
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, SelfossError::Unauthorized(_))
+    }
+}
+
+impl From<reqwest::Error> for SelfossError {
+    fn from(value: reqwest::Error) -> Self {
+        SelfossError::Reqwest(value)
+    }
+}
+
+impl fmt::Display for SelfossError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SelfossError::Reqwest(ref e) => e.fmt(f),
+            SelfossError::Unauthorized(status) => write!(
+                f,
+                "Selfoss returned {}, check SELFOSS_USERNAME and SELFOSS_PASSWORD",
+                status
+            ),
+            SelfossError::NotFound(ref path) => write!(
+                f,
+                "Selfoss returned 404 Not Found for {}, check SELFOSS_BASE_URL",
+                path
+            ),
+            SelfossError::Status(status, ref body) => {
+                write!(f, "Selfoss returned {}: {}", status, body)
+            }
+        }
+    }
+}
@@ -1,2 +1,3 @@
 pub mod adapter;
+pub mod errors;
 pub mod models;
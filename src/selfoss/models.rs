@@ -2,8 +2,7 @@ use chrono::{DateTime, Utc};
 use scraper::Html;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct SelfossItemMarkedReadResponse {}
+use crate::utils::truncate;
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct SelfossItem {
@@ -12,18 +11,54 @@ pub struct SelfossItem {
     pub content: String,
     pub datetime: DateTime<Utc>,
     pub id: u64,
+    #[serde(default)]
+    pub link: String,
+    pub author: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub unread: bool,
+    #[serde(default)]
+    pub starred: bool,
+    /// URL of an image of the item, or its file name in the thumbnails of Selfoss.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail: Option<String>,
 }
 
-fn truncate(s: &str, max_chars: usize) -> &str {
-    match s.char_indices().nth(max_chars) {
-        None => s,
-        Some((idx, _)) => &s[..idx],
+/// A feed subscribed to in Selfoss.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SelfossSource {
+    pub id: u64,
+    pub title: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub spout: String,
+}
+
+/// A tag that groups sources in Selfoss.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SelfossTag {
+    pub tag: String,
+    /// Colour in the `#rrggbb` format.
+    #[serde(default)]
+    pub color: String,
+}
+
+impl SelfossTag {
+    pub fn rgb(&self) -> Option<u32> {
+        u32::from_str_radix(self.color.strip_prefix('#')?, 16).ok()
     }
 }
 
+/// The name of the Discord channel for a source, as Discord does not allow spaces and dots.
+pub fn channel_name_for_source(source: &str) -> String {
+    source.replace([' ', '.'], "-").to_lowercase()
+}
+
 impl SelfossItem {
     pub fn get_discord_channel_name(self) -> String {
-        self.sourcetitle.replace([' ', '.'], "-").to_lowercase()
+        channel_name_for_source(&self.sourcetitle)
     }
     pub fn get_discord_message_content(self) -> String {
         let truncated_string = truncate(&self.content, 2000).to_string();
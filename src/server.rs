@@ -0,0 +1,114 @@
+use std::{convert::Infallible, net::SocketAddr, sync::Arc};
+
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use tracing::{error, info};
+
+use crate::metrics::{Health, Metrics};
+
+/// The health of every bridge, with status 503 unless `ok` holds for all of them.
+fn health_response(metrics: &Metrics, ok: fn(&Health) -> bool) -> Response<Body> {
+    let health = metrics.health();
+    let status = match !health.is_empty() && health.values().all(ok) {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(&health).unwrap()))
+        .unwrap()
+}
+
+fn respond(metrics: &Metrics, req: &Request<Body>) -> Response<Body> {
+    match (req.method(), req.uri().path()) {
+        (&Method::GET, "/metrics") => Response::builder()
+            .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+            .body(Body::from(metrics.encode()))
+            .unwrap(),
+        (&Method::GET, "/healthz") => health_response(metrics, Health::is_healthy),
+        (&Method::GET, "/readyz") => health_response(metrics, Health::is_ready),
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+            .unwrap(),
+    }
+}
+
+/// Serves `/metrics`, `/healthz` and `/readyz` in the background and returns the bound address.
+pub fn start(addr: SocketAddr, metrics: Arc<Metrics>) -> Result<SocketAddr, hyper::Error> {
+    let make_service = make_service_fn(move |_| {
+        let metrics = metrics.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let response = respond(&metrics, &req);
+                async move { Ok::<_, Infallible>(response) }
+            }))
+        }
+    });
+
+    let server = Server::try_bind(&addr)?.serve(make_service);
+    let local_addr = server.local_addr();
+    info!(address = %local_addr, "Serving metrics");
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            error!(error = %e, "Metrics server stopped");
+        }
+    });
+    Ok(local_addr)
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use reqwest::StatusCode;
+
+    use crate::{metrics::Metrics, server::start, stats::RunStats};
+
+    #[tokio::test]
+    async fn test_metrics_and_health() {
+        let registry = Metrics::new();
+        let first = registry.for_bridges(&[String::from("first")]);
+        let second = registry.for_bridges(&[String::from("second")]);
+        let addr = start("127.0.0.1:0".parse().unwrap(), Arc::new(registry)).unwrap();
+        let url = |path: &str| format!("http://{}{}", addr, path);
+
+        let readyz = reqwest::get(url("/readyz")).await.unwrap();
+        assert_eq!(readyz.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        // Ready once every bridge is.
+        first.record_selfoss_fetch(true);
+        first.record_discord_post(true);
+        let readyz = reqwest::get(url("/readyz")).await.unwrap();
+        assert_eq!(readyz.status(), StatusCode::SERVICE_UNAVAILABLE);
+        second.record_selfoss_fetch(true);
+        second.record_run(
+            &RunStats {
+                posted: 3,
+                ..Default::default()
+            },
+            true,
+        );
+        let readyz = reqwest::get(url("/readyz")).await.unwrap();
+        assert_eq!(readyz.status(), StatusCode::OK);
+
+        // A bridge that works does not hide one that does not.
+        first.record_discord_post(false);
+        second.record_discord_post(true);
+        let healthz = reqwest::get(url("/healthz")).await.unwrap();
+        assert_eq!(healthz.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            healthz.text().await.unwrap(),
+            r#"{"first":{"selfoss_ok":true,"discord_ok":false},"second":{"selfoss_ok":true,"discord_ok":true}}"#
+        );
+
+        let body = reqwest::get(url("/metrics")).await.unwrap().text().await;
+        assert!(body
+            .unwrap()
+            .contains(r#"selfoss_discord_items_total{bridge="second",outcome="posted"} 3"#));
+    }
+}
@@ -0,0 +1,277 @@
+//! The Discord guild of a bridge.
+//!
+//! Digests and summaries share the channels of the items, so they are posted through
+//! [`DiscordSink::post_content`] with the same channel map.
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use reqwest::StatusCode;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use super::Sink;
+use crate::{
+    config::{ChannelDeletedAction, Config},
+    discord::{
+        adapter::{
+            create_channel, crosspost_message, delete_message, edit_message, post_message,
+            post_message_with_files,
+        },
+        errors::RequestError,
+        models::{AllowedMentions, Attachment, ChannelOptions, DiscordMessage},
+    },
+    get_channel_map, images, mentions, sanitize,
+    selfoss::models::SelfossItem,
+    timestamps,
+    utils::MAX_MESSAGE_LENGTH,
+};
+
+pub struct DiscordSink {
+    config: Config,
+    /// Whether items ping the mentions of their routes.
+    pub mentions: bool,
+    /// Channel ids by name, fetched when the first channel is needed and again after a channel
+    /// turned out to be deleted.
+    channel_map: Mutex<Option<HashMap<String, String>>>,
+}
+
+impl DiscordSink {
+    pub fn new(config: Config, channel_map: Option<HashMap<String, String>>) -> Self {
+        DiscordSink {
+            config,
+            mentions: true,
+            channel_map: Mutex::new(channel_map),
+        }
+    }
+
+    /// The content of the message of an item, with the mentions it pings in front of it.
+    fn render(&self, item: &SelfossItem) -> (String, AllowedMentions) {
+        let content = sanitize::message_content(&self.config.sanitize, item);
+        let mentions = match self.mentions {
+            true => mentions::allowed_mentions(self.config.mentions_for(item), item),
+            false => AllowedMentions::default(),
+        };
+        let prefix = mentions.prefix();
+        // Leaves room for the mentions in front of the content.
+        let max_chars = match prefix.is_empty() {
+            true => MAX_MESSAGE_LENGTH,
+            false => MAX_MESSAGE_LENGTH.saturating_sub(prefix.chars().count() + 1),
+        };
+        let now = self.config.clock.now();
+        let content = timestamps::append(
+            self.config.timestamps.as_ref(),
+            content,
+            item,
+            now,
+            max_chars,
+        );
+        match prefix.is_empty() {
+            true => (content, mentions),
+            false => (format!("{}\n{}", prefix, content), mentions),
+        }
+    }
+
+    /// Posts a message with `files` attached, or without them if Discord rejects the upload.
+    async fn post_with_files(
+        &self,
+        channel: &str,
+        content: &str,
+        mentions: &AllowedMentions,
+        files: &[Attachment],
+    ) -> Result<DiscordMessage, RequestError> {
+        let config = &self.config;
+        if files.is_empty() {
+            return post_message(config, channel, content, mentions).await;
+        }
+        match post_message_with_files(config, channel, content, mentions, files).await {
+            Err(RequestError::InvalidFormBody(e) | RequestError::Discord(e))
+                if e.status == StatusCode::BAD_REQUEST
+                    || e.status == StatusCode::PAYLOAD_TOO_LARGE =>
+            {
+                warn!(error = %e, "Could not upload images, posting without them");
+                post_message(config, channel, content, mentions).await
+            }
+            result => result,
+        }
+    }
+
+    /// Posts `content`, such as a digest, to the channel `name`, and if it has been deleted,
+    /// resolves or creates it again and retries once. Returns the ids of the channel and the
+    /// message.
+    pub async fn post_content(
+        &self,
+        name: &str,
+        options: &ChannelOptions,
+        content: &str,
+    ) -> Result<(String, String), RequestError> {
+        let mentions = AllowedMentions::default();
+        let channel = self.ensure_destination(name, options).await?;
+        match post_message(&self.config, &channel, content, &mentions).await {
+            Err(RequestError::UnknownChannel(e)) => {
+                warn!(
+                    channel = name,
+                    channel_id = channel,
+                    "Channel has been deleted"
+                );
+                self.forget_destination(name).await;
+                if self.config.on_channel_deleted == ChannelDeletedAction::Unsubscribe {
+                    return Err(RequestError::UnknownChannel(e));
+                }
+                let channel = self.ensure_destination(name, options).await?;
+                let message = post_message(&self.config, &channel, content, &mentions).await?;
+                Ok((channel, message.id))
+            }
+            result => Ok((channel, result?.id)),
+        }
+    }
+
+    /// Replaces the content of a message.
+    pub async fn edit_content(
+        &self,
+        channel_id: &str,
+        message_id: &str,
+        content: &str,
+    ) -> Result<(), RequestError> {
+        edit_message(&self.config, channel_id, message_id, content).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Sink for DiscordSink {
+    async fn ensure_destination(
+        &self,
+        name: &str,
+        options: &ChannelOptions,
+    ) -> Result<String, RequestError> {
+        let mut channel_map = self.channel_map.lock().await;
+        // Someone may have created a channel with the same name since it was fetched.
+        if channel_map.is_none() {
+            *channel_map = Some(get_channel_map(&self.config).await?);
+        }
+        let channel_map = channel_map.as_mut().unwrap();
+        if !channel_map.contains_key(name) {
+            let c = create_channel(&self.config, name, options).await?;
+            info!(channel = name, channel_id = c.id, "Created channel");
+            channel_map.insert(name.to_string(), c.id);
+        }
+        Ok(channel_map[name].clone())
+    }
+
+    async fn forget_destination(&self, _name: &str) {
+        *self.channel_map.lock().await = None;
+    }
+
+    /// Only the content of an item is posted, so items without it are skipped.
+    fn has_content(&self, item: &SelfossItem) -> bool {
+        !item.content.is_empty()
+            && !sanitize::message_content(&self.config.sanitize, item).is_empty()
+    }
+
+    async fn post_item(
+        &self,
+        destination: &str,
+        item: &SelfossItem,
+    ) -> Result<String, RequestError> {
+        let (content, mentions) = self.render(item);
+        let files = match &self.config.images {
+            Some(images) => images::download_images(&self.config, images, item).await,
+            None => vec![],
+        };
+        let message = self
+            .post_with_files(destination, &content, &mentions, &files)
+            .await?;
+        Ok(message.id)
+    }
+
+    async fn update(
+        &self,
+        destination: &str,
+        message_id: &str,
+        item: &SelfossItem,
+    ) -> Result<(), RequestError> {
+        let (content, _) = self.render(item);
+        self.edit_content(destination, message_id, &content).await
+    }
+
+    async fn delete(&self, destination: &str, message_id: &str) -> Result<(), RequestError> {
+        delete_message(&self.config, destination, message_id).await
+    }
+
+    async fn publish(&self, destination: &str, message_id: &str) -> Result<(), RequestError> {
+        crosspost_message(&self.config, destination, message_id).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use httpmock::Method::{DELETE, GET, PATCH, POST};
+
+    use crate::{
+        discord::models::ChannelOptions,
+        sinks::{discord::DiscordSink, Sink},
+        test::{get_mock_item, start_server},
+    };
+
+    #[tokio::test]
+    async fn test_discord_sink() {
+        let (server, config) = start_server();
+
+        let get_channels_mock = server.mock(|when, then| {
+            when.method(GET).path("/guilds/123/channels");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/discord_items_mock_response.json");
+        });
+        let send_message_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/channels/my_channel_id/messages")
+                .json_body_partial(r#"{"content": "My content"}"#);
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/discord_send_message_mock_response.json");
+        });
+        let edit_message_mock = server.mock(|when, then| {
+            when.method(PATCH)
+                .path("/channels/my_channel_id/messages/4242")
+                .json_body_partial(r#"{"content": "Edited"}"#);
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/discord_send_message_mock_response.json");
+        });
+        let delete_message_mock = server.mock(|when, then| {
+            when.method(DELETE)
+                .path("/channels/my_channel_id/messages/4242");
+            then.status(204);
+        });
+
+        let sink = DiscordSink::new(config, None);
+        let options = ChannelOptions::default();
+        let channel = sink.ensure_destination("my_channel", &options).await;
+        assert_eq!(channel.unwrap(), "my_channel_id");
+        // The channel map is only fetched once.
+        let channel = sink.ensure_destination("my_channel", &options).await;
+        assert_eq!(channel.unwrap(), "my_channel_id");
+
+        let mut item = get_mock_item();
+        assert!(sink.has_content(&item));
+        let message_id = sink.post_item("my_channel_id", &item).await;
+        assert_eq!(message_id.unwrap(), "4242");
+        item.content = String::from("Edited");
+        sink.update("my_channel_id", "4242", &item)
+            .await
+            .expect("Error editing message");
+        sink.delete("my_channel_id", "4242")
+            .await
+            .expect("Error deleting message");
+        item.content = String::from("<p></p>");
+        assert!(!sink.has_content(&item));
+
+        get_channels_mock.assert_async().await;
+        send_message_mock.assert_async().await;
+        edit_message_mock.assert_async().await;
+        delete_message_mock.assert_async().await;
+    }
+}
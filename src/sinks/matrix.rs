@@ -0,0 +1,287 @@
+//! Matrix, through the client-server API of a homeserver.
+//!
+//! Destinations are rooms with the alias `#<name>:<server_name>`, see
+//! https://spec.matrix.org/latest/client-server-api/
+
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use async_trait::async_trait;
+use reqwest::{header::AUTHORIZATION, Method, RequestBuilder, StatusCode, Url};
+use serde_json::{json, Value};
+
+use super::{check_response, escape_html, plain_text, Sink, SinkError};
+use crate::{
+    discord::{errors::RequestError, models::ChannelOptions},
+    selfoss::models::SelfossItem,
+};
+
+const MATRIX: &str = "Matrix";
+const MAX_TEXT_LENGTH: usize = 4000;
+
+pub struct MatrixSink {
+    homeserver: Url,
+    /// Server name used in room aliases, e.g. `example.org`.
+    server_name: String,
+    token: String,
+}
+
+/// Formats an item as the content of an `m.room.message` event, with an HTML version.
+pub fn format_item(item: &SelfossItem) -> Value {
+    let text = plain_text(item, MAX_TEXT_LENGTH);
+    let title = match item.link.is_empty() {
+        true => format!("<b>{}</b>", escape_html(&item.title)),
+        false => format!(
+            "<b><a href=\"{}\">{}</a></b>",
+            escape_html(&item.link),
+            escape_html(&item.title)
+        ),
+    };
+    json!({
+        "msgtype": "m.text",
+        "body": format!("{}\n{}\n\n{}", item.title, item.link, text).trim(),
+        "format": "org.matrix.custom.html",
+        "formatted_body": format!("{}<br>{}", title, escape_html(&text).replace('\n', "<br>")),
+    })
+}
+
+/// Transaction id of the event `content` for `item`. The homeserver ignores an event that is sent
+/// again with the same id, e.g. when the response to the first attempt got lost.
+fn transaction_id(item: &SelfossItem, content: &Value) -> String {
+    let mut hasher = DefaultHasher::new();
+    content.to_string().hash(&mut hasher);
+    format!("selfoss-discord-{}-{:x}", item.id, hasher.finish())
+}
+
+impl MatrixSink {
+    pub fn new(homeserver: &str, server_name: String, token: String) -> Result<Self, RequestError> {
+        let invalid = || {
+            let message = format!("Invalid homeserver URL {:?}", homeserver);
+            RequestError::Sink(SinkError::new(MATRIX, message))
+        };
+        let homeserver = Url::parse(homeserver).map_err(|_| invalid())?;
+        if homeserver.cannot_be_a_base() {
+            return Err(invalid());
+        }
+        Ok(MatrixSink {
+            homeserver,
+            server_name,
+            token,
+        })
+    }
+
+    fn request(&self, method: Method, segments: &[&str]) -> RequestBuilder {
+        let mut url = self.homeserver.clone();
+        url.path_segments_mut()
+            .expect("checked in MatrixSink::new")
+            .pop_if_empty()
+            .extend(["_matrix", "client", "v3"])
+            .extend(segments);
+        reqwest::Client::new()
+            .request(method, url)
+            .header(AUTHORIZATION, format!("Bearer {}", self.token))
+    }
+
+    async fn send(&self, request: RequestBuilder) -> Result<Value, RequestError> {
+        let response = check_response(MATRIX, request.send().await?).await?;
+        Ok(response.json().await?)
+    }
+
+    async fn send_event(
+        &self,
+        room_id: &str,
+        item: &SelfossItem,
+        content: Value,
+    ) -> Result<String, RequestError> {
+        let path = [
+            "rooms",
+            room_id,
+            "send",
+            "m.room.message",
+            &transaction_id(item, &content),
+        ];
+        let body = self
+            .send(self.request(Method::PUT, &path).json(&content))
+            .await?;
+        Ok(body["event_id"].as_str().unwrap_or_default().to_string())
+    }
+}
+
+#[async_trait]
+impl Sink for MatrixSink {
+    async fn ensure_destination(
+        &self,
+        name: &str,
+        options: &ChannelOptions,
+    ) -> Result<String, RequestError> {
+        let alias = format!("#{}:{}", name, self.server_name);
+        let request = self.request(Method::GET, &["directory", "room", &alias]);
+        match self.send(request).await {
+            Ok(body) => return Ok(body["room_id"].as_str().unwrap_or_default().to_string()),
+            Err(RequestError::Sink(SinkError {
+                status: Some(StatusCode::NOT_FOUND),
+                ..
+            })) => {}
+            Err(e) => return Err(e),
+        }
+        let mut payload = json!({"room_alias_name": name, "name": name, "preset": "public_chat"});
+        if let Some(topic) = &options.topic {
+            payload["topic"] = json!(topic);
+        }
+        let request = self.request(Method::POST, &["createRoom"]).json(&payload);
+        let body = self.send(request).await?;
+        Ok(body["room_id"].as_str().unwrap_or_default().to_string())
+    }
+
+    async fn post_item(
+        &self,
+        destination: &str,
+        item: &SelfossItem,
+    ) -> Result<String, RequestError> {
+        self.send_event(destination, item, format_item(item)).await
+    }
+
+    async fn update(
+        &self,
+        destination: &str,
+        message_id: &str,
+        item: &SelfossItem,
+    ) -> Result<(), RequestError> {
+        let new_content = format_item(item);
+        let mut content = new_content.clone();
+        content["body"] = json!(format!("* {}", new_content["body"].as_str().unwrap()));
+        content["m.new_content"] = new_content;
+        content["m.relates_to"] = json!({"rel_type": "m.replace", "event_id": message_id});
+        self.send_event(destination, item, content).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, destination: &str, message_id: &str) -> Result<(), RequestError> {
+        let path = [
+            "rooms",
+            destination,
+            "redact",
+            message_id,
+            // Redacting an event again changes nothing.
+            &format!("selfoss-discord-redact-{}", message_id),
+        ];
+        self.send(self.request(Method::PUT, &path).json(&json!({})))
+            .await?;
+        Ok(())
+    }
+
+    async fn publish(&self, _destination: &str, _message_id: &str) -> Result<(), RequestError> {
+        Err(SinkError::unsupported(MATRIX, "Publishing"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use httpmock::{
+        Method::{GET, POST, PUT},
+        MockServer,
+    };
+    use serde_json::json;
+
+    use crate::{
+        discord::{errors::RequestError, models::ChannelOptions},
+        sinks::{
+            matrix::{format_item, transaction_id, MatrixSink},
+            Sink,
+        },
+        test::get_mock_item,
+    };
+
+    #[test]
+    fn test_invalid_homeserver() {
+        for homeserver in ["matrix.example.org", "mailto:admin@example.org"] {
+            let sink = MatrixSink::new(homeserver, String::new(), String::new());
+            match sink {
+                Err(RequestError::Sink(e)) => assert!(e.message.contains("homeserver")),
+                _ => panic!("accepted homeserver {:?}", homeserver),
+            }
+        }
+    }
+
+    #[test]
+    fn test_format_item() {
+        let mut item = get_mock_item();
+        item.content = String::from("<p>1 < 2</p>");
+        assert_eq!(
+            format_item(&item),
+            json!({
+                "msgtype": "m.text",
+                "body": "My title\nMy link\n\n1 < 2",
+                "format": "org.matrix.custom.html",
+                "formatted_body": "<b><a href=\"My link\">My title</a></b><br>1 &lt; 2",
+            })
+        );
+    }
+
+    #[test]
+    fn test_transaction_id() {
+        let mut item = get_mock_item();
+        let id = transaction_id(&item, &format_item(&item));
+        assert!(id.starts_with("selfoss-discord-187204-"));
+        assert_eq!(transaction_id(&item, &format_item(&item)), id);
+        item.content = String::from("Edited");
+        assert_ne!(transaction_id(&item, &format_item(&item)), id);
+    }
+
+    #[tokio::test]
+    async fn test_matrix_sink() {
+        let server = MockServer::start();
+        let alias_mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/_matrix/client/v3/directory/room/%23my_channel:example.org");
+            then.status(404)
+                .json_body(json!({"errcode": "M_NOT_FOUND", "error": "Room alias not found"}));
+        });
+        let create_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/_matrix/client/v3/createRoom")
+                .header("authorization", "Bearer matrix-token")
+                .json_body_partial(r#"{"room_alias_name": "my_channel", "topic": "My topic"}"#);
+            then.status(200)
+                .json_body(json!({"room_id": "!room:example.org"}));
+        });
+        let send_mock = server.mock(|when, then| {
+            when.method(PUT)
+                .path_contains("/_matrix/client/v3/rooms/!room:example.org/send/m.room.message/")
+                .json_body_partial(
+                    r#"{"msgtype": "m.text", "body": "My title\nMy link\n\nMy content"}"#,
+                );
+            then.status(200).json_body(json!({"event_id": "$event"}));
+        });
+        let edit_mock = server.mock(|when, then| {
+            when.method(PUT)
+                .path_contains("/send/m.room.message/")
+                .json_body_partial(
+                    r#"{"m.relates_to": {"rel_type": "m.replace", "event_id": "$event"}}"#,
+                );
+            then.status(200).json_body(json!({"event_id": "$edit"}));
+        });
+
+        let sink = MatrixSink::new(
+            &server.base_url(),
+            String::from("example.org"),
+            String::from("matrix-token"),
+        )
+        .unwrap();
+        let options = ChannelOptions {
+            topic: Some(String::from("My topic")),
+            ..Default::default()
+        };
+        let room_id = sink.ensure_destination("my_channel", &options).await;
+        assert_eq!(room_id.unwrap(), "!room:example.org");
+        let event_id = sink.post_item("!room:example.org", &get_mock_item()).await;
+        assert_eq!(event_id.unwrap(), "$event");
+        sink.update("!room:example.org", "$event", &get_mock_item())
+            .await
+            .expect("Error editing message");
+
+        alias_mock.assert_async().await;
+        create_mock.assert_async().await;
+        send_mock.assert_async().await;
+        edit_mock.assert_async().await;
+    }
+}
@@ -0,0 +1,153 @@
+//! Mattermost, through an incoming webhook.
+//!
+//! The channel of every message is set explicitly, which requires a webhook that is not locked to
+//! its channel. See https://developers.mattermost.com/integrate/webhooks/incoming/
+
+use async_trait::async_trait;
+use serde_json::json;
+
+use super::{check_response, plain_text, Sink, SinkError};
+use crate::{
+    discord::{errors::RequestError, models::ChannelOptions},
+    selfoss::models::SelfossItem,
+};
+
+const MATTERMOST: &str = "Mattermost";
+const MAX_TEXT_LENGTH: usize = 4000;
+
+pub struct MattermostSink {
+    pub webhook_url: String,
+}
+
+/// Escapes the characters that would end the text of a Markdown link.
+fn escape(s: &str) -> String {
+    s.replace('[', "\\[").replace(']', "\\]")
+}
+
+/// Formats an item as Markdown, a bold title linking to the item followed by its text.
+pub fn format_item(item: &SelfossItem) -> String {
+    let title = match item.link.is_empty() {
+        true => format!("**{}**", escape(&item.title)),
+        false => format!("**[{}]({})**", escape(&item.title), item.link),
+    };
+    let text = plain_text(item, MAX_TEXT_LENGTH);
+    match text.is_empty() {
+        true => title,
+        false => format!("{}\n{}", title, text),
+    }
+}
+
+#[async_trait]
+impl Sink for MattermostSink {
+    /// Webhooks cannot look up or create channels, the channel has to exist already.
+    async fn ensure_destination(
+        &self,
+        name: &str,
+        _options: &ChannelOptions,
+    ) -> Result<String, RequestError> {
+        Ok(name.to_string())
+    }
+
+    async fn post_item(
+        &self,
+        destination: &str,
+        item: &SelfossItem,
+    ) -> Result<String, RequestError> {
+        let payload = json!({"channel": destination, "text": format_item(item)});
+        let response = reqwest::Client::new()
+            .post(&self.webhook_url)
+            .json(&payload)
+            .send()
+            .await
+            // The URL of a webhook is its secret.
+            .map_err(reqwest::Error::without_url)?;
+        check_response(MATTERMOST, response).await?;
+        Ok(String::new())
+    }
+
+    async fn update(
+        &self,
+        _destination: &str,
+        _message_id: &str,
+        _item: &SelfossItem,
+    ) -> Result<(), RequestError> {
+        Err(SinkError::unsupported(MATTERMOST, "Updating"))
+    }
+
+    async fn delete(&self, _destination: &str, _message_id: &str) -> Result<(), RequestError> {
+        Err(SinkError::unsupported(MATTERMOST, "Deleting"))
+    }
+
+    async fn publish(&self, _destination: &str, _message_id: &str) -> Result<(), RequestError> {
+        Err(SinkError::unsupported(MATTERMOST, "Publishing"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use httpmock::{Method::POST, MockServer};
+    use serde_json::json;
+
+    use crate::{
+        discord::{errors::RequestError, models::ChannelOptions},
+        sinks::{
+            mattermost::{format_item, MattermostSink},
+            Sink,
+        },
+        test::get_mock_item,
+    };
+
+    #[test]
+    fn test_format_item() {
+        let mut item = get_mock_item();
+        item.title = String::from("[Update] Release");
+        assert_eq!(
+            format_item(&item),
+            "**[\\[Update\\] Release](My link)**\nMy content"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mattermost_sink() {
+        let server = MockServer::start();
+        let webhook_mock = server.mock(|when, then| {
+            when.method(POST).path("/hooks/abc").json_body(json!({
+                "channel": "my_channel",
+                "text": "**[My title](My link)**\nMy content"
+            }));
+            then.status(200).body("ok");
+        });
+        let unknown_channel_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/hooks/abc")
+                .json_body_partial(r#"{"channel": "unknown"}"#);
+            then.status(400).body("Unable to find the channel");
+        });
+
+        let sink = MattermostSink {
+            webhook_url: server.url("/hooks/abc"),
+        };
+        let options = ChannelOptions::default();
+        let channel = sink
+            .ensure_destination("my_channel", &options)
+            .await
+            .unwrap();
+        sink.post_item(&channel, &get_mock_item())
+            .await
+            .expect("Error posting item");
+        match sink.post_item("unknown", &get_mock_item()).await {
+            Err(RequestError::Sink(e)) => assert!(!e.is_retryable()),
+            result => panic!("expected Mattermost error, got {:?}", result),
+        }
+
+        webhook_mock.assert_async().await;
+        unknown_channel_mock.assert_async().await;
+
+        // Nothing listens on port 9.
+        let sink = MattermostSink {
+            webhook_url: String::from("http://127.0.0.1:9/hooks/secret"),
+        };
+        let error = sink.post_item("my_channel", &get_mock_item()).await;
+        assert!(!error.unwrap_err().to_string().contains("secret"));
+    }
+}
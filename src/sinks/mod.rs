@@ -0,0 +1,333 @@
+//! Chat services that items are posted to.
+//!
+//! Items are posted through the [`Sink`] of their route, the Discord guild of the bridge unless the
+//! route names another one. The pipeline in `main` handles duplicates and deleted destinations the
+//! same way for all of them. Digests and summaries are only posted in Discord.
+
+use std::{collections::HashMap, env, fmt};
+
+use async_trait::async_trait;
+use reqwest::{Response, StatusCode};
+use scraper::Html;
+
+use tracing::warn;
+
+use crate::{
+    config::{ChannelDeletedAction, Config, SinkKind},
+    discord::{errors::RequestError, models::ChannelOptions},
+    selfoss::models::SelfossItem,
+    utils::truncate,
+};
+
+pub mod discord;
+pub mod matrix;
+pub mod mattermost;
+pub mod slack;
+pub mod telegram;
+
+/// Name of the sink that routes can give to post in the Discord guild of the bridge, as without a
+/// sink.
+pub const DISCORD_SINK: &str = "discord";
+
+#[async_trait]
+pub trait Sink: Send + Sync {
+    /// Finds or creates the channel, room or chat called `name` and returns its id.
+    async fn ensure_destination(
+        &self,
+        name: &str,
+        options: &ChannelOptions,
+    ) -> Result<String, RequestError>;
+
+    /// Forgets the destination called `name` after it turned out to be deleted, so that
+    /// `ensure_destination` resolves it again.
+    async fn forget_destination(&self, _name: &str) {}
+
+    /// Whether there is anything to post for `item`, items without are skipped.
+    fn has_content(&self, _item: &SelfossItem) -> bool {
+        true
+    }
+
+    /// Posts an item and returns the id of the message, empty if the service does not return one.
+    async fn post_item(
+        &self,
+        destination: &str,
+        item: &SelfossItem,
+    ) -> Result<String, RequestError>;
+
+    /// Replaces a message with the current version of the item.
+    async fn update(
+        &self,
+        destination: &str,
+        message_id: &str,
+        item: &SelfossItem,
+    ) -> Result<(), RequestError>;
+
+    async fn delete(&self, destination: &str, message_id: &str) -> Result<(), RequestError>;
+
+    /// Publishes a message in an announcement destination to the destinations that follow it.
+    async fn publish(&self, destination: &str, message_id: &str) -> Result<(), RequestError>;
+}
+
+/// Error returned by a sink other than Discord.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SinkError {
+    pub sink: &'static str,
+    /// `None` if the request was never sent or the service reports errors in the body.
+    pub status: Option<StatusCode>,
+    pub message: String,
+}
+
+impl SinkError {
+    pub fn new(sink: &'static str, message: impl Into<String>) -> Self {
+        SinkError {
+            sink,
+            status: None,
+            message: message.into(),
+        }
+    }
+
+    fn unsupported(sink: &'static str, action: &str) -> RequestError {
+        RequestError::Sink(SinkError::new(
+            sink,
+            format!("{} messages is not supported", action),
+        ))
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        self.status
+            .is_some_and(|s| s.is_server_error() || s == StatusCode::TOO_MANY_REQUESTS)
+    }
+}
+
+impl fmt::Display for SinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.status {
+            Some(status) => write!(f, "{} returned {}: {}", self.sink, status, self.message),
+            None => write!(f, "{}: {}", self.sink, self.message),
+        }
+    }
+}
+
+/// Returns the response if it is successful, and its body as error otherwise.
+async fn check_response(sink: &'static str, response: Response) -> Result<Response, RequestError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    Err(RequestError::Sink(SinkError {
+        sink,
+        status: Some(status),
+        message: response.text().await.map_err(reqwest::Error::without_url)?,
+    }))
+}
+
+/// The text of the content of an item, without HTML and shortened to `max_chars`.
+pub fn plain_text(item: &SelfossItem, max_chars: usize) -> String {
+    let text: String = Html::parse_fragment(&item.content)
+        .root_element()
+        .text()
+        .collect();
+    truncate(text.trim(), max_chars).to_string()
+}
+
+pub fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn build(config: &Config, name: &str) -> Result<Box<dyn Sink>, RequestError> {
+    let Some(sink) = config.sinks.iter().find(|s| s.name == name) else {
+        return Err(RequestError::Sink(SinkError::new(
+            "config",
+            format!("Unknown sink {:?}", name),
+        )));
+    };
+    let secret = |key: &str| {
+        env::var(key).map_err(|_| {
+            RequestError::Sink(SinkError::new(
+                "config",
+                format!("Missing environment variable {} of sink {:?}", key, name),
+            ))
+        })
+    };
+    Ok(match &sink.kind {
+        SinkKind::Slack {
+            base_url,
+            token_env,
+            webhook_env,
+        } => match webhook_env {
+            Some(key) => Box::new(slack::SlackSink::Webhook { url: secret(key)? }),
+            None => Box::new(slack::SlackSink::WebApi {
+                base_url: base_url.clone(),
+                token: secret(token_env)?,
+            }),
+        },
+        SinkKind::Matrix {
+            homeserver,
+            server_name,
+            token_env,
+        } => Box::new(matrix::MatrixSink::new(
+            homeserver,
+            server_name.clone(),
+            secret(token_env)?,
+        )?),
+        SinkKind::Mattermost { webhook_env } => Box::new(mattermost::MattermostSink {
+            webhook_url: secret(webhook_env)?,
+        }),
+        SinkKind::Telegram {
+            base_url,
+            token_env,
+            chats,
+            default_chat,
+        } => Box::new(telegram::TelegramSink {
+            base_url: base_url.clone(),
+            token: secret(token_env)?,
+            chats: chats.clone(),
+            default_chat: default_chat.clone(),
+        }),
+    })
+}
+
+/// The sinks used during a run, with the destinations they already resolved.
+pub struct Sinks {
+    discord: discord::DiscordSink,
+    sinks: HashMap<String, Box<dyn Sink>>,
+    destinations: HashMap<(String, String), String>,
+}
+
+impl Sinks {
+    /// Sinks that post in the Discord channels of `channel_map`, which is fetched when the first
+    /// channel is needed if it is `None`.
+    pub fn new(config: &Config, channel_map: Option<HashMap<String, String>>) -> Self {
+        Sinks {
+            discord: discord::DiscordSink::new(config.clone(), channel_map),
+            sinks: HashMap::new(),
+            destinations: HashMap::new(),
+        }
+    }
+
+    /// Posts items in Discord without pinging the mentions of their routes, e.g. when old items
+    /// are posted again.
+    pub fn without_mentions(mut self) -> Self {
+        self.discord.mentions = false;
+        self
+    }
+
+    pub fn discord(&self) -> &discord::DiscordSink {
+        &self.discord
+    }
+
+    /// The sink called `sink`, built when it is first used.
+    pub fn get(&mut self, config: &Config, sink: &str) -> Result<&dyn Sink, RequestError> {
+        if sink == DISCORD_SINK {
+            return Ok(&self.discord);
+        }
+        if !self.sinks.contains_key(sink) {
+            self.sinks.insert(sink.to_string(), build(config, sink)?);
+        }
+        Ok(self.sinks[sink].as_ref())
+    }
+
+    /// Posts an item through the sink called `sink` in the destination called `destination`, and
+    /// if that has been deleted, resolves or creates it again and retries once. Returns the ids of
+    /// the destination and the message.
+    pub async fn post_item(
+        &mut self,
+        config: &Config,
+        sink: &str,
+        destination: &str,
+        options: &ChannelOptions,
+        item: &SelfossItem,
+    ) -> Result<(String, String), RequestError> {
+        let key = (sink.to_string(), destination.to_string());
+        let mut retried = false;
+        loop {
+            let cached = self.destinations.get(&key).cloned();
+            let built = self.get(config, sink)?;
+            let destination_id = match cached {
+                Some(id) => id,
+                None => built.ensure_destination(destination, options).await?,
+            };
+            match built.post_item(&destination_id, item).await {
+                Ok(message_id) => {
+                    self.destinations.insert(key, destination_id.clone());
+                    return Ok((destination_id, message_id));
+                }
+                Err(RequestError::UnknownChannel(e)) => {
+                    warn!(
+                        sink,
+                        destination, destination_id, "Destination has been deleted"
+                    );
+                    built.forget_destination(destination).await;
+                    self.destinations.remove(&key);
+                    if retried || config.on_channel_deleted == ChannelDeletedAction::Unsubscribe {
+                        return Err(RequestError::UnknownChannel(e));
+                    }
+                    retried = true;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub async fn update(
+        &mut self,
+        config: &Config,
+        sink: &str,
+        destination_id: &str,
+        message_id: &str,
+        item: &SelfossItem,
+    ) -> Result<(), RequestError> {
+        let built = self.get(config, sink)?;
+        built.update(destination_id, message_id, item).await
+    }
+
+    pub async fn delete(
+        &mut self,
+        config: &Config,
+        sink: &str,
+        destination_id: &str,
+        message_id: &str,
+    ) -> Result<(), RequestError> {
+        let built = self.get(config, sink)?;
+        built.delete(destination_id, message_id).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use reqwest::StatusCode;
+
+    use crate::{
+        sinks::{escape_html, plain_text, SinkError},
+        test::get_mock_item,
+    };
+
+    #[test]
+    fn test_plain_text() {
+        let mut item = get_mock_item();
+        item.content = String::from("<p>Some <b>bold</b> text</p>\n");
+        assert_eq!(plain_text(&item, 100), "Some bold text");
+        assert_eq!(plain_text(&item, 4), "Some");
+        assert_eq!(escape_html("<a & \"b\">"), "&lt;a &amp; &quot;b&quot;&gt;");
+    }
+
+    #[test]
+    fn test_retryable_errors() {
+        let error = |status| SinkError {
+            sink: "Slack",
+            status,
+            message: String::from("error"),
+        };
+        assert!(error(Some(StatusCode::BAD_GATEWAY)).is_retryable());
+        assert!(error(Some(StatusCode::TOO_MANY_REQUESTS)).is_retryable());
+        assert!(!error(Some(StatusCode::NOT_FOUND)).is_retryable());
+        assert!(!error(None).is_retryable());
+        assert_eq!(
+            error(Some(StatusCode::NOT_FOUND)).to_string(),
+            "Slack returned 404 Not Found: error"
+        );
+    }
+}
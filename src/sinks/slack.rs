@@ -0,0 +1,263 @@
+//! Slack, through the Web API or an incoming webhook.
+//!
+//! See https://api.slack.com/web and https://api.slack.com/messaging/webhooks
+
+use async_trait::async_trait;
+use reqwest::header::AUTHORIZATION;
+use serde_json::{json, Value};
+
+use super::{check_response, plain_text, Sink, SinkError};
+use crate::{
+    discord::{errors::RequestError, models::ChannelOptions},
+    selfoss::models::SelfossItem,
+};
+
+const SLACK: &str = "Slack";
+/// Slack truncates longer messages.
+const MAX_TEXT_LENGTH: usize = 4000;
+
+pub enum SlackSink {
+    /// Uses a bot token, which allows creating channels and editing messages.
+    WebApi { base_url: String, token: String },
+    /// Always posts in the channel of the webhook.
+    Webhook { url: String },
+}
+
+/// Escapes the characters that Slack uses for links and mentions.
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Formats an item as Slack `mrkdwn`, a bold title linking to the item followed by its text.
+pub fn format_item(item: &SelfossItem) -> String {
+    let title = match item.link.is_empty() {
+        true => format!("*{}*", escape(&item.title)),
+        false => format!("*<{}|{}>*", item.link, escape(&item.title)),
+    };
+    let text = plain_text(item, MAX_TEXT_LENGTH);
+    match text.is_empty() {
+        true => title,
+        false => format!("{}\n{}", title, escape(&text)),
+    }
+}
+
+impl SlackSink {
+    /// Calls a method of the Web API, which reports errors with `"ok": false`.
+    async fn call(&self, method: &str, payload: Value) -> Result<Value, RequestError> {
+        let SlackSink::WebApi { base_url, token } = self else {
+            unreachable!("webhooks do not have methods");
+        };
+        let response = reqwest::Client::new()
+            .post(format!("{}/{}", base_url, method))
+            .header(AUTHORIZATION, format!("Bearer {}", token))
+            .json(&payload)
+            .send()
+            .await?;
+        let body: Value = check_response(SLACK, response).await?.json().await?;
+        match body["ok"].as_bool() {
+            Some(true) => Ok(body),
+            _ => Err(RequestError::Sink(SinkError::new(
+                SLACK,
+                body["error"].as_str().unwrap_or("unknown error"),
+            ))),
+        }
+    }
+
+    async fn find_channel(&self, name: &str) -> Result<Option<String>, RequestError> {
+        let mut cursor = String::new();
+        loop {
+            let payload = json!({"cursor": cursor, "exclude_archived": true, "limit": 1000});
+            let body = self.call("conversations.list", payload).await?;
+            let channels = body["channels"].as_array().into_iter().flatten();
+            if let Some(channel) = channels.into_iter().find(|c| c["name"] == name) {
+                return Ok(channel["id"].as_str().map(str::to_string));
+            }
+            match body["response_metadata"]["next_cursor"].as_str() {
+                Some(next) if !next.is_empty() => cursor = next.to_string(),
+                _ => return Ok(None),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Sink for SlackSink {
+    async fn ensure_destination(
+        &self,
+        name: &str,
+        options: &ChannelOptions,
+    ) -> Result<String, RequestError> {
+        if let SlackSink::Webhook { .. } = self {
+            return Ok(name.to_string());
+        }
+        if let Some(id) = self.find_channel(name).await? {
+            return Ok(id);
+        }
+        let body = self
+            .call("conversations.create", json!({"name": name}))
+            .await?;
+        let id = body["channel"]["id"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        if let Some(topic) = &options.topic {
+            let payload = json!({"channel": id, "topic": topic});
+            self.call("conversations.setTopic", payload).await?;
+        }
+        Ok(id)
+    }
+
+    async fn post_item(
+        &self,
+        destination: &str,
+        item: &SelfossItem,
+    ) -> Result<String, RequestError> {
+        let text = format_item(item);
+        match self {
+            SlackSink::Webhook { url } => {
+                let response = reqwest::Client::new()
+                    .post(url)
+                    .json(&json!({"text": text}))
+                    .send()
+                    .await
+                    // The URL of a webhook is its secret.
+                    .map_err(reqwest::Error::without_url)?;
+                check_response(SLACK, response).await?;
+                Ok(String::new())
+            }
+            SlackSink::WebApi { .. } => {
+                let payload = json!({"channel": destination, "text": text});
+                let body = self.call("chat.postMessage", payload).await?;
+                Ok(body["ts"].as_str().unwrap_or_default().to_string())
+            }
+        }
+    }
+
+    async fn update(
+        &self,
+        destination: &str,
+        message_id: &str,
+        item: &SelfossItem,
+    ) -> Result<(), RequestError> {
+        if let SlackSink::Webhook { .. } = self {
+            return Err(SinkError::unsupported(SLACK, "Updating"));
+        }
+        let payload = json!({"channel": destination, "ts": message_id, "text": format_item(item)});
+        self.call("chat.update", payload).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, destination: &str, message_id: &str) -> Result<(), RequestError> {
+        if let SlackSink::Webhook { .. } = self {
+            return Err(SinkError::unsupported(SLACK, "Deleting"));
+        }
+        let payload = json!({"channel": destination, "ts": message_id});
+        self.call("chat.delete", payload).await?;
+        Ok(())
+    }
+
+    async fn publish(&self, _destination: &str, _message_id: &str) -> Result<(), RequestError> {
+        Err(SinkError::unsupported(SLACK, "Publishing"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use httpmock::{Method::POST, MockServer};
+    use serde_json::json;
+
+    use crate::{
+        discord::{errors::RequestError, models::ChannelOptions},
+        sinks::{
+            slack::{format_item, SlackSink},
+            Sink,
+        },
+        test::get_mock_item,
+    };
+
+    #[test]
+    fn test_format_item() {
+        let mut item = get_mock_item();
+        item.title = String::from("Q&A <live>");
+        item.link = String::from("https://example.com/qa");
+        assert_eq!(
+            format_item(&item),
+            "*<https://example.com/qa|Q&amp;A &lt;live&gt;>*\nMy content"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_web_api() {
+        let server = MockServer::start();
+        let list_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/conversations.list")
+                .header("authorization", "Bearer xoxb-test");
+            then.status(200).json_body(json!({
+                "ok": true,
+                "channels": [{"id": "C1", "name": "general"}],
+                "response_metadata": {"next_cursor": ""}
+            }));
+        });
+        let create_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/conversations.create")
+                .json_body(json!({"name": "my_channel"}));
+            then.status(200)
+                .json_body(json!({"ok": true, "channel": {"id": "C2"}}));
+        });
+        let post_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/chat.postMessage")
+                .json_body(json!({"channel": "C2", "text": "*<My link|My title>*\nMy content"}));
+            then.status(200)
+                .json_body(json!({"ok": true, "ts": "1702662036.000100"}));
+        });
+        let delete_mock = server.mock(|when, then| {
+            when.method(POST).path("/chat.delete");
+            then.status(200)
+                .json_body(json!({"ok": false, "error": "message_not_found"}));
+        });
+
+        let sink = SlackSink::WebApi {
+            base_url: server.base_url(),
+            token: String::from("xoxb-test"),
+        };
+        let options = ChannelOptions::default();
+        let channel = sink.ensure_destination("my_channel", &options).await;
+        assert_eq!(channel.unwrap(), "C2");
+        let message_id = sink.post_item("C2", &get_mock_item()).await;
+        assert_eq!(message_id.unwrap(), "1702662036.000100");
+        match sink.delete("C2", "1702662036.000100").await {
+            Err(RequestError::Sink(e)) => assert_eq!(e.message, "message_not_found"),
+            result => panic!("expected Slack error, got {:?}", result),
+        }
+
+        list_mock.assert_async().await;
+        create_mock.assert_async().await;
+        post_mock.assert_async().await;
+        delete_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_webhook() {
+        let server = MockServer::start();
+        let webhook_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/services/T0/B0/secret")
+                .json_body(json!({"text": "*<My link|My title>*\nMy content"}));
+            then.status(200).body("ok");
+        });
+
+        let sink = SlackSink::Webhook {
+            url: server.url("/services/T0/B0/secret"),
+        };
+        let message_id = sink.post_item("my_channel", &get_mock_item()).await;
+        assert_eq!(message_id.unwrap(), "");
+        assert!(sink.delete("my_channel", "").await.is_err());
+
+        webhook_mock.assert_async().await;
+    }
+}
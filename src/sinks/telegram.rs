@@ -0,0 +1,209 @@
+//! Telegram, through the Bot API.
+//!
+//! Bots cannot create chats, so destinations are mapped to existing chats in the settings. See
+//! https://core.telegram.org/bots/api
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+
+use super::{escape_html, plain_text, Sink, SinkError};
+use crate::{
+    discord::{errors::RequestError, models::ChannelOptions},
+    selfoss::models::SelfossItem,
+};
+
+const TELEGRAM: &str = "Telegram";
+/// Telegram allows 4096 characters after parsing the entities.
+const MAX_TEXT_LENGTH: usize = 3500;
+
+pub struct TelegramSink {
+    pub base_url: String,
+    pub token: String,
+    /// Chat ids by destination name.
+    pub chats: HashMap<String, String>,
+    /// Chat for destinations that are not in `chats`.
+    pub default_chat: Option<String>,
+}
+
+/// Formats an item as Telegram HTML, a bold title linking to the item followed by its text.
+pub fn format_item(item: &SelfossItem) -> String {
+    let title = match item.link.is_empty() {
+        true => format!("<b>{}</b>", escape_html(&item.title)),
+        false => format!(
+            "<b><a href=\"{}\">{}</a></b>",
+            escape_html(&item.link),
+            escape_html(&item.title)
+        ),
+    };
+    let text = plain_text(item, MAX_TEXT_LENGTH);
+    match text.is_empty() {
+        true => title,
+        false => format!("{}\n{}", title, escape_html(&text)),
+    }
+}
+
+impl TelegramSink {
+    /// Calls a method of the Bot API, returns its `result`.
+    async fn call(&self, method: &str, payload: Value) -> Result<Value, RequestError> {
+        let response = reqwest::Client::new()
+            .post(format!("{}/bot{}/{}", self.base_url, self.token, method))
+            .json(&payload)
+            .send()
+            .await
+            // The URL contains the token.
+            .map_err(reqwest::Error::without_url)?;
+        let status = response.status();
+        let body: Value = response.json().await.map_err(reqwest::Error::without_url)?;
+        match body["ok"].as_bool() {
+            Some(true) => Ok(body["result"].clone()),
+            _ => Err(RequestError::Sink(SinkError {
+                sink: TELEGRAM,
+                status: Some(status),
+                message: body["description"]
+                    .as_str()
+                    .unwrap_or("unknown error")
+                    .to_string(),
+            })),
+        }
+    }
+}
+
+#[async_trait]
+impl Sink for TelegramSink {
+    async fn ensure_destination(
+        &self,
+        name: &str,
+        _options: &ChannelOptions,
+    ) -> Result<String, RequestError> {
+        self.chats
+            .get(name)
+            .or(self.default_chat.as_ref())
+            .cloned()
+            .ok_or_else(|| {
+                RequestError::Sink(SinkError::new(
+                    TELEGRAM,
+                    format!("No chat configured for {:?}", name),
+                ))
+            })
+    }
+
+    async fn post_item(
+        &self,
+        destination: &str,
+        item: &SelfossItem,
+    ) -> Result<String, RequestError> {
+        let payload =
+            json!({"chat_id": destination, "text": format_item(item), "parse_mode": "HTML"});
+        let message = self.call("sendMessage", payload).await?;
+        Ok(message["message_id"].to_string())
+    }
+
+    async fn update(
+        &self,
+        destination: &str,
+        message_id: &str,
+        item: &SelfossItem,
+    ) -> Result<(), RequestError> {
+        let payload = json!({
+            "chat_id": destination,
+            "message_id": message_id.parse::<i64>().unwrap_or_default(),
+            "text": format_item(item),
+            "parse_mode": "HTML",
+        });
+        self.call("editMessageText", payload).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, destination: &str, message_id: &str) -> Result<(), RequestError> {
+        let payload = json!({
+            "chat_id": destination,
+            "message_id": message_id.parse::<i64>().unwrap_or_default(),
+        });
+        self.call("deleteMessage", payload).await?;
+        Ok(())
+    }
+
+    async fn publish(&self, _destination: &str, _message_id: &str) -> Result<(), RequestError> {
+        Err(SinkError::unsupported(TELEGRAM, "Publishing"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use httpmock::{Method::POST, MockServer};
+    use serde_json::json;
+
+    use crate::{
+        discord::{errors::RequestError, models::ChannelOptions},
+        sinks::{
+            telegram::{format_item, TelegramSink},
+            Sink,
+        },
+        test::get_mock_item,
+    };
+
+    #[test]
+    fn test_format_item() {
+        let mut item = get_mock_item();
+        item.link = String::new();
+        item.title = String::from("Fish & <chips>");
+        assert_eq!(
+            format_item(&item),
+            "<b>Fish &amp; &lt;chips&gt;</b>\nMy content"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_telegram_sink() {
+        let server = MockServer::start();
+        let send_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/botsecret/sendMessage")
+                .json_body(json!({
+                    "chat_id": "-100123",
+                    "text": "<b><a href=\"My link\">My title</a></b>\nMy content",
+                    "parse_mode": "HTML"
+                }));
+            then.status(200)
+                .json_body(json!({"ok": true, "result": {"message_id": 17}}));
+        });
+        let delete_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/botsecret/deleteMessage")
+                .json_body(json!({"chat_id": "-100123", "message_id": 17}));
+            then.status(400).json_body(json!({
+                "ok": false,
+                "error_code": 400,
+                "description": "Bad Request: message to delete not found"
+            }));
+        });
+
+        let sink = TelegramSink {
+            base_url: server.base_url(),
+            token: String::from("secret"),
+            chats: HashMap::from([(String::from("my_channel"), String::from("-100123"))]),
+            default_chat: None,
+        };
+        let options = ChannelOptions::default();
+        let chat_id = sink
+            .ensure_destination("my_channel", &options)
+            .await
+            .unwrap();
+        assert!(sink.ensure_destination("other", &options).await.is_err());
+        let message_id = sink.post_item(&chat_id, &get_mock_item()).await;
+        assert_eq!(message_id.unwrap(), "17");
+        match sink.delete(&chat_id, "17").await {
+            Err(RequestError::Sink(e)) => {
+                assert_eq!(e.message, "Bad Request: message to delete not found")
+            }
+            result => panic!("expected Telegram error, got {:?}", result),
+        }
+
+        send_mock.assert_async().await;
+        delete_mock.assert_async().await;
+    }
+}
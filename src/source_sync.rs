@@ -0,0 +1,534 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+use tracing::{debug, info, instrument, warn};
+
+use crate::{
+    config::{ChannelLayout, Config, SourceSyncConfig, TagColors},
+    discord::{
+        adapter::{
+            create_channel, create_role, get_channels, get_roles, modify_channel, modify_role,
+        },
+        errors::RequestError,
+        models::{ChannelOptions, ChannelUpdate, DiscordChannel, GUILD_CATEGORY},
+    },
+    selfoss::{
+        adapter::get_tags,
+        models::{SelfossSource, SelfossTag},
+    },
+    state::{SourceChannel, State},
+};
+
+/// Changes made to the Discord channels by `sync_sources`.
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct SourceSyncStats {
+    pub created: usize,
+    pub renamed: usize,
+    /// Channels moved to the category of their tag.
+    pub moved: usize,
+    pub archived: usize,
+}
+
+/// The id of the category with this name, which is created if it does not exist yet.
+pub async fn find_or_create_category(
+    config: &Config,
+    channels: &mut Vec<DiscordChannel>,
+    name: &str,
+) -> Result<String, RequestError> {
+    if let Some(category) = channels
+        .iter()
+        .find(|c| c.kind == GUILD_CATEGORY && c.name == name)
+    {
+        return Ok(category.id.clone());
+    }
+    let options = ChannelOptions {
+        kind: Some(GUILD_CATEGORY),
+        ..Default::default()
+    };
+    let category = create_channel(config, name, &options).await?;
+    info!(category = name, "Created category");
+    let id = category.id.clone();
+    channels.push(category);
+    Ok(id)
+}
+
+/// Keeps a role with the colour of each tag.
+async fn sync_tag_roles(config: &Config, tags: &[SelfossTag]) -> Result<(), RequestError> {
+    let roles = get_roles(config).await?;
+    for tag in tags {
+        let Some(color) = tag.rgb() else {
+            continue;
+        };
+        match roles.iter().find(|r| r.name == tag.tag) {
+            Some(role) if role.color == color => {}
+            Some(role) => {
+                modify_role(config, &role.id, color).await?;
+                info!(
+                    tag = tag.tag,
+                    color = tag.color,
+                    "Updated colour of tag role"
+                );
+            }
+            None => {
+                create_role(config, &tag.tag, color).await?;
+                info!(tag = tag.tag, color = tag.color, "Created tag role");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The distinct tags of `sources`, without colours.
+fn source_tags(sources: &[SelfossSource]) -> Vec<SelfossTag> {
+    let mut tags: Vec<SelfossTag> = vec![];
+    for tag in sources.iter().flat_map(|s| &s.tags) {
+        if !tags.iter().any(|t| &t.tag == tag) {
+            tags.push(SelfossTag {
+                tag: tag.clone(),
+                color: String::new(),
+            });
+        }
+    }
+    tags
+}
+
+/// Creates a channel for every Selfoss source, renames the channels of renamed sources and moves
+/// the channels of removed sources to the archive category. With the tags layout, channels are
+/// also moved to the category of the first tag of their source.
+#[instrument(skip_all)]
+pub async fn sync_sources(
+    config: &Config,
+    source_sync: &SourceSyncConfig,
+    state: &mut State,
+) -> Result<SourceSyncStats, RequestError> {
+    let sources: Vec<SelfossSource> = config
+        .source()
+        .list_feeds()
+        .await?
+        .into_iter()
+        .filter(|source| config.handles_source(&source.title))
+        .collect();
+    let mut channels = get_channels(config).await?;
+    let mut stats = SourceSyncStats::default();
+
+    let tags = match source_sync.layout {
+        ChannelLayout::Tags if config.source.is_none() => get_tags(config).await?,
+        // Other readers do not have tag colours, only the tags of the sources.
+        ChannelLayout::Tags => source_tags(&sources),
+        ChannelLayout::Flat => vec![],
+    };
+    let mut categories = HashMap::new();
+    for tag in &tags {
+        let category = find_or_create_category(config, &mut channels, &tag.tag).await?;
+        categories.insert(tag.tag.clone(), category);
+    }
+    if source_sync.tag_colors == Some(TagColors::Role) {
+        sync_tag_roles(config, &tags).await?;
+    }
+
+    for source in &sources {
+        let name = config.channel_name(&source.title);
+        let route = config.route_for_source(&source.title);
+        // Channels set by a route are shared by its sources, so they are left as they are.
+        let shared = route.is_some_and(|r| r.channel.is_some());
+        let mut options = route.map(|r| r.channel_options()).unwrap_or_default();
+        let tag = source
+            .tags
+            .first()
+            .and_then(|t| tags.iter().find(|tag| &tag.tag == t));
+        if let Some(tag) = tag.filter(|_| !shared) {
+            options.parent_id = options
+                .parent_id
+                .or_else(|| categories.get(&tag.tag).cloned());
+            if source_sync.tag_colors == Some(TagColors::Topic) && !tag.color.is_empty() {
+                options.topic = Some(format!("{} ({})", tag.tag, tag.color));
+            }
+        }
+
+        let tracked = state
+            .sources
+            .get(&source.id)
+            .filter(|_| !shared)
+            .and_then(|s| channels.iter().position(|c| c.id == s.channel_id));
+        let existing = tracked.or_else(|| {
+            channels
+                .iter()
+                .position(|c| c.kind != GUILD_CATEGORY && c.name == name)
+        });
+
+        let channel_id = match existing {
+            Some(index) => {
+                let channel = &mut channels[index];
+                let mut update = ChannelUpdate::default();
+                if tracked.is_some() && channel.name != name {
+                    update.name = Some(name.clone());
+                }
+                if !shared && source_sync.layout == ChannelLayout::Tags {
+                    if options.parent_id.is_some() && channel.parent_id != options.parent_id {
+                        update.parent_id = options.parent_id.clone();
+                    }
+                    if options.topic.is_some() && channel.topic != options.topic {
+                        update.topic = options.topic.clone();
+                    }
+                }
+                if update != ChannelUpdate::default() {
+                    modify_channel(config, &channel.id, &update).await?;
+                    if let Some(name) = update.name {
+                        info!(from = channel.name, to = name, "Renamed channel");
+                        channel.name = name;
+                        stats.renamed += 1;
+                    }
+                    if let Some(parent_id) = update.parent_id {
+                        info!(
+                            channel = channel.name,
+                            "Moved channel to the category of its tag"
+                        );
+                        channel.parent_id = Some(parent_id);
+                        stats.moved += 1;
+                    }
+                    if update.topic.is_some() {
+                        channel.topic = update.topic;
+                    }
+                }
+                channel.id.clone()
+            }
+            None => {
+                let channel = create_channel(config, &name, &options).await?;
+                info!(channel = name, source = source.title, "Created channel");
+                stats.created += 1;
+                let id = channel.id.clone();
+                channels.push(channel);
+                id
+            }
+        };
+
+        if !shared {
+            let tracked = SourceChannel {
+                title: source.title.clone(),
+                channel_id,
+                missing_runs: 0,
+            };
+            state.sources.insert(source.id, tracked);
+        }
+    }
+
+    let removed: Vec<u64> = state
+        .sources
+        .keys()
+        .filter(|id| !sources.iter().any(|s| s.id == **id))
+        .copied()
+        .collect();
+    if sources.is_empty() && !removed.is_empty() {
+        // More likely a hiccup of the reader than all sources removed at once.
+        warn!("The reader returned no sources, not archiving any channels");
+        return Ok(stats);
+    }
+    for id in removed {
+        let source = state.sources.get_mut(&id).unwrap();
+        source.missing_runs += 1;
+        if source.missing_runs < source_sync.archive_after_runs {
+            debug!(
+                source = source.title,
+                missing_runs = source.missing_runs,
+                "Source is missing, not archiving its channel yet"
+            );
+            continue;
+        }
+        // The source is only forgotten once its channel is archived, so that a failure is retried.
+        let source = source.clone();
+        let Some(channel) = channels.iter().find(|c| c.id == source.channel_id) else {
+            state.sources.remove(&id);
+            continue;
+        };
+        let channel_id = channel.id.clone();
+        let category =
+            find_or_create_category(config, &mut channels, &source_sync.archive_category).await?;
+        let update = ChannelUpdate {
+            parent_id: Some(category),
+            ..Default::default()
+        };
+        modify_channel(config, &channel_id, &update).await?;
+        state.sources.remove(&id);
+        info!(source = source.title, "Archived channel of removed source");
+        stats.archived += 1;
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use httpmock::Method::{GET, PATCH, POST};
+    use serde_json::json;
+
+    use crate::{
+        config::{ChannelLayout, SourceSyncConfig, TagColors},
+        source_sync::{sync_sources, SourceSyncStats},
+        state::{SourceChannel, State},
+        test::start_server,
+    };
+
+    #[tokio::test]
+    async fn test_sync_sources() {
+        let (server, config) = start_server();
+
+        server.mock(|when, then| {
+            when.method(GET).path("/sources/list");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(r#"[{"id": 12, "title": "my channel"}, {"id": 13, "title": "Brand New"}]"#);
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/guilds/123/channels");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(
+                    r#"[
+                        {"id": "c12", "name": "old-name", "type": 0},
+                        {"id": "c99", "name": "gone", "type": 0}
+                    ]"#,
+                );
+        });
+        let rename_mock = server.mock(|when, then| {
+            when.method(PATCH)
+                .path("/channels/c12")
+                .json_body(json!({"name": "my-channel"}));
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(r#"{"id": "c12", "name": "my-channel", "type": 0}"#);
+        });
+        let create_channel_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/guilds/123/channels")
+                .json_body(json!({"name": "brand-new"}));
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(r#"{"id": "c13", "name": "brand-new", "type": 0}"#);
+        });
+        let create_category_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/guilds/123/channels")
+                .json_body(json!({"name": "archived", "type": 4}));
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(r#"{"id": "archive_id", "name": "archived", "type": 4}"#);
+        });
+        let archive_mock = server.mock(|when, then| {
+            when.method(PATCH)
+                .path("/channels/c99")
+                .json_body(json!({"parent_id": "archive_id"}));
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(r#"{"id": "c99", "name": "gone", "type": 0, "parent_id": "archive_id"}"#);
+        });
+
+        let tracked = |title: &str, channel_id: &str| SourceChannel {
+            title: title.to_string(),
+            channel_id: channel_id.to_string(),
+            missing_runs: 0,
+        };
+        let mut state = State {
+            sources: HashMap::from([
+                (12, tracked("old name", "c12")),
+                (
+                    99,
+                    SourceChannel {
+                        missing_runs: 2,
+                        ..tracked("gone", "c99")
+                    },
+                ),
+            ]),
+            ..Default::default()
+        };
+        let source_sync = SourceSyncConfig::default();
+        let stats = sync_sources(&config, &source_sync, &mut state)
+            .await
+            .expect("Error syncing sources");
+
+        assert_eq!(
+            stats,
+            SourceSyncStats {
+                created: 1,
+                renamed: 1,
+                moved: 0,
+                archived: 1,
+            }
+        );
+        assert_eq!(
+            state.sources,
+            HashMap::from([
+                (12, tracked("my channel", "c12")),
+                (13, tracked("Brand New", "c13")),
+            ])
+        );
+        rename_mock.assert_async().await;
+        create_channel_mock.assert_async().await;
+        create_category_mock.assert_async().await;
+        archive_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_failed_archive_is_retried() {
+        let (server, config) = start_server();
+
+        server.mock(|when, then| {
+            when.method(GET).path("/sources/list");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(r#"[{"id": 12, "title": "my channel"}]"#);
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/guilds/123/channels");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(
+                    r#"[
+                        {"id": "archive_id", "name": "archived", "type": 4},
+                        {"id": "c12", "name": "my-channel", "type": 0},
+                        {"id": "c99", "name": "gone", "type": 0}
+                    ]"#,
+                );
+        });
+        let archive_mock = server.mock(|when, then| {
+            when.method(PATCH).path("/channels/c99");
+            then.status(400)
+                .header("content-type", "application/json")
+                .body(r#"{"message": "Invalid Form Body", "code": 50035}"#);
+        });
+
+        let tracked = SourceChannel {
+            title: String::from("gone"),
+            channel_id: String::from("c99"),
+            missing_runs: 0,
+        };
+        let mut state = State {
+            sources: HashMap::from([(99, tracked.clone())]),
+            ..Default::default()
+        };
+        let source_sync = SourceSyncConfig {
+            archive_after_runs: 2,
+            ..Default::default()
+        };
+        // The source has to be missing twice in a row.
+        let stats = sync_sources(&config, &source_sync, &mut state)
+            .await
+            .expect("Error syncing sources");
+        assert_eq!(stats.archived, 0);
+        archive_mock.assert_hits_async(0).await;
+        sync_sources(&config, &source_sync, &mut state)
+            .await
+            .expect_err("Archiving did not fail");
+        archive_mock.assert_async().await;
+        assert_eq!(state.sources[&99].missing_runs, 2);
+        assert_eq!(state.sources[&99].channel_id, "c99");
+    }
+
+    #[tokio::test]
+    async fn test_empty_source_list_archives_nothing() {
+        let (server, config) = start_server();
+
+        server.mock(|when, then| {
+            when.method(GET).path("/sources/list");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body("[]");
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/guilds/123/channels");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(r#"[{"id": "c99", "name": "gone", "type": 0}]"#);
+        });
+        let archive_mock = server.mock(|when, then| {
+            when.method(PATCH).path("/channels/c99");
+            then.status(200);
+        });
+
+        let tracked = SourceChannel {
+            title: String::from("gone"),
+            channel_id: String::from("c99"),
+            missing_runs: 5,
+        };
+        let mut state = State {
+            sources: HashMap::from([(99, tracked.clone())]),
+            ..Default::default()
+        };
+        let stats = sync_sources(&config, &SourceSyncConfig::default(), &mut state)
+            .await
+            .expect("Error syncing sources");
+        assert_eq!(stats.archived, 0);
+        archive_mock.assert_hits_async(0).await;
+        assert_eq!(state.sources, HashMap::from([(99, tracked)]));
+    }
+
+    #[tokio::test]
+    async fn test_sync_sources_by_tag() {
+        let (server, config) = start_server();
+
+        server.mock(|when, then| {
+            when.method(GET).path("/sources/list");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("src/assets/selfoss_sources_mock_response.json");
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/tags");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(r##"[{"tag": "news", "color": "#ff0000", "unread": 3}]"##);
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/guilds/123/channels");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(r#"[{"id": "c12", "name": "my-channel", "type": 0}]"#);
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/guilds/123/roles");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(r#"[{"id": "r1", "name": "@everyone", "color": 0}]"#);
+        });
+        let create_category_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/guilds/123/channels")
+                .json_body(json!({"name": "news", "type": 4}));
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(r#"{"id": "news_id", "name": "news", "type": 4}"#);
+        });
+        let move_mock = server.mock(|when, then| {
+            when.method(PATCH)
+                .path("/channels/c12")
+                .json_body(json!({"parent_id": "news_id"}));
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(r#"{"id": "c12", "name": "my-channel", "type": 0, "parent_id": "news_id"}"#);
+        });
+        let create_role_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/guilds/123/roles")
+                .json_body(json!({"name": "news", "color": 0xff0000}));
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(r#"{"id": "r2", "name": "news", "color": 16711680}"#);
+        });
+
+        let source_sync = SourceSyncConfig {
+            layout: ChannelLayout::Tags,
+            tag_colors: Some(TagColors::Role),
+            ..Default::default()
+        };
+        let mut state = State::default();
+        let stats = sync_sources(&config, &source_sync, &mut state)
+            .await
+            .expect("Error syncing sources");
+
+        assert_eq!(stats.moved, 1);
+        assert_eq!(stats.created, 0);
+        create_category_mock.assert_async().await;
+        move_mock.assert_async().await;
+        create_role_mock.assert_async().await;
+    }
+}
@@ -0,0 +1,518 @@
+//! RSS, Atom and JSON feeds fetched directly, without a feed reader.
+//!
+//! Feeds are fetched with conditional requests. Entries that are not marked as read yet are kept
+//! in a state file of their own, next to the GUIDs of the entries that were, so they are posted
+//! once even if the feed has not changed since.
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fs,
+    io::ErrorKind,
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use chrono::Utc;
+use feed_rs::{model::Entry, parser};
+use reqwest::{
+    header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, USER_AGENT},
+    StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, MutexGuard};
+use tracing::{debug, warn};
+
+use super::{check_response, Source, SourceError, UNREAD_LIMIT};
+use crate::{
+    config::FeedConfig,
+    discord::errors::RequestError,
+    metrics::Metrics,
+    selfoss::models::{SelfossItem, SelfossSource},
+};
+
+const FEEDS: &str = "Feeds";
+
+pub struct Feeds {
+    feeds: Vec<FeedConfig>,
+    state_path: String,
+    metrics: Arc<Metrics>,
+    /// Loaded from `state_path` when first needed.
+    state: Mutex<Option<FeedState>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct FeedState {
+    /// State of every feed, by URL.
+    #[serde(default)]
+    feeds: BTreeMap<String, FeedEntries>,
+    /// Whether the state changed since it was last written.
+    #[serde(skip)]
+    changed: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct FeedEntries {
+    /// Title of the feed from its last successful fetch.
+    title: Option<String>,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    /// GUIDs of the entries that were marked as read and are still in the feed.
+    #[serde(default)]
+    seen: BTreeSet<String>,
+    /// Entries that were not marked as read yet, by GUID.
+    #[serde(default)]
+    unread: BTreeMap<String, SelfossItem>,
+}
+
+/// Id of an entry that stays the same between runs, based on its feed and GUID (FNV-1a).
+fn item_id(url: &str, guid: &str) -> u64 {
+    [url, guid]
+        .join("\n")
+        .bytes()
+        .fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+}
+
+fn to_item(feed: &FeedConfig, title: &str, entry: Entry) -> SelfossItem {
+    let thumbnail = entry
+        .media
+        .iter()
+        .flat_map(|media| &media.thumbnails)
+        .map(|thumbnail| thumbnail.image.uri.clone())
+        .next();
+    SelfossItem {
+        id: item_id(&feed.url, &entry.id),
+        title: entry.title.map(|t| t.content).unwrap_or_default(),
+        sourcetitle: title.to_string(),
+        content: entry
+            .content
+            .and_then(|c| c.body)
+            .or(entry.summary.map(|s| s.content))
+            .unwrap_or_default(),
+        datetime: entry.published.or(entry.updated).unwrap_or_else(Utc::now),
+        link: entry
+            .links
+            .into_iter()
+            .find(|l| l.rel.as_deref().is_none_or(|rel| rel == "alternate"))
+            .map(|l| l.href)
+            .unwrap_or_default(),
+        author: entry
+            .authors
+            .into_iter()
+            .next()
+            .and_then(|a| a.name)
+            .filter(|a| !a.is_empty()),
+        tags: feed.tags.clone(),
+        unread: true,
+        starred: false,
+        thumbnail,
+    }
+}
+
+fn state_error(message: String) -> RequestError {
+    RequestError::Source(SourceError::new(FEEDS, message))
+}
+
+impl FeedState {
+    fn load(path: &str) -> Result<FeedState, RequestError> {
+        match fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map_err(|e| state_error(format!("Invalid feed state file {:?}: {}", path, e))),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(FeedState::default()),
+            Err(e) => Err(state_error(format!(
+                "Could not read feed state file {:?}: {}",
+                path, e
+            ))),
+        }
+    }
+
+    fn save(&self, path: &str) -> Result<(), RequestError> {
+        let contents = serde_json::to_string_pretty(self)?;
+        fs::write(path, contents)
+            .map_err(|e| state_error(format!("Could not write feed state file {:?}: {}", path, e)))
+    }
+}
+
+impl Feeds {
+    pub fn new(feeds: Vec<FeedConfig>, state_path: String, metrics: Arc<Metrics>) -> Self {
+        Feeds {
+            feeds,
+            state_path,
+            metrics,
+            state: Mutex::new(None),
+        }
+    }
+
+    async fn state(&self) -> Result<MutexGuard<'_, Option<FeedState>>, RequestError> {
+        let mut state = self.state.lock().await;
+        if state.is_none() {
+            *state = Some(FeedState::load(&self.state_path)?);
+        }
+        Ok(state)
+    }
+
+    /// Fetches a feed unless it has not changed, and adds its new entries to `entries`.
+    async fn fetch(
+        &self,
+        feed: &FeedConfig,
+        entries: &mut FeedEntries,
+    ) -> Result<(), RequestError> {
+        let mut request = reqwest::Client::new().get(&feed.url).header(
+            USER_AGENT,
+            concat!("selfoss-discord/", env!("CARGO_PKG_VERSION")),
+        );
+        if let Some(etag) = &entries.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &entries.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+        let response = request.send().await?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            debug!(url = feed.url, "Feed has not changed");
+            return Ok(());
+        }
+        let response = check_response(FEEDS, response).await?;
+        let header = |name| {
+            let value = response.headers().get(name)?.to_str().ok()?;
+            Some(value.to_string())
+        };
+        let (etag, last_modified) = (header(ETAG), header(LAST_MODIFIED));
+        let body = response.bytes().await?;
+        let parsed = parser::Builder::new()
+            .base_uri(Some(&feed.url))
+            .build()
+            .parse(body.as_ref())
+            .map_err(|e| {
+                let message = format!("Could not parse {}: {}", feed.url, e);
+                RequestError::Source(SourceError::new(FEEDS, message))
+            })?;
+
+        entries.etag = etag;
+        entries.last_modified = last_modified;
+        entries.title = parsed.title.map(|t| t.content);
+        let title = feed
+            .title
+            .clone()
+            .or(entries.title.clone())
+            .unwrap_or_else(|| feed.url.clone());
+        // Entries that left the feed will not come back, so they do not have to be remembered.
+        let guids: HashSet<&String> = parsed.entries.iter().map(|e| &e.id).collect();
+        entries.seen.retain(|guid| guids.contains(guid));
+        for entry in parsed.entries {
+            if !entries.seen.contains(&entry.id) && !entries.unread.contains_key(&entry.id) {
+                let guid = entry.id.clone();
+                entries.unread.insert(guid, to_item(feed, &title, entry));
+            }
+        }
+        debug!(
+            url = feed.url,
+            unread = entries.unread.len(),
+            "Fetched feed"
+        );
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Source for Feeds {
+    fn name(&self) -> &'static str {
+        FEEDS
+    }
+
+    async fn fetch_unread(&self) -> Result<Vec<SelfossItem>, RequestError> {
+        let mut items = self.fetch_all_unread().await?;
+        items.truncate(UNREAD_LIMIT);
+        debug!(items = items.len(), "Fetched unread items");
+        Ok(items)
+    }
+
+    async fn fetch_all_unread(&self) -> Result<Vec<SelfossItem>, RequestError> {
+        let mut guard = self.state().await?;
+        let state = guard.as_mut().unwrap();
+        // Forget the feeds that were removed from the settings.
+        state
+            .feeds
+            .retain(|url, _| self.feeds.iter().any(|f| &f.url == url));
+
+        let mut ok = true;
+        for feed in &self.feeds {
+            let entries = state.feeds.entry(feed.url.clone()).or_default();
+            if let Err(e) = self.fetch(feed, entries).await {
+                warn!(url = feed.url, "Could not fetch feed: {}", e);
+                ok = false;
+            }
+        }
+        self.metrics.record_selfoss_fetch(ok);
+        state.changed = true;
+
+        let mut items: Vec<SelfossItem> = state
+            .feeds
+            .values()
+            .flat_map(|entries| entries.unread.values().cloned())
+            .collect();
+        items.sort_by_key(|item| Reverse(item.datetime));
+        Ok(items)
+    }
+
+    async fn mark_read(&self, id: u64) -> Result<(), RequestError> {
+        let mut guard = self.state().await?;
+        let state = guard.as_mut().unwrap();
+        for entries in state.feeds.values_mut() {
+            let Some(guid) = entries
+                .unread
+                .iter()
+                .find_map(|(guid, item)| Some(guid.clone()).filter(|_| item.id == id))
+            else {
+                continue;
+            };
+            entries.unread.remove(&guid);
+            entries.seen.insert(guid);
+            state.changed = true;
+            debug!(id, "Marked item as read");
+            return Ok(());
+        }
+        debug!(id, "Item was already read");
+        Ok(())
+    }
+
+    async fn star(&self, _id: u64) -> Result<(), RequestError> {
+        Err(RequestError::Source(SourceError::new(
+            FEEDS,
+            "Items of feeds cannot be starred",
+        )))
+    }
+
+    async fn list_feeds(&self) -> Result<Vec<SelfossSource>, RequestError> {
+        let guard = self.state().await?;
+        let state = guard.as_ref().unwrap();
+        Ok(self
+            .feeds
+            .iter()
+            .map(|feed| SelfossSource {
+                id: item_id(&feed.url, ""),
+                title: feed
+                    .title
+                    .clone()
+                    .or_else(|| state.feeds.get(&feed.url)?.title.clone())
+                    .unwrap_or_else(|| feed.url.clone()),
+                tags: feed.tags.clone(),
+                spout: String::new(),
+            })
+            .collect())
+    }
+
+    async fn save(&self) -> Result<(), RequestError> {
+        let mut guard = self.state.lock().await;
+        if let Some(state) = guard.as_mut().filter(|state| state.changed) {
+            state.save(&self.state_path)?;
+            state.changed = false;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs, sync::Arc};
+
+    use httpmock::{Method::GET, MockServer};
+    use serde_json::json;
+
+    use crate::{
+        config::FeedConfig,
+        discord::errors::RequestError,
+        metrics::Metrics,
+        sources::{feeds::Feeds, Source},
+        test::get_mock_item,
+    };
+
+    const RSS: &str = r#"<?xml version="1.0"?>
+<rss version="2.0">
+  <channel>
+    <title>my_channel</title>
+    <item>
+      <guid>item-1</guid>
+      <title>My title</title>
+      <link>https://example.com/my-link</link>
+      <description>My content</description>
+      <author>Me</author>
+      <pubDate>Fri, 15 Dec 2023 17:40:36 GMT</pubDate>
+    </item>
+  </channel>
+</rss>"#;
+
+    const ATOM: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title>Atom feed</title>
+  <id>urn:feed</id>
+  <updated>2023-12-15T17:40:36Z</updated>
+  <entry>
+    <id>urn:entry:1</id>
+    <title>Atom title</title>
+    <link rel="alternate" href="https://example.com/atom"/>
+    <updated>2023-12-15T17:40:36Z</updated>
+    <summary>Atom summary</summary>
+  </entry>
+</feed>"#;
+
+    fn temp_state_path(name: &str) -> String {
+        let path = env::temp_dir().join(format!(
+            "selfoss-discord-{}-{}.json",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        path.to_string_lossy().into_owned()
+    }
+
+    fn feed(url: String, title: Option<&str>) -> FeedConfig {
+        FeedConfig {
+            url,
+            title: title.map(str::to_string),
+            tags: vec![String::from("news")],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rss_conditional_get_and_mark_read() {
+        let server = MockServer::start();
+        let state_path = temp_state_path("rss");
+        let mut changed_mock = server.mock(|when, then| {
+            when.method(GET).path("/rss.xml");
+            then.status(200).header("etag", "\"v1\"").body(RSS);
+        });
+
+        let source = Feeds::new(
+            vec![feed(server.url("/rss.xml"), None)],
+            state_path.clone(),
+            Arc::new(Metrics::new()),
+        );
+        let items = source.fetch_unread().await.unwrap();
+        let mock_item = get_mock_item();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].title, mock_item.title);
+        assert_eq!(items[0].sourcetitle, mock_item.sourcetitle);
+        assert_eq!(items[0].content, mock_item.content);
+        assert_eq!(items[0].link, "https://example.com/my-link");
+        assert_eq!(items[0].datetime, mock_item.datetime);
+        assert_eq!(items[0].tags, mock_item.tags);
+        assert!(items[0].unread);
+        changed_mock.assert_async().await;
+        changed_mock.delete();
+        source.save().await.unwrap();
+
+        // Unread entries are kept while the feed has not changed, until they are marked as read.
+        let mut unchanged_mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/rss.xml")
+                .header("if-none-match", "\"v1\"");
+            then.status(304);
+        });
+        let source = Feeds::new(
+            vec![feed(server.url("/rss.xml"), Some("Renamed"))],
+            state_path.clone(),
+            Arc::new(Metrics::new()),
+        );
+        assert_eq!(source.fetch_unread().await.unwrap(), items);
+        source.mark_read(items[0].id).await.unwrap();
+        assert!(source.fetch_unread().await.unwrap().is_empty());
+        // The state file is only written at the end of the run.
+        assert!(fs::read_to_string(&state_path)
+            .unwrap()
+            .contains("\"unread\": {\n"));
+        source.save().await.unwrap();
+        unchanged_mock.assert_hits_async(2).await;
+        assert_eq!(
+            source.list_feeds().await.unwrap()[0].title,
+            String::from("Renamed")
+        );
+
+        unchanged_mock.delete();
+
+        // Seen entries are not posted again when the feed changes.
+        server.mock(|when, then| {
+            when.method(GET).path("/rss.xml");
+            then.status(200).header("etag", "\"v2\"").body(RSS);
+        });
+        assert!(source.fetch_unread().await.unwrap().is_empty());
+        source.save().await.unwrap();
+        let state = fs::read_to_string(&state_path).unwrap();
+        assert!(state.contains("\"item-1\"") && state.contains("v2"));
+        fs::remove_file(&state_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_atom_and_json_feed() {
+        let server = MockServer::start();
+        let state_path = temp_state_path("atom-json");
+        server.mock(|when, then| {
+            when.method(GET).path("/atom.xml");
+            then.status(200).body(ATOM);
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/feed.json");
+            then.status(200).json_body(json!({
+                "version": "https://jsonfeed.org/version/1.1",
+                "title": "JSON feed",
+                "items": [{
+                    "id": "1",
+                    "url": "https://example.com/json",
+                    "title": "JSON title",
+                    "content_html": "<p>JSON content</p>",
+                    "date_published": "2023-12-16T10:00:00Z",
+                    "authors": [{"name": "Someone"}]
+                }]
+            }));
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/broken.xml");
+            then.status(200).body("not a feed");
+        });
+
+        let source = Feeds::new(
+            vec![
+                feed(server.url("/atom.xml"), None),
+                feed(server.url("/feed.json"), None),
+                feed(server.url("/broken.xml"), None),
+            ],
+            state_path.clone(),
+            Arc::new(Metrics::new()),
+        );
+        // A broken feed does not stop the others, and the newest item comes first.
+        let items = source.fetch_unread().await.unwrap();
+        let titles: Vec<(&str, &str, &str)> = items
+            .iter()
+            .map(|i| (i.sourcetitle.as_str(), i.title.as_str(), i.link.as_str()))
+            .collect();
+        assert_eq!(
+            titles,
+            vec![
+                ("JSON feed", "JSON title", "https://example.com/json"),
+                ("Atom feed", "Atom title", "https://example.com/atom"),
+            ]
+        );
+        assert_eq!(items[0].content, "<p>JSON content</p>");
+        assert_eq!(items[0].author.as_deref(), Some("Someone"));
+        assert_eq!(items[1].content, "Atom summary");
+
+        match source.star(items[0].id).await {
+            Err(RequestError::Source(e)) => assert_eq!(e.source, "Feeds"),
+            result => panic!("expected error, got {:?}", result),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_invalid_state_file() {
+        let state_path = temp_state_path("invalid");
+        fs::write(&state_path, "not json").unwrap();
+        let source = Feeds::new(vec![], state_path.clone(), Arc::new(Metrics::new()));
+        match source.fetch_unread().await {
+            Err(RequestError::Source(e)) => assert!(e.message.starts_with("Invalid feed state")),
+            result => panic!("expected error, got {:?}", result),
+        }
+        fs::remove_file(&state_path).unwrap();
+    }
+}
@@ -0,0 +1,394 @@
+//! FreshRSS, or any other reader with the Google Reader API.
+//!
+//! The base URL is the one of the API, e.g. `https://freshrss.example.com/api/greader.php`. See
+//! https://freshrss.github.io/FreshRSS/en/developers/06_GoogleReader_API.html
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use reqwest::{header::AUTHORIZATION, RequestBuilder, StatusCode};
+use serde::Deserialize;
+use tokio::sync::Mutex;
+use tracing::debug;
+
+use super::{check_response, Source, SourceError, UNREAD_LIMIT};
+use crate::{
+    discord::errors::RequestError,
+    metrics::Metrics,
+    selfoss::models::{SelfossItem, SelfossSource},
+};
+
+const FRESHRSS: &str = "FreshRSS";
+const ITEM_PREFIX: &str = "tag:google.com,2005:reader/item/";
+const LABEL_PREFIX: &str = "user/-/label/";
+const READ: &str = "user/-/state/com.google/read";
+const STARRED: &str = "user/-/state/com.google/starred";
+
+pub struct FreshRss {
+    base_url: String,
+    username: String,
+    password: String,
+    metrics: Arc<Metrics>,
+    /// Auth token from the login and the token for changing items, once logged in.
+    session: Mutex<Option<Session>>,
+}
+
+#[derive(Clone)]
+struct Session {
+    auth: String,
+    token: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Stream {
+    items: Vec<Item>,
+    /// Token of the next page, if there is one.
+    continuation: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Item {
+    id: String,
+    #[serde(default)]
+    title: String,
+    published: i64,
+    #[serde(default)]
+    alternate: Vec<Link>,
+    summary: Option<Content>,
+    author: Option<String>,
+    origin: Origin,
+    #[serde(default)]
+    categories: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Link {
+    href: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Content {
+    content: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Origin {
+    title: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Subscriptions {
+    subscriptions: Vec<Subscription>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Subscription {
+    id: String,
+    title: String,
+    #[serde(default)]
+    categories: Vec<Label>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Label {
+    label: String,
+}
+
+/// Parses the long form of an item id, `tag:google.com,2005:reader/item/<hex>`.
+fn parse_item_id(id: &str) -> u64 {
+    match id.strip_prefix(ITEM_PREFIX) {
+        Some(hex) => u64::from_str_radix(hex, 16).unwrap_or_default(),
+        None => id.parse().unwrap_or_default(),
+    }
+}
+
+impl From<Item> for SelfossItem {
+    fn from(item: Item) -> Self {
+        let is = |state: &str| item.categories.iter().any(|c| c == state);
+        SelfossItem {
+            id: parse_item_id(&item.id),
+            unread: !is(READ),
+            starred: is(STARRED),
+            thumbnail: None,
+            title: item.title,
+            sourcetitle: item.origin.title,
+            content: item.summary.map(|s| s.content).unwrap_or_default(),
+            datetime: DateTime::from_timestamp(item.published, 0).unwrap_or_else(Utc::now),
+            link: item
+                .alternate
+                .into_iter()
+                .next()
+                .map(|l| l.href)
+                .unwrap_or_default(),
+            author: item.author.filter(|a| !a.is_empty()),
+            tags: item
+                .categories
+                .iter()
+                .filter_map(|c| c.strip_prefix(LABEL_PREFIX))
+                .map(str::to_string)
+                .collect(),
+        }
+    }
+}
+
+impl FreshRss {
+    pub fn new(
+        base_url: String,
+        username: String,
+        password: String,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        FreshRss {
+            base_url,
+            username,
+            password,
+            metrics,
+            session: Mutex::new(None),
+        }
+    }
+
+    async fn session(&self) -> Result<Session, RequestError> {
+        let mut session = self.session.lock().await;
+        if let Some(session) = session.as_ref() {
+            return Ok(session.clone());
+        }
+        let form = [("Email", &self.username), ("Passwd", &self.password)];
+        let response = reqwest::Client::new()
+            .post(format!("{}/accounts/ClientLogin", self.base_url))
+            .form(&form)
+            .send()
+            .await?;
+        let body = check_response(FRESHRSS, response).await?.text().await?;
+        let Some(auth) = body.lines().find_map(|l| l.strip_prefix("Auth=")) else {
+            return Err(RequestError::Source(SourceError::new(
+                FRESHRSS,
+                "Login did not return an auth token",
+            )));
+        };
+        let auth = auth.to_string();
+        let response = reqwest::Client::new()
+            .get(format!("{}/reader/api/0/token", self.base_url))
+            .header(AUTHORIZATION, format!("GoogleLogin auth={}", auth))
+            .send()
+            .await?;
+        let token = check_response(FRESHRSS, response).await?.text().await?;
+        debug!("Logged in to FreshRSS");
+        Ok(session
+            .insert(Session {
+                auth,
+                token: token.trim().to_string(),
+            })
+            .clone())
+    }
+
+    /// Sends a request with the auth token, and logs in again next time if it has expired.
+    async fn send(
+        &self,
+        request: RequestBuilder,
+        session: &Session,
+    ) -> Result<reqwest::Response, RequestError> {
+        let response = request
+            .header(AUTHORIZATION, format!("GoogleLogin auth={}", session.auth))
+            .send()
+            .await?;
+        if response.status() == StatusCode::UNAUTHORIZED {
+            *self.session.lock().await = None;
+        }
+        check_response(FRESHRSS, response).await
+    }
+
+    /// A page of unread items, the first one without `continuation`.
+    async fn unread_page(&self, continuation: Option<String>) -> Result<Stream, RequestError> {
+        let result: Result<Stream, RequestError> = async {
+            let session = self.session().await?;
+            let mut query = vec![
+                ("xt", READ.to_string()),
+                ("n", UNREAD_LIMIT.to_string()),
+                ("output", String::from("json")),
+            ];
+            if let Some(continuation) = continuation {
+                query.push(("c", continuation));
+            }
+            let request = reqwest::Client::new()
+                .get(format!(
+                    "{}/reader/api/0/stream/contents/user/-/state/com.google/reading-list",
+                    self.base_url
+                ))
+                .query(&query);
+            Ok(self.send(request, &session).await?.json().await?)
+        }
+        .await;
+        self.metrics.record_selfoss_fetch(result.is_ok());
+        result
+    }
+
+    async fn edit_tag(&self, id: u64, tag: &str) -> Result<(), RequestError> {
+        let session = self.session().await?;
+        let form = [
+            ("i", format!("{}{:016x}", ITEM_PREFIX, id)),
+            ("a", tag.to_string()),
+            ("T", session.token.clone()),
+        ];
+        let request = reqwest::Client::new()
+            .post(format!("{}/reader/api/0/edit-tag", self.base_url))
+            .form(&form);
+        self.send(request, &session).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Source for FreshRss {
+    fn name(&self) -> &'static str {
+        FRESHRSS
+    }
+
+    async fn fetch_unread(&self) -> Result<Vec<SelfossItem>, RequestError> {
+        let stream = self.unread_page(None).await?;
+        let items: Vec<SelfossItem> = stream.items.into_iter().map(Into::into).collect();
+        debug!(items = items.len(), "Fetched unread items");
+        Ok(items)
+    }
+
+    async fn fetch_all_unread(&self) -> Result<Vec<SelfossItem>, RequestError> {
+        let mut items = vec![];
+        let mut continuation = None;
+        loop {
+            let stream = self.unread_page(continuation).await?;
+            items.extend(stream.items.into_iter().map(Into::into));
+            match stream.continuation {
+                Some(c) if !c.is_empty() => continuation = Some(c),
+                _ => return Ok(items),
+            }
+        }
+    }
+
+    async fn mark_read(&self, id: u64) -> Result<(), RequestError> {
+        self.edit_tag(id, READ).await?;
+        debug!(id, "Marked item as read");
+        Ok(())
+    }
+
+    async fn star(&self, id: u64) -> Result<(), RequestError> {
+        self.edit_tag(id, STARRED).await
+    }
+
+    async fn list_feeds(&self) -> Result<Vec<SelfossSource>, RequestError> {
+        let session = self.session().await?;
+        let request = reqwest::Client::new()
+            .get(format!("{}/reader/api/0/subscription/list", self.base_url))
+            .query(&[("output", "json")]);
+        let subscriptions: Subscriptions = self.send(request, &session).await?.json().await?;
+        Ok(subscriptions
+            .subscriptions
+            .into_iter()
+            .map(|s| SelfossSource {
+                // Ids look like `feed/12`.
+                id: s
+                    .id
+                    .rsplit('/')
+                    .next()
+                    .and_then(|id| id.parse().ok())
+                    .unwrap_or_default(),
+                title: s.title,
+                tags: s.categories.into_iter().map(|c| c.label).collect(),
+                spout: String::new(),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use httpmock::{
+        Method::{GET, POST},
+        MockServer,
+    };
+    use serde_json::json;
+
+    use crate::{
+        metrics::Metrics,
+        sources::{
+            freshrss::{parse_item_id, FreshRss},
+            Source,
+        },
+        test::get_mock_item,
+    };
+
+    #[test]
+    fn test_parse_item_id() {
+        assert_eq!(
+            parse_item_id("tag:google.com,2005:reader/item/000000000002db44"),
+            187204
+        );
+        assert_eq!(parse_item_id("187204"), 187204);
+    }
+
+    #[tokio::test]
+    async fn test_freshrss_source() {
+        let server = MockServer::start();
+        let login_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/accounts/ClientLogin")
+                .body("Email=admin&Passwd=secret");
+            then.status(200)
+                .body("SID=admin/abc\nLSID=null\nAuth=admin/abc\n");
+        });
+        let token_mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/reader/api/0/token")
+                .header("authorization", "GoogleLogin auth=admin/abc");
+            then.status(200).body("csrf-token\n");
+        });
+        let items_mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/reader/api/0/stream/contents/user/-/state/com.google/reading-list")
+                .query_param("xt", "user/-/state/com.google/read");
+            then.status(200).json_body(json!({
+                "items": [{
+                    "id": "tag:google.com,2005:reader/item/000000000002db44",
+                    "title": "My title",
+                    "published": 1702662036,
+                    "alternate": [{"href": "My link"}],
+                    "summary": {"content": "My content"},
+                    "author": "Me",
+                    "origin": {"streamId": "feed/25", "title": "my_channel"},
+                    "categories": [
+                        "user/-/state/com.google/reading-list",
+                        "user/-/state/com.google/read",
+                        "user/-/label/news"
+                    ]
+                }]
+            }));
+        });
+        let mark_read_mock = server.mock(|when, then| {
+            when.method(POST).path("/reader/api/0/edit-tag").body(
+                "i=tag%3Agoogle.com%2C2005%3Areader%2Fitem%2F000000000002db44\
+                 &a=user%2F-%2Fstate%2Fcom.google%2Fread&T=csrf-token",
+            );
+            then.status(200).body("OK");
+        });
+
+        let source = FreshRss::new(
+            server.base_url(),
+            String::from("admin"),
+            String::from("secret"),
+            Arc::new(Metrics::new()),
+        );
+        let items = source.fetch_unread().await.unwrap();
+        assert_eq!(items, vec![get_mock_item()]);
+        source
+            .mark_read(items[0].id)
+            .await
+            .expect("Error marking item as read");
+
+        // The session is kept between requests.
+        login_mock.assert_async().await;
+        token_mock.assert_async().await;
+        items_mock.assert_async().await;
+        mark_read_mock.assert_async().await;
+    }
+}
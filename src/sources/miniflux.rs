@@ -0,0 +1,329 @@
+//! Miniflux, through its REST API with basic authentication.
+//!
+//! See https://miniflux.app/docs/api.html
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use reqwest::{Method, RequestBuilder};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::debug;
+
+use super::{check_response, Source, UNREAD_LIMIT};
+use crate::{
+    discord::errors::RequestError,
+    metrics::Metrics,
+    selfoss::models::{SelfossItem, SelfossSource},
+};
+
+const MINIFLUX: &str = "Miniflux";
+
+pub struct Miniflux {
+    pub base_url: String,
+    pub username: String,
+    pub password: String,
+    pub metrics: Arc<Metrics>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Entries {
+    entries: Vec<Entry>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Entry {
+    id: u64,
+    title: String,
+    url: String,
+    content: String,
+    author: String,
+    published_at: DateTime<Utc>,
+    status: String,
+    starred: bool,
+    feed: Feed,
+}
+
+/// The part of an entry that tells whether it is bookmarked.
+#[derive(Deserialize, Debug)]
+struct Bookmark {
+    starred: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct Feed {
+    id: u64,
+    title: String,
+    category: Option<Category>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Category {
+    title: String,
+}
+
+impl Feed {
+    fn tags(&self) -> Vec<String> {
+        self.category.iter().map(|c| c.title.clone()).collect()
+    }
+}
+
+impl From<Entry> for SelfossItem {
+    fn from(entry: Entry) -> Self {
+        SelfossItem {
+            title: entry.title,
+            tags: entry.feed.tags(),
+            sourcetitle: entry.feed.title,
+            content: entry.content,
+            datetime: entry.published_at,
+            id: entry.id,
+            link: entry.url,
+            author: Some(entry.author).filter(|a| !a.is_empty()),
+            unread: entry.status == "unread",
+            starred: entry.starred,
+            thumbnail: None,
+        }
+    }
+}
+
+impl Miniflux {
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        reqwest::Client::new()
+            .request(method, format!("{}/v1/{}", self.base_url, path))
+            .basic_auth(&self.username, Some(&self.password))
+    }
+
+    /// Up to `UNREAD_LIMIT` unread items, skipping the `offset` newest ones.
+    async fn unread_page(&self, offset: usize) -> Result<Vec<SelfossItem>, RequestError> {
+        let result: Result<Entries, RequestError> = async {
+            let (limit, offset) = (UNREAD_LIMIT.to_string(), offset.to_string());
+            let query = [
+                ("status", "unread"),
+                ("order", "published_at"),
+                ("direction", "desc"),
+                ("limit", limit.as_str()),
+                ("offset", offset.as_str()),
+            ];
+            let response = self.request(Method::GET, "entries").query(&query).send();
+            Ok(check_response(MINIFLUX, response.await?)
+                .await?
+                .json()
+                .await?)
+        }
+        .await;
+        self.metrics.record_selfoss_fetch(result.is_ok());
+        Ok(result?.entries.into_iter().map(Into::into).collect())
+    }
+}
+
+#[async_trait]
+impl Source for Miniflux {
+    fn name(&self) -> &'static str {
+        MINIFLUX
+    }
+
+    async fn fetch_unread(&self) -> Result<Vec<SelfossItem>, RequestError> {
+        let items = self.unread_page(0).await?;
+        debug!(items = items.len(), "Fetched unread items");
+        Ok(items)
+    }
+
+    async fn fetch_all_unread(&self) -> Result<Vec<SelfossItem>, RequestError> {
+        let mut items = vec![];
+        loop {
+            let page = self.unread_page(items.len()).await?;
+            let done = page.len() < UNREAD_LIMIT;
+            items.extend(page);
+            if done {
+                return Ok(items);
+            }
+        }
+    }
+
+    async fn mark_read(&self, id: u64) -> Result<(), RequestError> {
+        let payload = json!({"entry_ids": [id], "status": "read"});
+        let response = self.request(Method::PUT, "entries").json(&payload).send();
+        check_response(MINIFLUX, response.await?).await?;
+        debug!(id, "Marked item as read");
+        Ok(())
+    }
+
+    /// Miniflux toggles bookmarks, so items that are already starred are left alone.
+    async fn star(&self, id: u64) -> Result<(), RequestError> {
+        let response = self.request(Method::GET, &format!("entries/{}", id)).send();
+        let entry: Bookmark = check_response(MINIFLUX, response.await?)
+            .await?
+            .json()
+            .await?;
+        if entry.starred {
+            debug!(id, "Item is already starred");
+            return Ok(());
+        }
+        let path = format!("entries/{}/bookmark", id);
+        let response = self.request(Method::PUT, &path).send();
+        check_response(MINIFLUX, response.await?).await?;
+        Ok(())
+    }
+
+    async fn list_feeds(&self) -> Result<Vec<SelfossSource>, RequestError> {
+        let response = self.request(Method::GET, "feeds").send();
+        let feeds: Vec<Feed> = check_response(MINIFLUX, response.await?)
+            .await?
+            .json()
+            .await?;
+        Ok(feeds
+            .into_iter()
+            .map(|feed| SelfossSource {
+                id: feed.id,
+                tags: feed.tags(),
+                title: feed.title,
+                spout: String::new(),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use httpmock::{
+        Method::{GET, PUT},
+        MockServer,
+    };
+    use serde_json::json;
+
+    use crate::{
+        discord::errors::RequestError,
+        metrics::Metrics,
+        selfoss::models::SelfossSource,
+        sources::{miniflux::Miniflux, Source},
+        test::get_mock_item,
+    };
+
+    fn start_miniflux() -> (MockServer, Miniflux) {
+        let server = MockServer::start();
+        let source = Miniflux {
+            base_url: server.base_url(),
+            username: String::from("admin"),
+            password: String::from("secret"),
+            metrics: Arc::new(Metrics::new()),
+        };
+        (server, source)
+    }
+
+    #[tokio::test]
+    async fn test_fetch_unread_and_mark_read() {
+        let (server, source) = start_miniflux();
+
+        let entries_mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/v1/entries")
+                .query_param("status", "unread")
+                // admin:secret
+                .header("authorization", "Basic YWRtaW46c2VjcmV0");
+            then.status(200).json_body(json!({
+                "total": 1,
+                "entries": [{
+                    "id": 187204,
+                    "title": "My title",
+                    "url": "My link",
+                    "content": "My content",
+                    "author": "Me",
+                    "published_at": "2023-12-15T18:40:36+01:00",
+                    "status": "read",
+                    "starred": false,
+                    "feed": {"id": 25, "title": "my_channel", "category": {"id": 1, "title": "news"}}
+                }]
+            }));
+        });
+        let mark_read_mock = server.mock(|when, then| {
+            when.method(PUT)
+                .path("/v1/entries")
+                .json_body(json!({"entry_ids": [187204], "status": "read"}));
+            then.status(204);
+        });
+
+        let items = source.fetch_unread().await.unwrap();
+        assert_eq!(items, vec![get_mock_item()]);
+        source
+            .mark_read(items[0].id)
+            .await
+            .expect("Error marking item as read");
+
+        entries_mock.assert_async().await;
+        mark_read_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_list_feeds() {
+        let (server, source) = start_miniflux();
+
+        let feeds_mock = server.mock(|when, then| {
+            when.method(GET).path("/v1/feeds");
+            then.status(200).json_body(json!([
+                {"id": 12, "title": "my channel", "category": {"id": 1, "title": "news"}}
+            ]));
+        });
+        assert_eq!(
+            source.list_feeds().await.unwrap(),
+            vec![SelfossSource {
+                id: 12,
+                title: String::from("my channel"),
+                tags: vec![String::from("news")],
+                spout: String::new(),
+            }]
+        );
+        feeds_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_star_keeps_starred_items_starred() {
+        let (server, source) = start_miniflux();
+
+        server.mock(|when, then| {
+            when.method(GET).path("/v1/entries/4");
+            then.status(200)
+                .json_body(json!({"id": 4, "starred": true}));
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/v1/entries/5");
+            then.status(200)
+                .json_body(json!({"id": 5, "starred": false}));
+        });
+        let starred_mock = server.mock(|when, then| {
+            when.method(PUT).path("/v1/entries/4/bookmark");
+            then.status(204);
+        });
+        let unstarred_mock = server.mock(|when, then| {
+            when.method(PUT).path("/v1/entries/5/bookmark");
+            then.status(204);
+        });
+
+        source.star(4).await.unwrap();
+        source.star(5).await.unwrap();
+        starred_mock.assert_hits_async(0).await;
+        unstarred_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_star_unauthorized() {
+        let (server, source) = start_miniflux();
+
+        server.mock(|when, then| {
+            when.method(GET).path("/v1/entries/4");
+            then.status(200)
+                .json_body(json!({"id": 4, "starred": false}));
+        });
+        server.mock(|when, then| {
+            when.method(PUT).path("/v1/entries/4/bookmark");
+            then.status(401)
+                .json_body(json!({"error_message": "Access Unauthorized"}));
+        });
+        match source.star(4).await {
+            Err(RequestError::Source(e)) => assert!(e.is_fatal()),
+            result => panic!("expected Miniflux error, got {:?}", result),
+        }
+    }
+}
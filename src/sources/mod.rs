@@ -0,0 +1,228 @@
+//! Feed readers that items are fetched from.
+//!
+//! Every reader is normalized into [`SelfossItem`] and [`SelfossSource`], so routing, filtering
+//! and formatting do not depend on the reader.
+
+use std::{fmt, sync::Arc};
+
+use async_trait::async_trait;
+use reqwest::{Response, StatusCode};
+
+use crate::{
+    config::{Config, SourceKind},
+    discord::errors::RequestError,
+    metrics::Metrics,
+    selfoss::{
+        adapter::{get_items, get_sources, get_tree, mark_items_as_read, star_item, ItemQuery},
+        models::{SelfossItem, SelfossSource},
+    },
+};
+
+pub mod feeds;
+pub mod freshrss;
+pub mod miniflux;
+pub mod ttrss;
+
+/// Number of unread items fetched per run, like Selfoss.
+const UNREAD_LIMIT: usize = 200;
+
+#[async_trait]
+pub trait Source: Send + Sync {
+    /// Name of the reader, used in errors and logs.
+    fn name(&self) -> &'static str;
+
+    /// Unread items, newest first.
+    async fn fetch_unread(&self) -> Result<Vec<SelfossItem>, RequestError>;
+
+    /// All unread items, newest first, also those beyond the ones `fetch_unread` returns.
+    async fn fetch_all_unread(&self) -> Result<Vec<SelfossItem>, RequestError>;
+
+    async fn mark_read(&self, id: u64) -> Result<(), RequestError>;
+
+    /// Stars the item, which stays starred if it already is.
+    async fn star(&self, id: u64) -> Result<(), RequestError>;
+
+    /// The feeds subscribed to in the reader.
+    async fn list_feeds(&self) -> Result<Vec<SelfossSource>, RequestError>;
+
+    /// Writes the local state changed by the other calls, once at the end of a run. Readers that
+    /// keep their state on the server have nothing to write.
+    async fn save(&self) -> Result<(), RequestError> {
+        Ok(())
+    }
+}
+
+/// Error returned by a reader other than Selfoss.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceError {
+    pub source: &'static str,
+    /// `None` if the reader reports errors in the body.
+    pub status: Option<StatusCode>,
+    pub message: String,
+}
+
+impl SourceError {
+    pub fn new(source: &'static str, message: impl Into<String>) -> Self {
+        SourceError {
+            source,
+            status: None,
+            message: message.into(),
+        }
+    }
+
+    /// Errors that will fail for every request, so there is no point in continuing.
+    pub fn is_fatal(&self) -> bool {
+        matches!(
+            self.status,
+            Some(StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN)
+        )
+    }
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.status {
+            Some(status) => write!(f, "{} returned {}: {}", self.source, status, self.message),
+            None => write!(f, "{}: {}", self.source, self.message),
+        }
+    }
+}
+
+/// Returns the response if it is successful, and its body as error otherwise.
+async fn check_response(
+    source: &'static str,
+    response: Response,
+) -> Result<Response, RequestError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    Err(RequestError::Source(SourceError {
+        source,
+        status: Some(status),
+        message: response.text().await?,
+    }))
+}
+
+/// Builds the reader of a Selfoss instance whose `type` is not `selfoss`.
+pub fn build(
+    kind: SourceKind,
+    base_url: &str,
+    username: &str,
+    password: &str,
+    metrics: Arc<Metrics>,
+) -> Option<Arc<dyn Source>> {
+    let base_url = base_url.trim_end_matches('/').to_string();
+    let (username, password) = (username.to_string(), password.to_string());
+    match kind {
+        SourceKind::Selfoss | SourceKind::Feeds => None,
+        SourceKind::Miniflux => Some(Arc::new(miniflux::Miniflux {
+            base_url,
+            username,
+            password,
+            metrics,
+        })),
+        SourceKind::FreshRss => Some(Arc::new(freshrss::FreshRss::new(
+            base_url, username, password, metrics,
+        ))),
+        SourceKind::TtRss => Some(Arc::new(ttrss::TtRss::new(
+            base_url, username, password, metrics,
+        ))),
+    }
+}
+
+/// Reads from Selfoss through `selfoss::adapter`.
+pub struct Selfoss {
+    config: Config,
+}
+
+impl Selfoss {
+    pub fn new(config: Config) -> Self {
+        Selfoss { config }
+    }
+}
+
+#[async_trait]
+impl Source for Selfoss {
+    fn name(&self) -> &'static str {
+        "Selfoss"
+    }
+
+    async fn fetch_unread(&self) -> Result<Vec<SelfossItem>, RequestError> {
+        Ok(get_tree(&self.config).await?)
+    }
+
+    async fn fetch_all_unread(&self) -> Result<Vec<SelfossItem>, RequestError> {
+        let mut query = ItemQuery::default();
+        let mut items = vec![];
+        loop {
+            let page = get_items(&self.config, &query).await?;
+            let done = page.len() < query.items;
+            items.extend(page);
+            if done {
+                return Ok(items);
+            }
+            query.offset += query.items;
+        }
+    }
+
+    async fn mark_read(&self, id: u64) -> Result<(), RequestError> {
+        mark_items_as_read(&self.config, id).await?;
+        Ok(())
+    }
+
+    async fn star(&self, id: u64) -> Result<(), RequestError> {
+        star_item(&self.config, id).await?;
+        Ok(())
+    }
+
+    async fn list_feeds(&self) -> Result<Vec<SelfossSource>, RequestError> {
+        Ok(get_sources(&self.config).await?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use httpmock::Method::POST;
+    use reqwest::StatusCode;
+
+    use crate::{
+        sources::SourceError,
+        test::{get_mock_item, start_server},
+    };
+
+    #[tokio::test]
+    async fn test_selfoss_source() {
+        let (server, config) = start_server();
+
+        let star_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/starr/187204")
+                .query_param("username", "test username");
+            then.status(200).body("");
+        });
+
+        let source = config.source();
+        assert_eq!(source.name(), "Selfoss");
+        source
+            .star(get_mock_item().id)
+            .await
+            .expect("Error starring item");
+        star_mock.assert_async().await;
+    }
+
+    #[test]
+    fn test_fatal_errors() {
+        let error = |status| SourceError {
+            source: "Miniflux",
+            status,
+            message: String::from("Access Unauthorized"),
+        };
+        assert!(error(Some(StatusCode::UNAUTHORIZED)).is_fatal());
+        assert!(!error(Some(StatusCode::BAD_GATEWAY)).is_fatal());
+        assert_eq!(
+            error(Some(StatusCode::UNAUTHORIZED)).to_string(),
+            "Miniflux returned 401 Unauthorized: Access Unauthorized"
+        );
+    }
+}
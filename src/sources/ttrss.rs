@@ -0,0 +1,310 @@
+//! Tiny Tiny RSS, through its JSON API.
+//!
+//! The base URL is the one of the instance, the API has to be enabled in the preferences of the
+//! user. See https://tt-rss.org/wiki/ApiReference
+
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+use tracing::debug;
+
+use super::{check_response, Source, SourceError, UNREAD_LIMIT};
+use crate::{
+    discord::errors::RequestError,
+    metrics::Metrics,
+    selfoss::models::{SelfossItem, SelfossSource},
+};
+
+const TTRSS: &str = "Tiny Tiny RSS";
+/// Virtual feed with all articles.
+const ALL_ARTICLES: i64 = -4;
+/// Virtual category with all feeds, without labels and other virtual feeds.
+const ALL_FEEDS: i64 = -3;
+/// Fields of `updateArticle`.
+const FIELD_STARRED: u8 = 0;
+const FIELD_UNREAD: u8 = 2;
+
+pub struct TtRss {
+    base_url: String,
+    username: String,
+    password: String,
+    metrics: Arc<Metrics>,
+    session_id: Mutex<Option<String>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Headline {
+    id: u64,
+    title: String,
+    link: String,
+    #[serde(default)]
+    content: String,
+    author: Option<String>,
+    updated: i64,
+    feed_title: String,
+    #[serde(default)]
+    tags: Vec<String>,
+    unread: bool,
+    marked: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct Feed {
+    id: u64,
+    title: String,
+    cat_id: i64,
+}
+
+#[derive(Deserialize, Debug)]
+struct Category {
+    /// Ids of categories are strings, unlike those of their feeds.
+    id: String,
+    title: String,
+}
+
+impl From<Headline> for SelfossItem {
+    fn from(headline: Headline) -> Self {
+        SelfossItem {
+            title: headline.title,
+            sourcetitle: headline.feed_title,
+            content: headline.content,
+            datetime: DateTime::from_timestamp(headline.updated, 0).unwrap_or_else(Utc::now),
+            id: headline.id,
+            link: headline.link,
+            author: headline.author.filter(|a| !a.is_empty()),
+            tags: headline
+                .tags
+                .into_iter()
+                .filter(|t| !t.is_empty())
+                .collect(),
+            unread: headline.unread,
+            starred: headline.marked,
+            thumbnail: None,
+        }
+    }
+}
+
+impl TtRss {
+    pub fn new(
+        base_url: String,
+        username: String,
+        password: String,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        TtRss {
+            base_url,
+            username,
+            password,
+            metrics,
+            session_id: Mutex::new(None),
+        }
+    }
+
+    /// Calls an operation and returns its `content`, which holds the error if `status` is 1.
+    async fn call(&self, payload: Value) -> Result<Value, RequestError> {
+        let response = reqwest::Client::new()
+            .post(format!("{}/api/", self.base_url))
+            .json(&payload)
+            .send()
+            .await?;
+        let body: Value = check_response(TTRSS, response).await?.json().await?;
+        match body["status"].as_u64() {
+            Some(0) => Ok(body["content"].clone()),
+            _ => Err(RequestError::Source(SourceError::new(
+                TTRSS,
+                body["content"]["error"].as_str().unwrap_or("unknown error"),
+            ))),
+        }
+    }
+
+    async fn login(&self) -> Result<String, RequestError> {
+        let mut session_id = self.session_id.lock().await;
+        if let Some(session_id) = session_id.as_ref() {
+            return Ok(session_id.clone());
+        }
+        let payload = json!({"op": "login", "user": self.username, "password": self.password});
+        let content = self.call(payload).await?;
+        debug!("Logged in to Tiny Tiny RSS");
+        let id = content["session_id"].as_str().unwrap_or_default();
+        Ok(session_id.insert(id.to_string()).clone())
+    }
+
+    /// Calls an operation within the session, logging in again once if it has expired.
+    async fn call_in_session<D: DeserializeOwned>(
+        &self,
+        mut payload: Value,
+    ) -> Result<D, RequestError> {
+        payload["sid"] = json!(self.login().await?);
+        let content = match self.call(payload.clone()).await {
+            Err(RequestError::Source(e)) if e.message == "NOT_LOGGED_IN" => {
+                *self.session_id.lock().await = None;
+                payload["sid"] = json!(self.login().await?);
+                self.call(payload).await?
+            }
+            result => result?,
+        };
+        Ok(serde_json::from_value(content)?)
+    }
+
+    /// Up to `UNREAD_LIMIT` unread items, skipping the `skip` newest ones.
+    async fn unread_page(&self, skip: usize) -> Result<Vec<SelfossItem>, RequestError> {
+        let payload = json!({
+            "op": "getHeadlines",
+            "feed_id": ALL_ARTICLES,
+            "view_mode": "unread",
+            "show_content": true,
+            "limit": UNREAD_LIMIT,
+            "skip": skip,
+        });
+        let result = self.call_in_session::<Vec<Headline>>(payload).await;
+        self.metrics.record_selfoss_fetch(result.is_ok());
+        Ok(result?.into_iter().map(Into::into).collect())
+    }
+
+    async fn update_article(&self, id: u64, field: u8, mode: u8) -> Result<(), RequestError> {
+        let payload = json!({
+            "op": "updateArticle",
+            "article_ids": id.to_string(),
+            "field": field,
+            "mode": mode,
+        });
+        self.call_in_session::<Value>(payload).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Source for TtRss {
+    fn name(&self) -> &'static str {
+        TTRSS
+    }
+
+    async fn fetch_unread(&self) -> Result<Vec<SelfossItem>, RequestError> {
+        let items = self.unread_page(0).await?;
+        debug!(items = items.len(), "Fetched unread items");
+        Ok(items)
+    }
+
+    async fn fetch_all_unread(&self) -> Result<Vec<SelfossItem>, RequestError> {
+        let mut items = vec![];
+        loop {
+            let page = self.unread_page(items.len()).await?;
+            let done = page.len() < UNREAD_LIMIT;
+            items.extend(page);
+            if done {
+                return Ok(items);
+            }
+        }
+    }
+
+    async fn mark_read(&self, id: u64) -> Result<(), RequestError> {
+        self.update_article(id, FIELD_UNREAD, 0).await?;
+        debug!(id, "Marked item as read");
+        Ok(())
+    }
+
+    async fn star(&self, id: u64) -> Result<(), RequestError> {
+        self.update_article(id, FIELD_STARRED, 1).await
+    }
+
+    async fn list_feeds(&self) -> Result<Vec<SelfossSource>, RequestError> {
+        let categories: Vec<Category> =
+            self.call_in_session(json!({"op": "getCategories"})).await?;
+        let categories: HashMap<String, String> =
+            categories.into_iter().map(|c| (c.id, c.title)).collect();
+        let feeds: Vec<Feed> = self
+            .call_in_session(json!({"op": "getFeeds", "cat_id": ALL_FEEDS}))
+            .await?;
+        Ok(feeds
+            .into_iter()
+            .map(|feed| SelfossSource {
+                id: feed.id,
+                title: feed.title,
+                tags: categories
+                    .get(&feed.cat_id.to_string())
+                    .into_iter()
+                    .cloned()
+                    .collect(),
+                spout: String::new(),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use httpmock::{Method::POST, MockServer};
+    use serde_json::json;
+
+    use crate::{
+        metrics::Metrics,
+        sources::{ttrss::TtRss, Source},
+        test::get_mock_item,
+    };
+
+    #[tokio::test]
+    async fn test_ttrss_source() {
+        let server = MockServer::start();
+        let login_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/api/")
+                .json_body(json!({"op": "login", "user": "admin", "password": "secret"}));
+            then.status(200)
+                .json_body(json!({"seq": 0, "status": 0, "content": {"session_id": "s1"}}));
+        });
+        let headlines_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/api/")
+                .json_body_partial(r#"{"op": "getHeadlines", "sid": "s1", "view_mode": "unread"}"#);
+            then.status(200).json_body(json!({
+                "seq": 0,
+                "status": 0,
+                "content": [{
+                    "id": 187204,
+                    "title": "My title",
+                    "link": "My link",
+                    "content": "My content",
+                    "author": "Me",
+                    "updated": 1702662036,
+                    "feed_id": "25",
+                    "feed_title": "my_channel",
+                    "tags": ["news"],
+                    "unread": false,
+                    "marked": false
+                }]
+            }));
+        });
+        let expired_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/api/")
+                .json_body_partial(r#"{"op": "updateArticle", "sid": "s1"}"#);
+            then.status(200).json_body(json!({
+                "seq": 0,
+                "status": 1,
+                "content": {"error": "NOT_LOGGED_IN"}
+            }));
+        });
+
+        let source = TtRss::new(
+            server.base_url(),
+            String::from("admin"),
+            String::from("secret"),
+            Arc::new(Metrics::new()),
+        );
+        let items = source.fetch_unread().await.unwrap();
+        assert_eq!(items, vec![get_mock_item()]);
+
+        // The session has expired, so it logs in again, which returns the same session here.
+        assert!(source.mark_read(items[0].id).await.is_err());
+
+        login_mock.assert_hits_async(2).await;
+        headlines_mock.assert_async().await;
+        expired_mock.assert_hits_async(2).await;
+    }
+}
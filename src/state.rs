@@ -0,0 +1,119 @@
+use std::{collections::HashMap, fs, io::ErrorKind};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{dead_letter::DeadLetter, selfoss::models::SelfossItem};
+
+/// Local state that has to survive between runs, stored as JSON at `Config::state_path`.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct State {
+    /// Time at which the last digest of each route was delivered, keyed by route name.
+    #[serde(default)]
+    pub digests: HashMap<String, DateTime<Utc>>,
+    /// Recently posted items, used to detect duplicates.
+    #[serde(default)]
+    pub posted: Vec<PostedItem>,
+    /// Items that could not be delivered and are retried with backoff.
+    #[serde(default)]
+    pub dead_letters: Vec<DeadLetter>,
+    /// Sources whose channel was deleted, their items are marked as read without posting.
+    #[serde(default)]
+    pub unsubscribed: Vec<String>,
+    /// Channels of Selfoss sources keyed by source id, so that renamed sources keep their channel.
+    #[serde(default)]
+    pub sources: HashMap<u64, SourceChannel>,
+    /// Messages in announcement channels that wait to be published because of the rate limit.
+    #[serde(default)]
+    pub unpublished: Vec<UnpublishedMessage>,
+    /// Times at which messages were published in the last hour, keyed by channel id.
+    #[serde(default)]
+    pub crossposts: HashMap<String, Vec<DateTime<Utc>>>,
+    /// Items that arrived outside of the delivery window of their route, in order of arrival.
+    #[serde(default)]
+    pub queued: Vec<QueuedItem>,
+    /// Recently posted items of routes with a limit, to count them against their caps.
+    #[serde(default)]
+    pub deliveries: Vec<Delivery>,
+    /// Whether there was no state file yet, so the bridge runs for the first time.
+    #[serde(skip)]
+    pub is_new: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Delivery {
+    /// Name of the channel the item was posted in.
+    pub channel: String,
+    pub source: String,
+    pub at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct QueuedItem {
+    /// Name of the route whose window the item waits for.
+    pub route: String,
+    pub item: SelfossItem,
+    pub queued_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct UnpublishedMessage {
+    pub channel_id: String,
+    pub message_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct SourceChannel {
+    pub title: String,
+    pub channel_id: String,
+    /// Consecutive runs in which the source was missing from the reader.
+    #[serde(default)]
+    pub missing_runs: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct PostedItem {
+    /// Canonicalized link of the item.
+    pub link: String,
+    /// Normalized title of the item.
+    pub title: String,
+    pub sourcetitle: String,
+    pub channel_id: String,
+    pub message_id: String,
+    pub content: String,
+    pub posted_at: DateTime<Utc>,
+    /// Sources of the duplicates that were folded into this message.
+    #[serde(default)]
+    pub also_in: Vec<String>,
+    /// Sink the message was posted through, `None` for Discord.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sink: Option<String>,
+    /// The posted item, kept for sinks that format the whole item again when it is updated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub item: Option<SelfossItem>,
+}
+
+impl State {
+    pub fn load(path: &str) -> Result<State, String> {
+        match fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map_err(|e| format!("Invalid state file {:?}: {}", path, e)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(State {
+                is_new: true,
+                ..Default::default()
+            }),
+            Err(e) => Err(format!("Could not read state file {:?}: {}", path, e)),
+        }
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Could not serialize state: {}", e))?;
+        fs::write(path, contents)
+            .map_err(|e| format!("Could not write state file {:?}: {}", path, e))
+    }
+
+    pub fn is_queued(&self, id: u64) -> bool {
+        self.queued.iter().any(|queued| queued.item.id == id)
+    }
+}
@@ -0,0 +1,17 @@
+use serde::Serialize;
+
+/// Counters describing what happened to the items of a single run.
+#[derive(Serialize, Debug, Default, PartialEq, Clone)]
+pub struct RunStats {
+    pub fetched: usize,
+    pub posted: usize,
+    pub filtered: usize,
+    pub duplicates: usize,
+    pub held_back: usize,
+    pub failed: usize,
+    pub marked_read: usize,
+    /// Messages published in announcement channels.
+    pub published: usize,
+    /// Messages that were posted but could not be published.
+    pub publish_failed: usize,
+}
@@ -0,0 +1,136 @@
+use std::fmt::Write;
+
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, Utc,
+};
+
+use crate::{
+    config::{TimestampConfig, TimestampStyle},
+    selfoss::models::SelfossItem,
+    utils::truncate,
+};
+
+/// Whether `format` can be used as `strftime` format.
+pub fn is_valid_format(format: &str) -> bool {
+    !StrftimeItems::new(format).any(|item| item == Item::Error)
+}
+
+/// The publication time of `item`, with a note if it is delivered at `now` after a long delay.
+pub fn timestamp_line(config: &TimestampConfig, item: &SelfossItem, now: DateTime<Utc>) -> String {
+    let unix = item.datetime.timestamp();
+    let mut line = match config.style {
+        TimestampStyle::Relative => format!("<t:{}:R>", unix),
+        TimestampStyle::Full => format!("<t:{}:f>", unix),
+        TimestampStyle::Formatted => {
+            let local = item.datetime.with_timezone(&config.timezone);
+            let mut line = String::new();
+            match write!(
+                line,
+                "{}",
+                local.format_localized(&config.format, config.locale)
+            ) {
+                Ok(()) => line,
+                Err(_) => local.to_rfc3339(),
+            }
+        }
+    };
+
+    let hours = (now - item.datetime).num_hours();
+    if hours >= config.delay_note_hours.max(1) {
+        let unit = if hours == 1 { "hour" } else { "hours" };
+        let _ = write!(line, " (published {} {} before delivery)", hours, unit);
+    }
+    line
+}
+
+/// Appends the timestamp line to the message `content`, which is shortened so that both fit in
+/// `max_chars`.
+pub fn append(
+    config: Option<&TimestampConfig>,
+    content: String,
+    item: &SelfossItem,
+    now: DateTime<Utc>,
+    max_chars: usize,
+) -> String {
+    let Some(config) = config else {
+        return truncate(&content, max_chars).to_string();
+    };
+    let line = timestamp_line(config, item, now);
+    let max_content_chars = max_chars.saturating_sub(line.chars().count() + 1);
+    format!("{}\n{}", truncate(&content, max_content_chars), line)
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, Duration, Locale, Utc};
+
+    use crate::{
+        config::{TimestampConfig, TimestampStyle},
+        test::get_mock_item,
+        timestamps::{append, is_valid_format, timestamp_line},
+    };
+
+    fn timestamps(toml: &str) -> TimestampConfig {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn test_timestamp_line() {
+        let item = get_mock_item();
+        let now: DateTime<Utc> = item.datetime + Duration::minutes(5);
+
+        let mut config = timestamps("");
+        assert_eq!(config.style, TimestampStyle::Relative);
+        assert_eq!(timestamp_line(&config, &item, now), "<t:1702662036:R>");
+        config.style = TimestampStyle::Full;
+        assert_eq!(timestamp_line(&config, &item, now), "<t:1702662036:f>");
+
+        let config = timestamps(
+            r#"
+            style = "formatted"
+            format = "%A %-d %B %Y, %H:%M %Z"
+            timezone = "Europe/Amsterdam"
+            locale = "nl_NL"
+            "#,
+        );
+        assert_eq!(config.locale, Locale::nl_NL);
+        assert_eq!(
+            timestamp_line(&config, &item, now),
+            "vrijdag 15 december 2023, 18:40 CET"
+        );
+
+        let config = timestamps("delay_note_hours = 3");
+        let later = item.datetime + Duration::hours(2);
+        assert_eq!(timestamp_line(&config, &item, later), "<t:1702662036:R>");
+        let later = item.datetime + Duration::minutes(3 * 60 + 30);
+        assert_eq!(
+            timestamp_line(&config, &item, later),
+            "<t:1702662036:R> (published 3 hours before delivery)"
+        );
+
+        assert!(toml::from_str::<TimestampConfig>(r#"locale = "xx_XX""#).is_err());
+        assert!(is_valid_format(&config.format));
+        assert!(!is_valid_format("%Q"));
+    }
+
+    #[test]
+    fn test_append() {
+        let item = get_mock_item();
+        let config = timestamps("");
+        let now = item.datetime;
+        assert_eq!(
+            append(None, String::from("My content"), &item, now, 2000),
+            "My content"
+        );
+        assert_eq!(
+            append(Some(&config), String::from("My content"), &item, now, 2000),
+            "My content\n<t:1702662036:R>"
+        );
+
+        let message = append(Some(&config), "a".repeat(2000), &item, now, 2000);
+        assert_eq!(message.chars().count(), 2000);
+        assert!(message.ends_with("a\n<t:1702662036:R>"));
+        assert_eq!(append(None, "a".repeat(2000), &item, now, 1900).len(), 1900);
+    }
+}
@@ -1,7 +1,30 @@
-use std::env;
+use chrono::{DateTime, Utc};
 
-pub fn deserialize_string_from_env(key: &str) -> String {
-    env::var(key)
-        .unwrap_or_else(|_| panic!("Could not find environment variable: {:?}", key))
-        .to_string()
+/// Maximum length of a Discord message.
+pub const MAX_MESSAGE_LENGTH: usize = 2000;
+
+pub fn truncate(s: &str, max_chars: usize) -> &str {
+    match s.char_indices().nth(max_chars) {
+        None => s,
+        Some((idx, _)) => &s[..idx],
+    }
+}
+
+/// Source of the current time, which tests can fix at a given moment.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Clock {
+    #[default]
+    System,
+    #[cfg(test)]
+    Fixed(DateTime<Utc>),
+}
+
+impl Clock {
+    pub fn now(&self) -> DateTime<Utc> {
+        match self {
+            Clock::System => Utc::now(),
+            #[cfg(test)]
+            Clock::Fixed(now) => *now,
+        }
+    }
 }
@@ -0,0 +1,66 @@
+use chrono::{DateTime, Datelike, Utc};
+
+use crate::config::WindowConfig;
+
+/// Whether items can be posted at `now`. A window that ends before it starts runs past midnight,
+/// and counts as the window of the day on which it started.
+pub fn is_open(window: &WindowConfig, now: DateTime<Utc>) -> bool {
+    let local = now.with_timezone(&window.timezone);
+    let time = local.time();
+    let (open, day) = if window.start <= window.end {
+        (window.start <= time && time < window.end, local.weekday())
+    } else if time >= window.start {
+        (true, local.weekday())
+    } else {
+        (time < window.end, local.weekday().pred())
+    };
+    open && (window.days.is_empty() || window.days.contains(&day))
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, Utc};
+
+    use crate::{config::WindowConfig, window::is_open};
+
+    fn parse(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().into()
+    }
+
+    fn window(toml: &str) -> WindowConfig {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn test_is_open() {
+        let weekdays = window(
+            r#"
+            days = ["mon", "tue", "wed", "thu", "fri"]
+            start = "08:00:00"
+            end = "20:00:00"
+            timezone = "Europe/Amsterdam"
+            "#,
+        );
+        // Friday 15 December 2023, an hour ahead of UTC in Amsterdam.
+        assert!(!is_open(&weekdays, parse("2023-12-15T06:59:59Z")));
+        assert!(is_open(&weekdays, parse("2023-12-15T07:00:00Z")));
+        assert!(is_open(&weekdays, parse("2023-12-15T18:59:59Z")));
+        assert!(!is_open(&weekdays, parse("2023-12-15T19:00:00Z")));
+        assert!(!is_open(&weekdays, parse("2023-12-16T12:00:00Z")));
+        assert!(is_open(&weekdays, parse("2023-12-18T07:00:00Z")));
+
+        let nights = window(
+            r#"
+            days = ["Friday"]
+            start = "22:00:00"
+            end = "02:00:00"
+            "#,
+        );
+        assert!(!is_open(&nights, parse("2023-12-15T21:59:59Z")));
+        assert!(is_open(&nights, parse("2023-12-15T22:00:00Z")));
+        assert!(is_open(&nights, parse("2023-12-16T01:59:59Z")));
+        assert!(!is_open(&nights, parse("2023-12-16T02:00:00Z")));
+        assert!(!is_open(&nights, parse("2023-12-16T23:00:00Z")));
+        assert!(!is_open(&nights, parse("2023-12-15T01:00:00Z")));
+    }
+}